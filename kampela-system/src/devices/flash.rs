@@ -1,80 +1,58 @@
+use alloc::vec::Vec;
 use core::cmp;
 use efm32pg23_fix::Peripherals;
 use crate::peripherals::usart::*;
-use crate::devices::se_aes_gcm::ENCODED_LEN;
+use crate::devices::se_aes_gcm::{ENCODED_LEN, LEGACY_ENCODED_LEN, SECRET_MAX_LEN};
 use crate::in_free;
 use cortex_m::asm::delay;
 
+use super::flash_device::FlashDevice;
 use super::se_aes_gcm::Protected;
+use super::record_store::{crc32, RecordKey, RecordStore, MAX_RECORD_LEN};
 
-const PAGE_SIZE: usize = 256;
+pub(crate) const PAGE_SIZE: usize = 256;
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum FlashErr {
-    WriteNotMatch
+    WriteNotMatch,
+    NotErased,
+    RecordTooLong,
+    Corrupted,
+    UnsupportedVersion,
 }
 
-pub fn store_data<const N: usize>(addr: u32, payload: &[u8; N]) -> Result<(), FlashErr> {
-    let mut data = [0u8; N];
-    let mut read_data_chunk = [0u8; PAGE_SIZE];
-    let initial_addr = addr / PAGE_SIZE as u32 * PAGE_SIZE as u32;
-    for (i, chunk) in payload.chunks(PAGE_SIZE).enumerate() {
-        let addr = initial_addr + i as u32 * PAGE_SIZE as u32;
+/// External SPI NOR flash on USART0
+pub struct SpiNorFlash;
+
+impl FlashDevice for SpiNorFlash {
+    fn read(&mut self, addr: u32, data: &mut [u8]) {
         in_free(|peripherals| {
             flash_wakeup(peripherals);
-    
-            flash_unlock(peripherals);
-            flash_erase_page(peripherals, addr);
-            flash_wait_ready(peripherals);
-    
-            flash_unlock(peripherals);
-    
-            flash_write_page(peripherals, addr, chunk);
             flash_wait_ready(peripherals);
-    
-            flash_read(peripherals, addr, &mut read_data_chunk);
+
+            flash_read(peripherals, addr, data);
             flash_sleep(peripherals);
         });
-        let chunk_start = i * PAGE_SIZE;
-        let chunk_len = cmp::min(N - chunk_start, PAGE_SIZE);
-        data[chunk_start..chunk_start + chunk_len].clone_from_slice(&read_data_chunk[0..chunk_len]);
     }
 
-    if &data != payload {
-        Err(FlashErr::WriteNotMatch)
-    } else {
-        Ok(())
-    }
-}
-
-pub fn read_data(addr: u32, data: &mut [u8]) -> Result<(), FlashErr> {
-    let mut read_data_chunk = [0u8; PAGE_SIZE];
-    let initial_addr = addr / PAGE_SIZE as u32 * PAGE_SIZE as u32;
-    for i in 0..data.len().div_ceil(PAGE_SIZE) {
-        let addr = initial_addr + i as u32 * PAGE_SIZE as u32;
+    fn erase_page(&mut self, addr: u32) {
         in_free(|peripherals| {
             flash_wakeup(peripherals);
-            flash_wait_ready(peripherals);
 
-            flash_read(peripherals, addr, &mut read_data_chunk);
+            flash_unlock(peripherals);
+            flash_erase_page(peripherals, addr);
+
+            flash_wait_ready(peripherals);
             flash_sleep(peripherals);
         });
-        let chunk_start = i * PAGE_SIZE;
-        let chunk_len = cmp::min(data.len() - chunk_start, PAGE_SIZE);
-        data[chunk_start..chunk_start + chunk_len].clone_from_slice(&read_data_chunk[0..chunk_len]);
     }
-    Ok(())
-}
 
-pub fn erase_data(addr: u32, pages: u32) {
-    let initial_addr = addr / PAGE_SIZE as u32 * PAGE_SIZE as u32;
-    for i in 0..pages {
-        let addr = initial_addr + i as u32 * PAGE_SIZE as u32;
+    fn program(&mut self, addr: u32, data: &[u8]) {
         in_free(|peripherals| {
             flash_wakeup(peripherals);
-    
+
             flash_unlock(peripherals);
-            flash_erase_page(peripherals, addr);
+            flash_write_page(peripherals, addr, data);
 
             flash_wait_ready(peripherals);
             flash_sleep(peripherals);
@@ -82,29 +60,447 @@ pub fn erase_data(addr: u32, pages: u32) {
     }
 }
 
-pub fn store_encoded_entopy(protected: &Protected) {
-    // stroring encoded entropy
-    if let Err(_) = store_data(0, &protected.0) {
-        panic!("Failed to save seedphrase");
-    }
+/// Encoded entropy is kept in two copies on separate pages, each followed
+/// by sequence number and CRC, so that one bad write does not lose the seed
+const ENTROPY_ADDRS: [u32; 2] = [0, 6 * PAGE_SIZE as u32];
+
+const ENTROPY_RECORD_LEN: usize = ENCODED_LEN + 4 + 4;
+
+/// Copy of encoded entropy in storage version 1 layout
+const LEGACY_ENTROPY_RECORD_LEN: usize = LEGACY_ENCODED_LEN + 4 + 4;
+
+enum EntropyCopy {
+    Blank,
+    Valid{seq: u32, protected: Protected},
+    Bad,
 }
 
-pub fn read_encoded_entropy() -> Option<Protected> {
-    let mut data = [0u8; ENCODED_LEN];
-    if let Err(_) = read_data(0, &mut data) {
+fn read_entropy_copy<F: FlashDevice>(flash: &mut F, addr: u32) -> EntropyCopy {
+    let mut data = [0u8; ENTROPY_RECORD_LEN];
+    if let Err(_) = flash.read_data(addr, &mut data) {
         panic!("Failed to read seedphrase");
     }
-    match data[0] {
-        0 => None,
-        16 | 20 | 24 | 28 | 32 => {
-            Some(Protected{0: data})
-        },
-        255 => None,
-        _ => {
-            erase_data(0, 1);
-            panic!("Seed storage corrupted! Wiping seed...");
+    if data.iter().all(|b| *b == 0xff) {
+        return EntropyCopy::Blank
+    }
+    match entropy_record_seq(&data) {
+        Some(seq) => {
+            let protected = Protected{0: data[..ENCODED_LEN].try_into().expect("static length")};
+            EntropyCopy::Valid{seq, protected}
         },
+        None => EntropyCopy::Bad,
+    }
+}
+
+fn store_entropy_copy<F: FlashDevice>(flash: &mut F, addr: u32, protected: &Protected, seq: u32) -> Result<(), FlashErr> {
+    flash.store_data(addr, &entropy_record::<ENTROPY_RECORD_LEN>(&protected.0, seq))
+}
+
+/// Encoded entropy followed by sequence number and CRC
+fn entropy_record<const N: usize>(encoded: &[u8], seq: u32) -> [u8; N] {
+    let len = N - 8;
+    let mut data = [0u8; N];
+    data[..len].copy_from_slice(encoded);
+    data[len..len + 4].copy_from_slice(&seq.to_le_bytes());
+    let crc = crc32(&data[..len + 4]);
+    data[len + 4..].copy_from_slice(&crc.to_le_bytes());
+    data
+}
+
+/// Sequence number of entropy record, if its CRC is valid
+fn entropy_record_seq(data: &[u8]) -> Option<u32> {
+    let len = data.len() - 8;
+    let crc = u32::from_le_bytes(data[len + 4..].try_into().expect("static length"));
+    if crc32(&data[..len + 4]) == crc {
+        Some(u32::from_le_bytes(data[len..len + 4].try_into().expect("static length")))
+    } else {
+        None
+    }
+}
+
+/// Store both copies of encoded entropy, one after another, so that
+/// either new or old entropy is readable if power is cut
+pub fn store_encoded_entopy<F: FlashDevice>(flash: &mut F, protected: &Protected) {
+    let seq = ENTROPY_ADDRS
+        .iter()
+        .filter_map(|addr| match read_entropy_copy(flash, *addr) {
+            EntropyCopy::Valid{seq, ..} => Some(seq),
+            _ => None,
+        })
+        .max()
+        .unwrap_or(0) + 1;
+    for addr in ENTROPY_ADDRS.iter() {
+        if let Err(_) = store_entropy_copy(flash, *addr, protected, seq) {
+            panic!("Failed to save seedphrase");
+        }
+    }
+}
+
+/// Encoded entropy from the newest valid copy, rewriting copy that is bad;
+/// `None` if no seed is stored, and error if no copy is valid. Older valid
+/// copy is kept for `settle_entropy_copies`, as it may be the one opening
+/// with PIN.
+pub fn read_encoded_entropy<F: FlashDevice>(flash: &mut F) -> Result<Option<Protected>, FlashErr> {
+    let mut newest: Option<(u32, Protected)> = None;
+    let mut all_blank = true;
+    let mut seqs = [None; 2];
+    for (i, addr) in ENTROPY_ADDRS.iter().enumerate() {
+        match read_entropy_copy(flash, *addr) {
+            EntropyCopy::Blank => {},
+            EntropyCopy::Valid{seq, protected} => {
+                all_blank = false;
+                seqs[i] = Some(seq);
+                if newest.as_ref().map_or(true, |(newest_seq, _)| seq > *newest_seq) {
+                    newest = Some((seq, protected));
+                }
+            },
+            EntropyCopy::Bad => all_blank = false,
+        }
     }
+    let Some((seq, protected)) = newest else {
+        return if all_blank { Ok(None) } else { Err(FlashErr::Corrupted) }
+    };
+    for (addr, copy_seq) in ENTROPY_ADDRS.iter().zip(seqs.iter()) {
+        if copy_seq.is_none() {
+            store_entropy_copy(flash, *addr, &protected, seq)?;
+        }
+    }
+    Ok(Some(protected))
+}
+
+/// Store encoded entropy in the first copy only, newer than the second one,
+/// which keeps the seed until `settle_entropy_copies` is called
+pub fn store_first_entropy_copy<F: FlashDevice>(flash: &mut F, protected: &Protected) {
+    let seq = match (read_entropy_copy(flash, ENTROPY_ADDRS[0]), read_entropy_copy(flash, ENTROPY_ADDRS[1])) {
+        (EntropyCopy::Valid{seq: first, ..}, EntropyCopy::Valid{seq: second, ..}) => cmp::max(first, second),
+        (EntropyCopy::Valid{seq, ..}, _) | (_, EntropyCopy::Valid{seq, ..}) => seq,
+        _ => 0,
+    } + 1;
+    if let Err(_) = store_entropy_copy(flash, ENTROPY_ADDRS[0], protected, seq) {
+        panic!("Failed to save seedphrase");
+    }
+}
+
+/// Bring valid copies of different age, left by seed rewrap or by power
+/// cut, to one: newest copy is kept if `opens` it, otherwise older copy is
+/// brought back if `opens` it. Settled entropy is returned if copies
+/// differed.
+pub fn settle_entropy_copies<F: FlashDevice, O: Fn(&Protected) -> bool>(flash: &mut F, opens: O) -> Result<Option<Protected>, FlashErr> {
+    let mut copies = [None, None];
+    for (copy, addr) in copies.iter_mut().zip(ENTROPY_ADDRS.iter()) {
+        if let EntropyCopy::Valid{seq, protected} = read_entropy_copy(flash, *addr) {
+            *copy = Some((seq, protected));
+        }
+    }
+    let [Some((first_seq, first)), Some((second_seq, second))] = copies else {
+        return Ok(None)
+    };
+    if first_seq == second_seq {
+        return Ok(None)
+    }
+    let ((newest_addr, newest_seq, newest), (older_addr, older)) = if first_seq > second_seq {
+        ((ENTROPY_ADDRS[0], first_seq, first), (ENTROPY_ADDRS[1], second))
+    } else {
+        ((ENTROPY_ADDRS[1], second_seq, second), (ENTROPY_ADDRS[0], first))
+    };
+    if !opens(&newest) && opens(&older) {
+        // newest copy is written over first, so that older one stays until
+        // it is no longer needed
+        store_entropy_copy(flash, newest_addr, &older, newest_seq + 1)?;
+        store_entropy_copy(flash, older_addr, &older, newest_seq + 1)?;
+        Ok(Some(older))
+    } else {
+        store_entropy_copy(flash, older_addr, &newest, newest_seq)?;
+        Ok(Some(newest))
+    }
+}
+
+/// Storage version 0 kept single copy of encoded entropy on page 0, without
+/// sequence number and CRC; it is rewritten as two copies, second one first,
+/// so that the seed survives power cut
+pub(crate) fn split_single_entropy_copy<F: FlashDevice>(flash: &mut F) -> Result<(), FlashErr> {
+    let mut data = [0u8; LEGACY_ENTROPY_RECORD_LEN];
+    flash.read_data(ENTROPY_ADDRS[0], &mut data)?;
+    if matches!(data[0], 16 | 20 | 24 | 28 | 32) && data[LEGACY_ENCODED_LEN..].iter().all(|b| *b == 0xff) {
+        let record = entropy_record::<LEGACY_ENTROPY_RECORD_LEN>(&data[..LEGACY_ENCODED_LEN], 1);
+        flash.store_data(ENTROPY_ADDRS[1], &record)?;
+        flash.store_data(ENTROPY_ADDRS[0], &record)?;
+    }
+    Ok(())
+}
+
+/// Page keeping record that migration rewrites in place, so that power cut
+/// between page erase and program does not lose the only copy; page is not
+/// used by any layout
+const MIGRATION_BACKUP_ADDR: u32 = 8 * PAGE_SIZE as u32;
+
+/// Target address and data length, followed by data and CRC
+const BACKUP_HEADER_LEN: usize = 4 + 2;
+
+/// Rewrite record kept only in one copy, through backup page
+fn rewrite_single_copy<F: FlashDevice>(flash: &mut F, addr: u32, data: &[u8]) -> Result<(), FlashErr> {
+    let mut backup = [0u8; PAGE_SIZE];
+    backup[..4].copy_from_slice(&addr.to_le_bytes());
+    backup[4..BACKUP_HEADER_LEN].copy_from_slice(&(data.len() as u16).to_le_bytes());
+    let crc_start = BACKUP_HEADER_LEN + data.len();
+    backup[BACKUP_HEADER_LEN..crc_start].copy_from_slice(data);
+    let crc = crc32(&backup[..crc_start]);
+    backup[crc_start..crc_start + 4].copy_from_slice(&crc.to_le_bytes());
+    flash.store_data(MIGRATION_BACKUP_ADDR, &backup)?;
+    finish_rewrite(flash)
+}
+
+/// Write record from backup page to its place, if backup is there
+fn finish_rewrite<F: FlashDevice>(flash: &mut F) -> Result<(), FlashErr> {
+    let mut backup = [0u8; PAGE_SIZE];
+    flash.read_data(MIGRATION_BACKUP_ADDR, &mut backup)?;
+    let len = u16::from_le_bytes(backup[4..BACKUP_HEADER_LEN].try_into().expect("static length")) as usize;
+    let crc_start = BACKUP_HEADER_LEN + len;
+    if crc_start + 4 > PAGE_SIZE
+        || crc32(&backup[..crc_start]) != u32::from_le_bytes(backup[crc_start..crc_start + 4].try_into().expect("static length"))
+    {
+        return Ok(())
+    }
+    let addr = u32::from_le_bytes(backup[..4].try_into().expect("static length"));
+    let mut page = [0xffu8; PAGE_SIZE];
+    page[..len].copy_from_slice(&backup[BACKUP_HEADER_LEN..crc_start]);
+    flash.store_data(addr, &page)?;
+    flash.erase_data(MIGRATION_BACKUP_ADDR, 1);
+    Ok(())
+}
+
+/// Storage version 1 kept SE-wrapped secrets without record version and IV;
+/// records of that layout are rewritten in the current one, keeping their
+/// encryption. Records already rewritten are recognized and skipped: entropy
+/// copy by its CRC, others by their first byte, which was nonzero length.
+/// Passphrase and PIN records have no second copy, and go through backup page.
+pub(crate) fn add_protected_record_version<F: FlashDevice>(flash: &mut F) -> Result<(), FlashErr> {
+    finish_rewrite(flash)?;
+    for addr in ENTROPY_ADDRS.iter() {
+        let mut data = [0u8; LEGACY_ENTROPY_RECORD_LEN];
+        flash.read_data(*addr, &mut data)?;
+        if let Some(seq) = entropy_record_seq(&data) {
+            let protected = Protected::from_legacy(data[..LEGACY_ENCODED_LEN].try_into().expect("static length"));
+            store_entropy_copy(flash, *addr, &protected, seq)?;
+        }
+    }
+
+    let mut data = [0u8; LEGACY_ENCODED_LEN];
+    flash.read_data(LEGACY_PASSPHRASE_ADDR, &mut data)?;
+    if (1..=SECRET_MAX_LEN).contains(&(data[0] as usize)) {
+        rewrite_single_copy(flash, LEGACY_PASSPHRASE_ADDR, &Protected::from_legacy(&data).0)?;
+    }
+
+    let mut data = [0u8; PIN_SALT_LEN + LEGACY_ENCODED_LEN];
+    flash.read_data(LEGACY_PIN_ADDR, &mut data)?;
+    if data[PIN_SALT_LEN] as usize == PIN_HASH_LEN {
+        let salt = data[..PIN_SALT_LEN].try_into().expect("static length");
+        let protected = Protected::from_legacy(data[PIN_SALT_LEN..].try_into().expect("static length"));
+        rewrite_single_copy(flash, LEGACY_PIN_ADDR, &pin_record(&salt, &protected))?;
+    }
+    Ok(())
+}
+
+/// Erase both copies of encoded entropy
+pub fn erase_encoded_entropy<F: FlashDevice>(flash: &mut F) {
+    for addr in ENTROPY_ADDRS.iter() {
+        flash.erase_data(*addr, 1);
+    }
+}
+
+/// Compressed SEC1 public key length
+pub const COMPANION_KEY_LEN: usize = 33;
+
+/// Number of companion keys kept, with key count fitting one byte
+pub const MAX_COMPANION_KEYS: usize = (PAGE_SIZE - 1) / COMPANION_KEY_LEN;
+
+pub fn store_companion_keys<F: FlashDevice>(store: &mut RecordStore<F>, keys: &[[u8; COMPANION_KEY_LEN]]) -> Result<(), FlashErr> {
+    let keys = &keys[..cmp::min(keys.len(), MAX_COMPANION_KEYS)];
+    let mut data = Vec::with_capacity(1 + keys.len() * COMPANION_KEY_LEN);
+    data.push(keys.len() as u8);
+    for key in keys.iter() {
+        data.extend_from_slice(key);
+    }
+    store.write(RecordKey::Companions, &data)
+}
+
+pub fn read_companion_keys<F: FlashDevice>(store: &mut RecordStore<F>) -> Vec<[u8; COMPANION_KEY_LEN]> {
+    let Some(data) = store.read(RecordKey::Companions) else {
+        return Vec::new()
+    };
+    let count = data[0] as usize;
+    if count > MAX_COMPANION_KEYS || data.len() != 1 + count * COMPANION_KEY_LEN {
+        // garbage, no keys trusted
+        return Vec::new()
+    }
+    data[1..]
+        .chunks_exact(COMPANION_KEY_LEN)
+        .map(|key| key.try_into().expect("static length"))
+        .collect()
+}
+
+/// Longest encoded account store
+pub const MAX_ACCOUNTS_LEN: usize = PAGE_SIZE;
+
+const _: () = assert!(MAX_ACCOUNTS_LEN <= MAX_RECORD_LEN, "accounts list fits one record");
+
+pub fn store_accounts<F: FlashDevice>(store: &mut RecordStore<F>, encoded: &[u8]) -> Result<(), FlashErr> {
+    if encoded.len() > MAX_ACCOUNTS_LEN {
+        return Err(FlashErr::RecordTooLong)
+    }
+    store.write(RecordKey::Accounts, encoded)
+}
+
+/// Encoded account store, empty if no accounts were stored yet
+pub fn read_accounts<F: FlashDevice>(store: &mut RecordStore<F>) -> Vec<u8> {
+    store.read(RecordKey::Accounts).unwrap_or_default()
+}
+
+/// SE-wrapped passphrase of hidden wallet
+pub fn store_encoded_passphrase<F: FlashDevice>(store: &mut RecordStore<F>, protected: &Protected) -> Result<(), FlashErr> {
+    store.write(RecordKey::Passphrase, &protected.0)
+}
+
+pub fn read_encoded_passphrase<F: FlashDevice>(store: &mut RecordStore<F>) -> Option<Protected> {
+    let protected = Protected{0: store.read(RecordKey::Passphrase)?.try_into().ok()?};
+    if protected.is_valid() && protected.secret_len() != 0 {
+        Some(protected)
+    } else {
+        None
+    }
+}
+
+pub fn erase_passphrase<F: FlashDevice>(store: &mut RecordStore<F>) {
+    if let Err(_) = store.remove(RecordKey::Passphrase) {
+        panic!("Failed to erase passphrase");
+    }
+}
+
+pub const PIN_SALT_LEN: usize = 16;
+
+/// Length of wrapped PIN hash
+const PIN_HASH_LEN: usize = 32;
+
+/// PIN hash record: salt followed by SE-wrapped salted hash
+const PIN_RECORD_LEN: usize = PIN_SALT_LEN + ENCODED_LEN;
+
+pub fn store_pin_record<F: FlashDevice>(store: &mut RecordStore<F>, salt: &[u8; PIN_SALT_LEN], protected: &Protected) -> Result<(), FlashErr> {
+    store.write(RecordKey::PinRecord, &pin_record(salt, protected))
+}
+
+fn pin_record(salt: &[u8; PIN_SALT_LEN], protected: &Protected) -> [u8; PIN_RECORD_LEN] {
+    let mut data = [0u8; PIN_RECORD_LEN];
+    data[..PIN_SALT_LEN].copy_from_slice(salt);
+    data[PIN_SALT_LEN..].copy_from_slice(&protected.0);
+    data
+}
+
+/// Salt and wrapped PIN hash, if PIN was set
+pub fn read_pin_record<F: FlashDevice>(store: &mut RecordStore<F>) -> Option<([u8; PIN_SALT_LEN], Protected)> {
+    let data: [u8; PIN_RECORD_LEN] = store.read(RecordKey::PinRecord)?.try_into().ok()?;
+    parse_pin_record(&data)
+}
+
+fn parse_pin_record(data: &[u8; PIN_RECORD_LEN]) -> Option<([u8; PIN_SALT_LEN], Protected)> {
+    let protected = Protected{0: data[PIN_SALT_LEN..].try_into().expect("static length")};
+    if !protected.is_valid() || protected.secret_len() != PIN_HASH_LEN {
+        return None
+    }
+    let salt = data[..PIN_SALT_LEN].try_into().expect("static length");
+    Some((salt, protected))
+}
+
+pub fn erase_pin_record<F: FlashDevice>(store: &mut RecordStore<F>) {
+    if let Err(_) = store.remove(RecordKey::PinRecord) {
+        panic!("Failed to erase PIN");
+    }
+}
+
+/// Failed PIN attempts counter is rewritten often, and wear levelling of
+/// record store spreads it over the whole region
+pub fn store_pin_failures<F: FlashDevice>(store: &mut RecordStore<F>, failures: u8) -> Result<(), FlashErr> {
+    store.write(RecordKey::PinFailures, &[failures])
+}
+
+pub fn read_pin_failures<F: FlashDevice>(store: &mut RecordStore<F>) -> u8 {
+    match store.read(RecordKey::PinFailures).as_deref() {
+        Some([failures]) => *failures,
+        _ => 0,
+    }
+}
+
+/// Pages holding device records before storage version 3; their contents
+/// are moved into record store
+const LEGACY_COMPANION_KEYS_ADDR: u32 = PAGE_SIZE as u32;
+const LEGACY_ACCOUNTS_ADDR: u32 = 2 * PAGE_SIZE as u32;
+const LEGACY_PASSPHRASE_ADDR: u32 = 3 * PAGE_SIZE as u32;
+const LEGACY_PIN_ADDR: u32 = 4 * PAGE_SIZE as u32;
+const LEGACY_PIN_FAILURES_ADDR: u32 = 5 * PAGE_SIZE as u32;
+
+/// Storage version 2 kept each record on its own page, with no CRC; records
+/// are copied into record store, and pages are erased after all of them are
+/// copied. Record already in store is not copied again.
+pub(crate) fn move_records_to_store<F: FlashDevice>(flash: &mut F) -> Result<(), FlashErr> {
+    let mut store = RecordStore::mount(&mut *flash)?;
+    let mut page = [0u8; PAGE_SIZE];
+
+    store.flash().read_data(LEGACY_COMPANION_KEYS_ADDR, &mut page)?;
+    let count = page[0] as usize;
+    if count <= MAX_COMPANION_KEYS {
+        copy_record(&mut store, RecordKey::Companions, &page[..1 + count * COMPANION_KEY_LEN])?;
+    }
+
+    store.flash().read_data(LEGACY_ACCOUNTS_ADDR, &mut page)?;
+    if page.iter().any(|b| *b != 0xff) {
+        copy_record(&mut store, RecordKey::Accounts, &page[..MAX_ACCOUNTS_LEN])?;
+    }
+
+    store.flash().read_data(LEGACY_PASSPHRASE_ADDR, &mut page)?;
+    let protected = Protected{0: page[..ENCODED_LEN].try_into().expect("static length")};
+    if protected.is_valid() && protected.secret_len() != 0 {
+        copy_record(&mut store, RecordKey::Passphrase, &protected.0)?;
+    }
+
+    store.flash().read_data(LEGACY_PIN_ADDR, &mut page)?;
+    let record: &[u8; PIN_RECORD_LEN] = page[..PIN_RECORD_LEN].try_into().expect("static length");
+    if parse_pin_record(record).is_some() {
+        copy_record(&mut store, RecordKey::PinRecord, record)?;
+    }
+
+    store.flash().read_data(LEGACY_PIN_FAILURES_ADDR, &mut page)?;
+    if !matches!(page[0], 0 | 0xff) {
+        copy_record(&mut store, RecordKey::PinFailures, &page[..1])?;
+    }
+
+    flash.erase_data(LEGACY_COMPANION_KEYS_ADDR, 5);
+    Ok(())
+}
+
+fn copy_record<F: FlashDevice>(store: &mut RecordStore<F>, key: RecordKey, data: &[u8]) -> Result<(), FlashErr> {
+    if store.read(key).is_some() {
+        return Ok(())
+    }
+    store.write(key, data)
+}
+
+/// Pages from encoded entropy to its second copy, with pages of storage
+/// version 2 records between them; storage header after them and wordlist
+/// further in flash are not among them
+const STORAGE_PAGES: u32 = 7;
+
+/// Erase encoded entropy, leftover pages of older layout and record store,
+/// and check that they read back blank
+pub fn erase_storage<F: FlashDevice>(store: &mut RecordStore<F>) -> Result<(), FlashErr> {
+    let flash = store.flash();
+    flash.erase_data(0, STORAGE_PAGES);
+    let mut data = [0u8; PAGE_SIZE];
+    for i in 0..STORAGE_PAGES {
+        flash.read_data(i * PAGE_SIZE as u32, &mut data)?;
+        if data.iter().any(|b| *b != 0xff) {
+            return Err(FlashErr::NotErased)
+        }
+    }
+    store.clear()
 }
 
 #[allow(dead_code)]
@@ -391,4 +787,226 @@ pub fn flash_read(peripherals: &mut Peripherals, addr: u32, data: &mut [u8]) {
     // flash_write_some(peripherals, &[0u8, 0u8, 0u8]);
     flash_read_some(peripherals, data);
     deselect_flash(&mut peripherals.GPIO_S);
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::flash_device::{CutFlash, RamFlash};
+    use crate::devices::record_store::{RECORD_STORE_ADDR, RECORD_STORE_PAGES};
+    use crate::devices::se_aes_gcm::{RECORD_PIN_BOUND, RECORD_RANDOM_IV};
+
+    /// Flash pages up to the end of record store region
+    const FLASH_PAGES: usize = RECORD_STORE_ADDR as usize / PAGE_SIZE + RECORD_STORE_PAGES;
+
+    fn protected(version: u8, secret_len: u8, fill: u8) -> Protected {
+        let mut protected = [fill; ENCODED_LEN];
+        protected[0] = version;
+        protected[1] = secret_len;
+        Protected(protected)
+    }
+
+    #[test]
+    fn companion_keys_stored() {
+        let mut flash = RamFlash::new(FLASH_PAGES);
+        let mut store = RecordStore::mount(&mut flash).unwrap();
+        assert!(read_companion_keys(&mut store).is_empty());
+        let keys = [[2u8; COMPANION_KEY_LEN], [3u8; COMPANION_KEY_LEN]];
+        store_companion_keys(&mut store, &keys).unwrap();
+        assert_eq!(read_companion_keys(&mut store), keys);
+
+        // keys beyond the limit are dropped
+        let keys: Vec<_> = (0..MAX_COMPANION_KEYS + 2).map(|i| [i as u8; COMPANION_KEY_LEN]).collect();
+        store_companion_keys(&mut store, &keys).unwrap();
+        assert_eq!(read_companion_keys(&mut store), keys[..MAX_COMPANION_KEYS]);
+
+        // count not matching length is garbage
+        store.write(RecordKey::Companions, &[2; 1 + COMPANION_KEY_LEN]).unwrap();
+        assert!(read_companion_keys(&mut store).is_empty());
+    }
+
+    #[test]
+    fn accounts_stored() {
+        let mut flash = RamFlash::new(FLASH_PAGES);
+        let mut store = RecordStore::mount(&mut flash).unwrap();
+        assert!(read_accounts(&mut store).is_empty());
+        store_accounts(&mut store, &[7; MAX_ACCOUNTS_LEN]).unwrap();
+        assert_eq!(read_accounts(&mut store), [7; MAX_ACCOUNTS_LEN]);
+        assert_eq!(store_accounts(&mut store, &[8; MAX_ACCOUNTS_LEN + 1]), Err(FlashErr::RecordTooLong));
+        assert_eq!(read_accounts(&mut store), [7; MAX_ACCOUNTS_LEN]);
+    }
+
+    #[test]
+    fn passphrase_stored_and_erased() {
+        let mut flash = RamFlash::new(FLASH_PAGES);
+        let mut store = RecordStore::mount(&mut flash).unwrap();
+        assert!(read_encoded_passphrase(&mut store).is_none());
+        store_encoded_passphrase(&mut store, &protected(RECORD_RANDOM_IV, 10, 0x43)).unwrap();
+        assert_eq!(read_encoded_passphrase(&mut store).unwrap().0, protected(RECORD_RANDOM_IV, 10, 0x43).0);
+        erase_passphrase(&mut store);
+        assert!(read_encoded_passphrase(&mut store).is_none());
+
+        // empty passphrase is no passphrase
+        store_encoded_passphrase(&mut store, &protected(RECORD_RANDOM_IV, 0, 0x43)).unwrap();
+        assert!(read_encoded_passphrase(&mut store).is_none());
+    }
+
+    #[test]
+    fn pin_record_stored_and_erased() {
+        let mut flash = RamFlash::new(FLASH_PAGES);
+        let mut store = RecordStore::mount(&mut flash).unwrap();
+        assert!(read_pin_record(&mut store).is_none());
+        let salt = [0x55; PIN_SALT_LEN];
+        store_pin_record(&mut store, &salt, &protected(RECORD_RANDOM_IV, PIN_HASH_LEN as u8, 0x44)).unwrap();
+        let (read_salt, read_protected) = read_pin_record(&mut store).unwrap();
+        assert_eq!((read_salt, read_protected.0), (salt, protected(RECORD_RANDOM_IV, PIN_HASH_LEN as u8, 0x44).0));
+
+        // wrapped secret that is not a hash is rejected
+        store_pin_record(&mut store, &salt, &protected(RECORD_RANDOM_IV, 10, 0x44)).unwrap();
+        assert!(read_pin_record(&mut store).is_none());
+        store_pin_record(&mut store, &salt, &protected(RECORD_PIN_BOUND + 1, PIN_HASH_LEN as u8, 0x44)).unwrap();
+        assert!(read_pin_record(&mut store).is_none());
+
+        store_pin_record(&mut store, &salt, &protected(RECORD_RANDOM_IV, PIN_HASH_LEN as u8, 0x44)).unwrap();
+        erase_pin_record(&mut store);
+        assert!(read_pin_record(&mut store).is_none());
+    }
+
+    #[test]
+    fn pin_failures_counted() {
+        let mut flash = RamFlash::new(FLASH_PAGES);
+        let mut store = RecordStore::mount(&mut flash).unwrap();
+        assert_eq!(read_pin_failures(&mut store), 0);
+        for failures in 1..=3 {
+            store_pin_failures(&mut store, failures).unwrap();
+        }
+        drop(store);
+        let mut store = RecordStore::mount(&mut flash).unwrap();
+        assert_eq!(read_pin_failures(&mut store), 3);
+    }
+
+    #[test]
+    fn entropy_survives_bad_copy() {
+        let mut flash = RamFlash::new(FLASH_PAGES);
+        assert!(read_encoded_entropy(&mut flash).unwrap().is_none());
+        store_encoded_entopy(&mut flash, &protected(RECORD_RANDOM_IV, 32, 0x21));
+        store_encoded_entopy(&mut flash, &protected(RECORD_PIN_BOUND, 32, 0x22));
+
+        for addr in ENTROPY_ADDRS.iter() {
+            // damage one copy, it is restored from another
+            flash.memory()[*addr as usize + 5] ^= 1;
+            assert_eq!(read_encoded_entropy(&mut flash).unwrap().unwrap().0, protected(RECORD_PIN_BOUND, 32, 0x22).0);
+            let copies = entropy_copies(&mut flash);
+            assert_eq!(copies[0], copies[1]);
+        }
+
+        // no valid copy left
+        for addr in ENTROPY_ADDRS.iter() {
+            flash.memory()[*addr as usize + 5] ^= 1;
+        }
+        assert_eq!(read_encoded_entropy(&mut flash).err(), Some(FlashErr::Corrupted));
+
+        erase_encoded_entropy(&mut flash);
+        assert!(read_encoded_entropy(&mut flash).unwrap().is_none());
+    }
+
+    fn entropy_copies(flash: &mut RamFlash) -> [[u8; ENTROPY_RECORD_LEN]; 2] {
+        let mut copies = [[0u8; ENTROPY_RECORD_LEN]; 2];
+        for (copy, addr) in copies.iter_mut().zip(ENTROPY_ADDRS.iter()) {
+            flash.read_data(*addr, copy).unwrap();
+        }
+        copies
+    }
+
+    #[test]
+    fn newest_entropy_copy_wins() {
+        let mut flash = RamFlash::new(FLASH_PAGES);
+        store_encoded_entopy(&mut flash, &protected(RECORD_RANDOM_IV, 32, 0x21));
+        // power cut after first copy of new entropy
+        store_first_entropy_copy(&mut flash, &protected(RECORD_RANDOM_IV, 16, 0x23));
+        assert_eq!(read_encoded_entropy(&mut flash).unwrap().unwrap().0, protected(RECORD_RANDOM_IV, 16, 0x23).0);
+        // older copy is kept until copies are settled
+        let copies = entropy_copies(&mut flash);
+        assert_eq!((entropy_record_seq(&copies[0]), entropy_record_seq(&copies[1])), (Some(2), Some(1)));
+    }
+
+    #[test]
+    fn entropy_copies_settled() {
+        let old = protected(RECORD_PIN_BOUND, 32, 0x21);
+        let new = protected(RECORD_PIN_BOUND, 32, 0x22);
+        let mut flash = RamFlash::new(FLASH_PAGES);
+        store_encoded_entopy(&mut flash, &old);
+        assert!(settle_entropy_copies(&mut flash, |_| true).unwrap().is_none());
+
+        // newest copy opens, rewrap is finished
+        store_first_entropy_copy(&mut flash, &new);
+        assert_eq!(settle_entropy_copies(&mut flash, |p| p.0 == new.0).unwrap().unwrap().0, new.0);
+        let copies = entropy_copies(&mut flash);
+        assert_eq!(copies[0], copies[1]);
+        assert_eq!(read_encoded_entropy(&mut flash).unwrap().unwrap().0, new.0);
+
+        // only older copy opens, rewrap is rolled back
+        store_first_entropy_copy(&mut flash, &old);
+        assert_eq!(settle_entropy_copies(&mut flash, |p| p.0 == new.0).unwrap().unwrap().0, new.0);
+        let copies = entropy_copies(&mut flash);
+        assert_eq!(copies[0], copies[1]);
+        assert_eq!(entropy_record_seq(&copies[0]), Some(4));
+        assert_eq!(read_encoded_entropy(&mut flash).unwrap().unwrap().0, new.0);
+
+        // neither opens, newest is kept
+        store_first_entropy_copy(&mut flash, &old);
+        assert_eq!(settle_entropy_copies(&mut flash, |_| false).unwrap().unwrap().0, old.0);
+        assert!(settle_entropy_copies(&mut flash, |_| false).unwrap().is_none());
+        assert_eq!(read_encoded_entropy(&mut flash).unwrap().unwrap().0, old.0);
+    }
+
+    #[test]
+    fn entropy_copies_settled_after_power_cut() {
+        let old = protected(RECORD_PIN_BOUND, 32, 0x21);
+        let new = protected(RECORD_PIN_BOUND, 32, 0x22);
+        for opening in [&old, &new] {
+            let mut cut = 0;
+            loop {
+                let mut flash = CutFlash::new(RamFlash::new(FLASH_PAGES));
+                store_encoded_entopy(&mut flash, &old);
+                store_first_entropy_copy(&mut flash, &new);
+                flash.ops_left = cut;
+                let settled = settle_entropy_copies(&mut flash, |p| p.0 == opening.0);
+                let done = flash.ops_left != 0;
+                flash.ops_left = usize::MAX;
+                if !done {
+                    // one of copies opening with PIN survives, and it is
+                    // settled on next boot
+                    read_encoded_entropy(&mut flash).unwrap();
+                    settle_entropy_copies(&mut flash, |p| p.0 == opening.0).unwrap();
+                } else {
+                    settled.unwrap();
+                }
+                assert_eq!(read_encoded_entropy(&mut flash).unwrap().unwrap().0, opening.0);
+                let copies = entropy_copies(&mut flash.flash);
+                assert_eq!(copies[0], copies[1]);
+                if done {
+                    break
+                }
+                cut += 1;
+            }
+        }
+    }
+
+    #[test]
+    fn storage_erased() {
+        let mut flash = RamFlash::new(FLASH_PAGES);
+        flash.memory()[..STORAGE_PAGES as usize * PAGE_SIZE].fill(0);
+        let header = flash.memory()[STORAGE_PAGES as usize * PAGE_SIZE..(STORAGE_PAGES as usize + 1) * PAGE_SIZE].to_vec();
+        let mut store = RecordStore::mount(&mut flash).unwrap();
+        store_accounts(&mut store, &[7; 20]).unwrap();
+        store_pin_failures(&mut store, 2).unwrap();
+        erase_storage(&mut store).unwrap();
+        assert!(read_accounts(&mut store).is_empty());
+        assert_eq!(read_pin_failures(&mut store), 0);
+        drop(store);
+        assert!(flash.memory()[..STORAGE_PAGES as usize * PAGE_SIZE].iter().all(|b| *b == 0xff));
+        // storage header is kept
+        assert_eq!(flash.memory()[STORAGE_PAGES as usize * PAGE_SIZE..(STORAGE_PAGES as usize + 1) * PAGE_SIZE], header[..]);
+        assert!(RecordStore::mount(&mut flash).unwrap().read(RecordKey::Accounts).is_none());
+    }
+}
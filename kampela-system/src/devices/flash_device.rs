@@ -0,0 +1,252 @@
+//! Flash memory access used by storage, with in-memory implementation for
+//! running storage logic off device.
+
+use alloc::{vec, vec::Vec};
+use core::cmp;
+
+use super::flash::{FlashErr, PAGE_SIZE};
+
+const ERASED: u8 = 0xff;
+
+/// NOR flash with pages of `PAGE_SIZE` bytes: erase sets all bytes of page to
+/// `0xff`, and programming could only clear bits
+pub trait FlashDevice {
+    /// Read data starting at address
+    fn read(&mut self, addr: u32, data: &mut [u8]);
+
+    /// Erase page starting at address
+    fn erase_page(&mut self, addr: u32);
+
+    /// Program data into erased flash; data must not cross page boundary
+    fn program(&mut self, addr: u32, data: &[u8]);
+
+    //----derivatives----
+
+    /// Erase pages starting with the one holding `addr`, write payload and
+    /// check that it reads back
+    fn store_data<const N: usize>(&mut self, addr: u32, payload: &[u8; N]) -> Result<(), FlashErr> {
+        let mut data = [0u8; N];
+        let mut read_data_chunk = [0u8; PAGE_SIZE];
+        let initial_addr = addr / PAGE_SIZE as u32 * PAGE_SIZE as u32;
+        for (i, chunk) in payload.chunks(PAGE_SIZE).enumerate() {
+            let addr = initial_addr + i as u32 * PAGE_SIZE as u32;
+            self.erase_page(addr);
+            self.program(addr, chunk);
+            self.read(addr, &mut read_data_chunk);
+            let chunk_start = i * PAGE_SIZE;
+            let chunk_len = cmp::min(N - chunk_start, PAGE_SIZE);
+            data[chunk_start..chunk_start + chunk_len].clone_from_slice(&read_data_chunk[0..chunk_len]);
+        }
+
+        if &data != payload {
+            Err(FlashErr::WriteNotMatch)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Read data starting with the page holding `addr`
+    fn read_data(&mut self, addr: u32, data: &mut [u8]) -> Result<(), FlashErr> {
+        let mut read_data_chunk = [0u8; PAGE_SIZE];
+        let initial_addr = addr / PAGE_SIZE as u32 * PAGE_SIZE as u32;
+        for i in 0..data.len().div_ceil(PAGE_SIZE) {
+            let addr = initial_addr + i as u32 * PAGE_SIZE as u32;
+            self.read(addr, &mut read_data_chunk);
+            let chunk_start = i * PAGE_SIZE;
+            let chunk_len = cmp::min(data.len() - chunk_start, PAGE_SIZE);
+            data[chunk_start..chunk_start + chunk_len].clone_from_slice(&read_data_chunk[0..chunk_len]);
+        }
+        Ok(())
+    }
+
+    /// Erase pages starting with the one holding `addr`
+    fn erase_data(&mut self, addr: u32, pages: u32) {
+        let initial_addr = addr / PAGE_SIZE as u32 * PAGE_SIZE as u32;
+        for i in 0..pages {
+            self.erase_page(initial_addr + i as u32 * PAGE_SIZE as u32);
+        }
+    }
+}
+
+/// Flash borrowed for a while, e.g. by record store mounted during migration
+impl<F: FlashDevice> FlashDevice for &mut F {
+    fn read(&mut self, addr: u32, data: &mut [u8]) {
+        (**self).read(addr, data)
+    }
+
+    fn erase_page(&mut self, addr: u32) {
+        (**self).erase_page(addr)
+    }
+
+    fn program(&mut self, addr: u32, data: &[u8]) {
+        (**self).program(addr, data)
+    }
+}
+
+/// Flash kept in memory, with NOR erase and program semantics
+pub struct RamFlash {
+    memory: Vec<u8>,
+}
+
+impl RamFlash {
+    /// Erased flash of given number of pages
+    pub fn new(pages: usize) -> Self {
+        RamFlash {
+            memory: vec![ERASED; pages * PAGE_SIZE],
+        }
+    }
+
+    /// Flash contents, to inspect or damage them
+    pub fn memory(&mut self) -> &mut [u8] {
+        &mut self.memory
+    }
+}
+
+impl FlashDevice for RamFlash {
+    fn read(&mut self, addr: u32, data: &mut [u8]) {
+        let start = addr as usize;
+        data.copy_from_slice(&self.memory[start..start + data.len()]);
+    }
+
+    fn erase_page(&mut self, addr: u32) {
+        let start = addr as usize / PAGE_SIZE * PAGE_SIZE;
+        self.memory[start..start + PAGE_SIZE].fill(ERASED);
+    }
+
+    fn program(&mut self, addr: u32, data: &[u8]) {
+        // page program wraps around within page, as on NOR chip
+        let page_start = addr as usize / PAGE_SIZE * PAGE_SIZE;
+        let offset = addr as usize - page_start;
+        for (i, byte) in data.iter().take(PAGE_SIZE).enumerate() {
+            self.memory[page_start + (offset + i) % PAGE_SIZE] &= byte;
+        }
+    }
+}
+
+/// Flash losing power after `ops_left` erase and program operations; later
+/// operations are dropped
+#[cfg(test)]
+pub(crate) struct CutFlash {
+    pub flash: RamFlash,
+    pub ops_left: usize,
+}
+
+#[cfg(test)]
+impl CutFlash {
+    /// Flash not losing power until `ops_left` is set
+    pub fn new(flash: RamFlash) -> Self {
+        CutFlash { flash, ops_left: usize::MAX }
+    }
+}
+
+#[cfg(test)]
+impl FlashDevice for CutFlash {
+    fn read(&mut self, addr: u32, data: &mut [u8]) {
+        self.flash.read(addr, data)
+    }
+
+    fn erase_page(&mut self, addr: u32) {
+        if self.ops_left != 0 {
+            self.ops_left -= 1;
+            self.flash.erase_page(addr)
+        }
+    }
+
+    fn program(&mut self, addr: u32, data: &[u8]) {
+        if self.ops_left != 0 {
+            self.ops_left -= 1;
+            self.flash.program(addr, data)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn counted(len: usize) -> Vec<u8> {
+        (0..len).map(|i| i as u8).collect()
+    }
+
+    #[test]
+    fn new_flash_is_erased() {
+        let mut flash = RamFlash::new(2);
+        assert_eq!(flash.memory().len(), 2 * PAGE_SIZE);
+        let mut data = [0u8; 2 * PAGE_SIZE];
+        flash.read(0, &mut data);
+        assert!(data.iter().all(|b| *b == ERASED));
+    }
+
+    #[test]
+    fn program_only_clears_bits() {
+        let mut flash = RamFlash::new(1);
+        flash.program(10, &[0b1010_1010, 0x0f]);
+        flash.program(10, &[0b1100_1100, 0xf0]);
+        let mut data = [0u8; 3];
+        flash.read(10, &mut data);
+        assert_eq!(data, [0b1000_1000, 0x00, ERASED]);
+    }
+
+    #[test]
+    fn erase_clears_only_its_page() {
+        let mut flash = RamFlash::new(3);
+        flash.memory().fill(0);
+        // any address within page erases whole page
+        flash.erase_page(PAGE_SIZE as u32 + 7);
+        assert!(flash.memory()[..PAGE_SIZE].iter().all(|b| *b == 0));
+        assert!(flash.memory()[PAGE_SIZE..2 * PAGE_SIZE].iter().all(|b| *b == ERASED));
+        assert!(flash.memory()[2 * PAGE_SIZE..].iter().all(|b| *b == 0));
+    }
+
+    #[test]
+    fn program_wraps_around_within_page() {
+        let mut flash = RamFlash::new(2);
+        flash.program(PAGE_SIZE as u32 - 2, &[1, 2, 3, 4]);
+        assert_eq!(flash.memory()[PAGE_SIZE - 2..PAGE_SIZE], [1, 2]);
+        assert_eq!(flash.memory()[..2], [3, 4]);
+        // next page untouched
+        assert!(flash.memory()[PAGE_SIZE..].iter().all(|b| *b == ERASED));
+    }
+
+    #[test]
+    fn read_crosses_page_boundary() {
+        let mut flash = RamFlash::new(2);
+        flash.program(0, &counted(PAGE_SIZE));
+        flash.program(PAGE_SIZE as u32, &[0xaa; 4]);
+        let mut data = [0u8; 4];
+        flash.read(PAGE_SIZE as u32 - 2, &mut data);
+        assert_eq!(data, [(PAGE_SIZE - 2) as u8, (PAGE_SIZE - 1) as u8, 0xaa, 0xaa]);
+    }
+
+    #[test]
+    fn data_spans_pages() {
+        let mut flash = RamFlash::new(4);
+        flash.memory().fill(0);
+        let payload: [u8; PAGE_SIZE + 10] = counted(PAGE_SIZE + 10).try_into().unwrap();
+        // data is aligned to page holding address
+        flash.store_data(PAGE_SIZE as u32 + 5, &payload).unwrap();
+        assert_eq!(flash.memory()[PAGE_SIZE..2 * PAGE_SIZE + 10], payload[..]);
+        // rest of the last page written is erased, pages around untouched
+        assert!(flash.memory()[2 * PAGE_SIZE + 10..3 * PAGE_SIZE].iter().all(|b| *b == ERASED));
+        assert!(flash.memory()[..PAGE_SIZE].iter().all(|b| *b == 0));
+        assert!(flash.memory()[3 * PAGE_SIZE..].iter().all(|b| *b == 0));
+
+        let mut data = [0u8; PAGE_SIZE + 10];
+        flash.read_data(PAGE_SIZE as u32 + 5, &mut data).unwrap();
+        assert_eq!(data, payload);
+
+        flash.erase_data(PAGE_SIZE as u32 + 5, 2);
+        assert!(flash.memory()[PAGE_SIZE..3 * PAGE_SIZE].iter().all(|b| *b == ERASED));
+        assert!(flash.memory()[3 * PAGE_SIZE..].iter().all(|b| *b == 0));
+    }
+
+    #[test]
+    fn failed_write_reported() {
+        let mut flash = CutFlash::new(RamFlash::new(1));
+        flash.flash.memory().fill(0);
+        flash.ops_left = 1;
+        // page erased, but program dropped
+        assert_eq!(flash.store_data(0, &[1u8, 2, 3]), Err(FlashErr::WriteNotMatch));
+        assert!(flash.flash.memory().iter().all(|b| *b == ERASED));
+    }
+}
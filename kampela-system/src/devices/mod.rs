@@ -6,6 +6,10 @@ pub mod display;
 pub mod display_transmission;
 pub mod se_rng;
 pub mod se_aes_gcm;
+pub mod se_hash;
 pub mod touch;
 pub mod flash;
+pub mod flash_device;
+pub mod record_store;
+pub mod storage_schema;
 
@@ -6,48 +6,61 @@ use efm32pg23_fix::Peripherals;
 use crate::peripherals::eusart::*;
 use substrate_parser::{cards::{Call, ExtendedData}, decode_as_call_unmarked, decode_extensions_unmarked};
 use crate::in_free;
+use crate::merkleized_metadata::{MetadataProof, MetadataProofError};
 
-pub fn psram_decode_call(call_psram_access: &PsramAccess, metadata_psram_access: &PsramAccess) -> (Call, ShortSpecs, String) {
+/// Decode call with types from metadata proof, if there is one.
+///
+/// Metadata proof must be checked by `psram_check_metadata_proof` before, only
+/// then its types are the ones chain has committed to.
+pub fn psram_decode_call(
+    call_psram_access: &PsramAccess,
+    metadata_psram_access: &PsramAccess,
+    metadata_proof_psram_access: Option<&PsramAccess>,
+) -> (Call, ShortSpecs, String) {
     let call_data = read_from_psram(call_psram_access);
 
-    let (
-        checked_metadata_metal,
-        specs,
-        spec_name,
-    ) = read_checked_metadata_metal(metadata_psram_access);
+    match metadata_proof_psram_access {
+        Some(metadata_proof_psram_access) => {
+            let (proof_metadata, specs, spec_name) = read_proof_metadata(metadata_proof_psram_access);
+            (decode_call_with(&call_data, &proof_metadata), specs, spec_name)
+        },
+        None => {
+            let (checked_metadata_metal, specs, spec_name) = read_checked_metadata_metal(metadata_psram_access);
+            (decode_call_with(&call_data, &checked_metadata_metal), specs, spec_name)
+        },
+    }
+}
 
+fn decode_call_with<M>(call_data: &[u8], metadata: &M) -> Call
+where
+    M: for<'a> AsMetadata<ExternalPsram<'a>>,
+{
     let mut decoded_call_option = None;
     in_free(|peripherals| {
         let mut external_psram = ExternalPsram{peripherals};
         let mut decoding_postition = 0;
         let decoded_call = decode_as_call_unmarked(
-            &call_data.as_ref(),
+            &call_data,
             &mut decoding_postition,
             &mut external_psram,
-            &checked_metadata_metal,
+            metadata,
         ).unwrap();
 
         decoded_call_option = Some(decoded_call);
     });
-    (
-        decoded_call_option.unwrap(),
-        specs,
-        spec_name,
-    )
+    decoded_call_option.unwrap()
 }
 
+/// Decode signed extensions with types from metadata proof, if there is one.
+///
+/// Metadata proof must be checked by `psram_check_metadata_proof` before.
 pub fn psram_decode_extension(
     extension_psram_access: &PsramAccess,
     metadata_psram_access: &PsramAccess,
+    metadata_proof_psram_access: Option<&PsramAccess>,
     genesis_hash_bytes_psram_access: &PsramAccess,
 ) -> (Vec<ExtendedData>, ShortSpecs, String) {
     let extension_data = read_from_psram(extension_psram_access);
-    
-    let (
-        checked_metadata_metal,
-        specs,
-        spec_name
-    ) = read_checked_metadata_metal(metadata_psram_access);
 
     let genesis_hash = H256(
         read_from_psram(genesis_hash_bytes_psram_access)
@@ -55,24 +68,66 @@ pub fn psram_decode_extension(
             .expect("static size")
     );
 
+    match metadata_proof_psram_access {
+        Some(metadata_proof_psram_access) => {
+            let (proof_metadata, specs, spec_name) = read_proof_metadata(metadata_proof_psram_access);
+            (decode_extension_with(&extension_data, &proof_metadata, genesis_hash), specs, spec_name)
+        },
+        None => {
+            let (checked_metadata_metal, specs, spec_name) = read_checked_metadata_metal(metadata_psram_access);
+            (decode_extension_with(&extension_data, &checked_metadata_metal, genesis_hash), specs, spec_name)
+        },
+    }
+}
+
+fn decode_extension_with<M>(extension_data: &[u8], metadata: &M, genesis_hash: H256) -> Vec<ExtendedData>
+where
+    M: for<'a> AsMetadata<ExternalPsram<'a>>,
+{
     let mut decoded_extension_option = None;
     in_free(|peripherals| {
         let mut external_psram = ExternalPsram{peripherals};
         let mut decoding_postition = 0;
         let decoded_extension = decode_extensions_unmarked(
-            &extension_data.as_ref(),
+            &extension_data,
             &mut decoding_postition,
             &mut external_psram,
-            &checked_metadata_metal,
+            metadata,
             Some(genesis_hash),
         ).unwrap();
 
         decoded_extension_option = Some(decoded_extension);
     });
+    decoded_extension_option.unwrap()
+}
+
+/// Check RFC-0078 metadata proof against received metadata and against
+/// metadata hash in signed extensions data.
+pub fn psram_check_metadata_proof(
+    metadata_proof_psram_access: &PsramAccess,
+    metadata_psram_access: &PsramAccess,
+    extension_psram_access: &PsramAccess,
+) -> Result<(), MetadataProofError> {
+    let proof_data = read_from_psram(metadata_proof_psram_access);
+    let metadata_proof = MetadataProof::decode_all(&mut &proof_data[..]).map_err(|_| MetadataProofError::Format)?;
+
+    let (checked_metadata_metal, _, _) = read_checked_metadata_metal(metadata_psram_access);
+    checked_metadata_metal.check_proof(&metadata_proof)?;
+
+    let extension_data = read_from_psram(extension_psram_access);
+    metadata_proof.check_extensions(&extension_data)
+}
+
+fn read_proof_metadata(metadata_proof_psram_access: &PsramAccess) -> (ProofMetadata, ShortSpecs, String) {
+    let proof_data = read_from_psram(metadata_proof_psram_access);
+    let metadata_proof = MetadataProof::decode_all(&mut &proof_data[..]).unwrap();
+    let proof_metadata = ProofMetadata::from(&metadata_proof).unwrap();
+    let specs = proof_metadata.to_specs();
+    let spec_name = proof_metadata.spec_name_version.spec_name.to_owned();
     (
-        decoded_extension_option.unwrap(),
+        proof_metadata,
         specs,
-        spec_name,
+        spec_name
     )
 }
 
@@ -316,8 +371,8 @@ pub struct PsramAccess {
     pub start_address: AddressPsram,
     pub total_len: usize,
 }
-use core::{any::TypeId, fmt::{Debug, Display, Formatter, Result as FmtResult}};
-use alloc::borrow::ToOwned;
+use core::{any::TypeId, cmp, fmt::{Debug, Display, Formatter, Result as FmtResult}};
+use alloc::{borrow::ToOwned, collections::BTreeMap};
 
 use external_memory_tools::{AddressableBuffer, BufferError, ExternalMemory};
 use parity_scale_codec::{Decode, DecodeAll, Encode};
@@ -438,6 +493,84 @@ impl <'a> AsMetadata<ExternalPsram<'a>> for CheckedMetadataMetal {
     }
 }
 
+/// Types registry made of metadata proof leaves.
+#[derive(Clone, Debug)]
+pub struct ProofRegistry(pub BTreeMap<u32, Type<PortableForm>>);
+
+impl <'a> ResolveType<ExternalPsram<'a>> for ProofRegistry {
+    fn resolve_ty(&self, id: u32, _ext_memory: &mut ExternalPsram<'a>) -> Result<Type<PortableForm>, RegistryError<ExternalPsram<'a>>> {
+        self.0
+            .get(&id)
+            .cloned()
+            .ok_or(RegistryError::Internal(RegistryInternalError::TypeNotResolved { id }))
+    }
+}
+
+/// Metadata with only the types from RFC-0078 metadata proof.
+#[derive(Debug)]
+pub struct ProofMetadata {
+    pub types: ProofRegistry,
+    pub call_ty: UntrackedSymbol<TypeId>,
+    pub signed_extensions: Vec<SignedExtensionMetadata>,
+    pub spec_name_version: SpecNameVersion,
+    pub base58prefix: u16,
+    pub decimals: u8,
+    pub unit: String,
+}
+
+impl <'a> AsMetadata<ExternalPsram<'a>> for ProofMetadata {
+    type TypeRegistry = ProofRegistry;
+    type MetaStructureError = NoEntries;
+    fn types(&self) -> Self::TypeRegistry {
+        self.types.to_owned()
+    }
+    fn spec_name_version(&self) -> Result<SpecNameVersion, Self::MetaStructureError> {
+        Ok(self.spec_name_version.to_owned())
+    }
+    fn call_ty(&self) -> Result<UntrackedSymbol<TypeId>, Self::MetaStructureError> {
+        Ok(self.call_ty.to_owned())
+    }
+    fn signed_extensions(&self) -> Result<Vec<SignedExtensionMetadata>, Self::MetaStructureError> {
+        Ok(self.signed_extensions.to_owned())
+    }
+}
+
+impl ProofMetadata {
+    pub fn from(metadata_proof: &MetadataProof) -> Result<Self, MetadataProofError> {
+        let extrinsic = &metadata_proof.extrinsic;
+        let signed_extensions = extrinsic
+            .signed_extensions
+            .iter()
+            .map(|a| Ok(SignedExtensionMetadata {
+                identifier: a.identifier.to_owned(),
+                ty: a.included_in_extrinsic.id()?.into(),
+                additional_signed: a.included_in_signed_data.id()?.into(),
+            }))
+            .collect::<Result<Vec<SignedExtensionMetadata>, MetadataProofError>>()?;
+        let extra_info = &metadata_proof.extra_info;
+        Ok(ProofMetadata {
+            types: ProofRegistry(metadata_proof.proof.types()?),
+            call_ty: extrinsic.call_ty.id()?.into(),
+            signed_extensions,
+            spec_name_version: SpecNameVersion {
+                printed_spec_version: format!("{}", extra_info.spec_version),
+                spec_name: extra_info.spec_name.to_owned(),
+            },
+            base58prefix: extra_info.base58_prefix,
+            decimals: extra_info.decimals,
+            unit: extra_info.token_symbol.to_owned(),
+        })
+    }
+
+    pub fn to_specs(&self) -> ShortSpecs {
+        ShortSpecs {
+            base58prefix: self.base58prefix,
+            decimals: self.decimals,
+            unit: self.unit.to_owned(),
+        }
+    }
+}
+
 /// Empty error enum, for cases with fault-free memory access.
 #[derive(Debug, Eq, PartialEq)]
 pub enum NoEntries {}
@@ -516,6 +649,32 @@ impl <'a> CheckedMetadataMetal {
         })
     }
 
+    /// Metadata used for decoding must describe the same chain as the proof.
+    ///
+    /// Types are not compared here: signature made with mismatching types is
+    /// still rejected by chain as metadata hash is signed.
+    pub fn check_proof(&self, metadata_proof: &MetadataProof) -> Result<(), MetadataProofError> {
+        let extra_info = &metadata_proof.extra_info;
+        if (extra_info.spec_name != self.spec_name_version.spec_name)
+            | (format!("{}", extra_info.spec_version) != self.spec_name_version.printed_spec_version)
+            | (extra_info.base58_prefix != self.base58prefix)
+            | (extra_info.decimals != self.decimals)
+            | (extra_info.token_symbol != self.unit)
+        {
+            return Err(MetadataProofError::SpecsMismatch)
+        }
+        let proof_extensions = &metadata_proof.extrinsic.signed_extensions;
+        if (proof_extensions.len() != self.signed_extensions.len())
+            | proof_extensions
+                .iter()
+                .zip(self.signed_extensions.iter())
+                .any(|(a, b)| a.identifier != b.identifier)
+        {
+            return Err(MetadataProofError::ExtensionsMismatch)
+        }
+        Ok(())
+    }
+
     pub fn to_specs(&self) -> ShortSpecs {
         ShortSpecs {
             base58prefix: self.base58prefix,
@@ -552,3 +711,119 @@ impl <'a> lt_codes::decoder_metal::ExternalMemory<AddressPsram> for ExternalPsra
          psram_read_at_address(self.peripherals, *address, len).unwrap() //TODO
     }
 }
+
+/// PSRAM handle that takes peripherals from global mutex on each access.
+///
+/// Unlike [`ExternalPsram`], holds no borrow and could be stored in long-living
+/// structures, so that code operating on PSRAM could be generic over memory.
+#[derive(Clone, Copy, Debug)]
+pub struct PsramInFree;
+
+impl ExternalMemory for PsramInFree {
+    type ExternalMemoryError = MemoryError;
+}
+
+impl AddressableBuffer<PsramInFree> for PsramAccess {
+    type ReadBuffer = Vec<u8>;
+    fn total_len(&self) -> usize {
+        self.total_len
+    }
+    fn read_slice(&self, _ext_memory: &mut PsramInFree, position: usize, len: usize) -> Result<Self::ReadBuffer, BufferError<PsramInFree>> {
+        if self.total_len() < position {return Err(BufferError::OutOfRange { position, total_length: self.total_len() })}
+        if self.total_len() < (position + len) {return Err(BufferError::DataTooShort { position: self.total_len(), minimal_length: position + len - self.total_len() })}
+        let address = self.start_address.try_shift(position).map_err(BufferError::External)?;
+        psram_read_in_free(address, len).map_err(BufferError::External)
+    }
+    fn limit_length(&self, new_len: usize) -> Result<Self, BufferError<PsramInFree>> {
+        if new_len > self.total_len {Err(BufferError::DataTooShort { position: 0, minimal_length: new_len })}
+        else {Ok(PsramAccess {
+            start_address: self.start_address,
+            total_len: new_len,
+        })}
+    }
+}
+
+impl lt_codes::decoder_metal::ExternalMemory<AddressPsram> for PsramInFree {
+    fn write_external(&mut self, address: &AddressPsram, data: &[u8]) {
+        let mut result = None;
+        in_free(|peripherals| result = Some(psram_write_at_address(peripherals, *address, data)));
+        result.expect("peripherals are available").unwrap() //TODO
+    }
+    fn read_external(&mut self, address: &AddressPsram, len: usize) -> Vec<u8> {
+        psram_read_in_free(*address, len).unwrap() //TODO
+    }
+}
+
+fn psram_read_in_free(address: AddressPsram, len: usize) -> Result<Vec<u8>, MemoryError> {
+    let mut result = None;
+    in_free(|peripherals| result = Some(psram_read_at_address(peripherals, address, len)));
+    result.expect("peripherals are available")
+}
+
+/// PSRAM kept in memory, for running NFC payload processing off device.
+///
+/// Memory grows with writes; bytes never written read as zero.
+#[derive(Clone, Debug, Default)]
+pub struct RamPsram {
+    memory: Vec<u8>,
+}
+
+impl RamPsram {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn read(&self, address: AddressPsram, len: usize) -> Result<Vec<u8>, MemoryError> {
+        let start = address.as_u32() as usize;
+        if start + len > PSRAM_TOTAL_SIZE as usize {return Err(MemoryError::ReadTooLarge)}
+        let mut out = alloc::vec![0u8; len];
+        if start < self.memory.len() {
+            let available = cmp::min(len, self.memory.len() - start);
+            out[..available].copy_from_slice(&self.memory[start..start + available]);
+        }
+        Ok(out)
+    }
+
+    fn write(&mut self, address: AddressPsram, data: &[u8]) -> Result<(), MemoryError> {
+        let start = address.as_u32() as usize;
+        if start + data.len() > PSRAM_TOTAL_SIZE as usize {return Err(MemoryError::WriteTooLarge)}
+        if self.memory.len() < start + data.len() {
+            self.memory.resize(start + data.len(), 0);
+        }
+        self.memory[start..start + data.len()].copy_from_slice(data);
+        Ok(())
+    }
+}
+
+impl ExternalMemory for RamPsram {
+    type ExternalMemoryError = MemoryError;
+}
+
+impl AddressableBuffer<RamPsram> for PsramAccess {
+    type ReadBuffer = Vec<u8>;
+    fn total_len(&self) -> usize {
+        self.total_len
+    }
+    fn read_slice(&self, ext_memory: &mut RamPsram, position: usize, len: usize) -> Result<Self::ReadBuffer, BufferError<RamPsram>> {
+        if self.total_len() < position {return Err(BufferError::OutOfRange { position, total_length: self.total_len() })}
+        if self.total_len() < (position + len) {return Err(BufferError::DataTooShort { position: self.total_len(), minimal_length: position + len - self.total_len() })}
+        let address = self.start_address.try_shift(position).map_err(BufferError::External)?;
+        ext_memory.read(address, len).map_err(BufferError::External)
+    }
+    fn limit_length(&self, new_len: usize) -> Result<Self, BufferError<RamPsram>> {
+        if new_len > self.total_len {Err(BufferError::DataTooShort { position: 0, minimal_length: new_len })}
+        else {Ok(PsramAccess {
+            start_address: self.start_address,
+            total_len: new_len,
+        })}
+    }
+}
+
+impl lt_codes::decoder_metal::ExternalMemory<AddressPsram> for RamPsram {
+    fn write_external(&mut self, address: &AddressPsram, data: &[u8]) {
+        self.write(*address, data).expect("addresses are within PSRAM")
+    }
+    fn read_external(&mut self, address: &AddressPsram, len: usize) -> Vec<u8> {
+        self.read(*address, len).expect("addresses are within PSRAM")
+    }
+}
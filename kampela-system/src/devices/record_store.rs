@@ -0,0 +1,508 @@
+//! Log-structured store of typed records in reserved region of external flash.
+//!
+//! Records are appended to pages of the region taken in a ring, so all pages
+//! wear evenly. Each record carries its key, sequence number and CRC; valid
+//! record with the highest sequence number is the current value of its key.
+//! Update is atomic: old record stays until the new one is written and read
+//! back, and record torn by power loss fails its CRC and is skipped.
+//!
+//! Page after the one being written is always kept erased. When writing moves
+//! to a new page, current records of the page after it, the oldest one, are
+//! moved to the new page, and the old page is erased.
+//!
+//! Store page spans two flash pages, so that the whole accounts list fits one
+//! record.
+
+use alloc::{vec, vec::Vec};
+
+use crate::devices::flash::{FlashErr, PAGE_SIZE};
+use crate::devices::flash_device::FlashDevice;
+
+/// Region starts after pages of fixed records
+pub const RECORD_STORE_ADDR: u32 = 16 * PAGE_SIZE as u32;
+
+/// Flash pages in region; wordlist starts further, at page 128
+pub const RECORD_STORE_PAGES: usize = 64;
+
+/// Flash pages in one store page
+const FLASH_PAGES_PER_PAGE: usize = 2;
+
+const STORE_PAGE_SIZE: usize = FLASH_PAGES_PER_PAGE * PAGE_SIZE;
+
+/// Store pages in region
+const STORE_PAGES: usize = RECORD_STORE_PAGES / FLASH_PAGES_PER_PAGE;
+
+const ERASED: u8 = 0xff;
+
+const PAGE_MAGIC: [u8; 4] = *b"KREC";
+
+/// Magic and page sequence number
+const PAGE_HEADER_LEN: usize = PAGE_MAGIC.len() + 4;
+
+/// Key, payload length and record sequence number
+const RECORD_HEADER_LEN: usize = 1 + 2 + 4;
+
+const CRC_LEN: usize = 4;
+
+/// Longest payload, so that record fits a page
+pub const MAX_RECORD_LEN: usize = STORE_PAGE_SIZE - PAGE_HEADER_LEN - RECORD_HEADER_LEN - CRC_LEN;
+
+/// Kinds of records kept in store
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[repr(u8)]
+pub enum RecordKey {
+    PinRecord = 1,
+    PinFailures = 2,
+    Accounts = 3,
+    Companions = 4,
+    Passphrase = 5,
+    NetworkSpecs = 6,
+    Calibration = 7,
+    Settings = 8,
+}
+
+/// Where current record of a key is
+#[derive(Clone, Copy, Debug)]
+struct Location {
+    key: u8,
+    seq: u32,
+    /// Sequence number of page holding record; record moved from older page
+    /// keeps its sequence number, and newer copy is preferred
+    page_seq: u32,
+    addr: u32,
+    len: u16,
+}
+
+pub struct RecordStore<F: FlashDevice> {
+    flash: F,
+    /// Current record of each key
+    index: Vec<Location>,
+    /// Page being written
+    head: usize,
+    head_seq: u32,
+    /// Start of free space in page being written
+    offset: usize,
+    next_seq: u32,
+}
+
+impl<F: FlashDevice> RecordStore<F> {
+    /// Scan region and build index of current records; blank region is
+    /// formatted
+    pub fn mount(flash: F) -> Result<Self, FlashErr> {
+        let mut store = RecordStore {
+            flash,
+            index: Vec::new(),
+            head: 0,
+            head_seq: 0,
+            offset: STORE_PAGE_SIZE,
+            next_seq: 0,
+        };
+        let mut formatted = false;
+        let mut page = [0u8; STORE_PAGE_SIZE];
+        for i in 0..STORE_PAGES {
+            store.flash.read_data(page_addr(i), &mut page)?;
+            let Some(page_seq) = page_seq(&page) else { continue };
+            let end = store.scan(i, page_seq, &page);
+            if !formatted || page_seq > store.head_seq {
+                store.head = i;
+                store.head_seq = page_seq;
+                store.offset = end;
+                formatted = true;
+            }
+        }
+        if formatted {
+            // moving records out of the oldest page could have been cut; if
+            // copies made so far end with torn record, they are dropped and
+            // moving starts over
+            if store.offset == STORE_PAGE_SIZE && !store.page_blank((store.head + 1) % STORE_PAGES)? {
+                store.open_page(store.head, store.head_seq)?;
+                return Self::mount(store.flash)
+            }
+            store.clear_next()?;
+        } else {
+            store.open_page(0, 0)?;
+        }
+        Ok(store)
+    }
+
+    /// Flash under the store, for data kept outside of it
+    pub fn flash(&mut self) -> &mut F {
+        &mut self.flash
+    }
+
+    /// Current value of record, if any
+    pub fn read(&mut self, key: RecordKey) -> Option<Vec<u8>> {
+        let location = *self.index.iter().find(|l| l.key == key as u8)?;
+        if location.len == 0 {
+            return None
+        }
+        let mut record = vec![0u8; RECORD_HEADER_LEN + location.len as usize + CRC_LEN];
+        // records are not page aligned, so flash is read as is
+        self.flash.read(location.addr, &mut record);
+        let payload_end = record.len() - CRC_LEN;
+        if crc32(&record[..payload_end]) != u32::from_le_bytes(record[payload_end..].try_into().expect("static length")) {
+            return None
+        }
+        Some(record[RECORD_HEADER_LEN..payload_end].to_vec())
+    }
+
+    /// Write new value of record
+    pub fn write(&mut self, key: RecordKey, data: &[u8]) -> Result<(), FlashErr> {
+        if data.len() > MAX_RECORD_LEN {
+            return Err(FlashErr::RecordTooLong)
+        }
+        if self.offset + RECORD_HEADER_LEN + data.len() + CRC_LEN > STORE_PAGE_SIZE {
+            self.next_page()?;
+        }
+        let seq = self.next_seq;
+        match self.append(key as u8, seq, data) {
+            // spot was not clean, retry on fresh page
+            Err(FlashErr::WriteNotMatch) => {
+                self.next_page()?;
+                self.append(key as u8, seq, data)
+            },
+            a => a,
+        }
+    }
+
+    /// Forget record; empty record shadows older ones until they are erased
+    pub fn remove(&mut self, key: RecordKey) -> Result<(), FlashErr> {
+        if self.index.iter().any(|l| l.key == key as u8 && l.len != 0) {
+            self.write(key, &[])
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Erase whole region and start over with no records
+    pub fn clear(&mut self) -> Result<(), FlashErr> {
+        for page in 0..STORE_PAGES {
+            self.erase_page(page)?;
+        }
+        self.index.clear();
+        self.next_seq = 0;
+        self.open_page(0, 0)
+    }
+
+    /// Parse records of page into index; returns offset of free space,
+    /// or page end if page has torn record
+    fn scan(&mut self, page: usize, page_seq: u32, data: &[u8; STORE_PAGE_SIZE]) -> usize {
+        let mut offset = PAGE_HEADER_LEN;
+        while offset + RECORD_HEADER_LEN + CRC_LEN <= STORE_PAGE_SIZE && data[offset] != ERASED {
+            let len = u16::from_le_bytes(data[offset + 1..offset + 3].try_into().expect("static length"));
+            let payload_end = offset + RECORD_HEADER_LEN + len as usize;
+            let end = payload_end + CRC_LEN;
+            if end > STORE_PAGE_SIZE || crc32(&data[offset..payload_end]) != u32::from_le_bytes(data[payload_end..end].try_into().expect("static length")) {
+                // nothing after torn record could be trusted
+                return STORE_PAGE_SIZE
+            }
+            let seq = u32::from_le_bytes(data[offset + 3..offset + RECORD_HEADER_LEN].try_into().expect("static length"));
+            self.update_index(Location {
+                key: data[offset],
+                seq,
+                page_seq,
+                addr: page_addr(page) + offset as u32,
+                len,
+            });
+            offset = end;
+        }
+        offset
+    }
+
+    fn update_index(&mut self, location: Location) {
+        self.next_seq = self.next_seq.max(location.seq + 1);
+        match self.index.iter_mut().find(|l| l.key == location.key) {
+            Some(l) => {
+                if (location.seq, location.page_seq) > (l.seq, l.page_seq) {
+                    *l = location;
+                }
+            },
+            None => self.index.push(location),
+        }
+    }
+
+    fn append(&mut self, key: u8, seq: u32, data: &[u8]) -> Result<(), FlashErr> {
+        let mut record = Vec::with_capacity(RECORD_HEADER_LEN + data.len() + CRC_LEN);
+        record.push(key);
+        record.extend_from_slice(&(data.len() as u16).to_le_bytes());
+        record.extend_from_slice(&seq.to_le_bytes());
+        record.extend_from_slice(data);
+        record.extend_from_slice(&crc32(&record).to_le_bytes());
+
+        let addr = page_addr(self.head) + self.offset as u32;
+        self.program(addr, &record);
+        self.offset += record.len();
+        let mut written = vec![0u8; record.len()];
+        self.flash.read(addr, &mut written);
+        if written != record {
+            return Err(FlashErr::WriteNotMatch)
+        }
+        self.update_index(Location {
+            key,
+            seq,
+            page_seq: self.head_seq,
+            addr,
+            len: data.len() as u16,
+        });
+        Ok(())
+    }
+
+    /// Program data that could cross flash page boundary
+    fn program(&mut self, mut addr: u32, mut data: &[u8]) {
+        while !data.is_empty() {
+            let len = core::cmp::min(data.len(), PAGE_SIZE - addr as usize % PAGE_SIZE);
+            self.flash.program(addr, &data[..len]);
+            addr += len as u32;
+            data = &data[len..];
+        }
+    }
+
+    /// Erase page and make it the one being written
+    fn open_page(&mut self, page: usize, page_seq: u32) -> Result<(), FlashErr> {
+        self.erase_page(page)?;
+        let mut header = [0u8; PAGE_HEADER_LEN];
+        header[..PAGE_MAGIC.len()].copy_from_slice(&PAGE_MAGIC);
+        header[PAGE_MAGIC.len()..].copy_from_slice(&page_seq.to_le_bytes());
+        self.program(page_addr(page), &header);
+        self.head = page;
+        self.head_seq = page_seq;
+        self.offset = PAGE_HEADER_LEN;
+        Ok(())
+    }
+
+    fn next_page(&mut self) -> Result<(), FlashErr> {
+        self.open_page((self.head + 1) % STORE_PAGES, self.head_seq + 1)?;
+        self.clear_next()
+    }
+
+    /// Move current records out of page after the one being written, and
+    /// erase it. Records of one page always fit freshly opened page.
+    fn clear_next(&mut self) -> Result<(), FlashErr> {
+        let next = (self.head + 1) % STORE_PAGES;
+        let start = page_addr(next);
+        if self.page_blank(next)? {
+            return Ok(())
+        }
+        let mut page = [0u8; STORE_PAGE_SIZE];
+        self.flash.read_data(start, &mut page)?;
+        let live: Vec<Location> = self.index
+            .iter()
+            .filter(|l| l.addr >= start && l.addr < start + STORE_PAGE_SIZE as u32)
+            .copied()
+            .collect();
+        for location in live.into_iter() {
+            let payload = (location.addr - start) as usize + RECORD_HEADER_LEN;
+            let data = page[payload..payload + location.len as usize].to_vec();
+            self.append(location.key, location.seq, &data)?;
+        }
+        self.erase_page(next)
+    }
+
+    fn page_blank(&mut self, page: usize) -> Result<bool, FlashErr> {
+        let mut data = [0u8; STORE_PAGE_SIZE];
+        self.flash.read_data(page_addr(page), &mut data)?;
+        Ok(data.iter().all(|b| *b == ERASED))
+    }
+
+    fn erase_page(&mut self, page: usize) -> Result<(), FlashErr> {
+        self.flash.erase_data(page_addr(page), FLASH_PAGES_PER_PAGE as u32);
+        if self.page_blank(page)? {
+            Ok(())
+        } else {
+            Err(FlashErr::NotErased)
+        }
+    }
+}
+
+fn page_addr(page: usize) -> u32 {
+    RECORD_STORE_ADDR + (page * STORE_PAGE_SIZE) as u32
+}
+
+/// Sequence number of formatted page
+fn page_seq(data: &[u8; STORE_PAGE_SIZE]) -> Option<u32> {
+    if data[..PAGE_MAGIC.len()] == PAGE_MAGIC {
+        Some(u32::from_le_bytes(data[PAGE_MAGIC.len()..PAGE_HEADER_LEN].try_into().expect("static length")))
+    } else {
+        None
+    }
+}
+
+/// CRC-32 (IEEE 802.3)
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data.iter() {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::flash_device::{CutFlash, RamFlash};
+
+    /// Flash pages up to the end of record store region
+    const FLASH_PAGES: usize = RECORD_STORE_ADDR as usize / PAGE_SIZE + RECORD_STORE_PAGES;
+
+    fn mount(flash: &mut RamFlash) -> RecordStore<&mut RamFlash> {
+        RecordStore::mount(flash).unwrap()
+    }
+
+    #[test]
+    fn blank_flash_is_formatted() {
+        let mut flash = RamFlash::new(FLASH_PAGES);
+        let mut store = mount(&mut flash);
+        assert_eq!(store.read(RecordKey::Accounts), None);
+        drop(store);
+        assert_eq!(flash.memory()[RECORD_STORE_ADDR as usize..][..PAGE_MAGIC.len()], PAGE_MAGIC);
+    }
+
+    #[test]
+    fn records_survive_remount() {
+        let mut flash = RamFlash::new(FLASH_PAGES);
+        let mut store = mount(&mut flash);
+        store.write(RecordKey::Accounts, &[1, 2, 3]).unwrap();
+        store.write(RecordKey::PinFailures, &[4]).unwrap();
+        store.write(RecordKey::Accounts, &[5, 6]).unwrap();
+        drop(store);
+
+        let mut store = mount(&mut flash);
+        assert_eq!(store.read(RecordKey::Accounts), Some(vec![5, 6]));
+        assert_eq!(store.read(RecordKey::PinFailures), Some(vec![4]));
+        assert_eq!(store.read(RecordKey::Companions), None);
+    }
+
+    #[test]
+    fn removed_record_stays_removed() {
+        let mut flash = RamFlash::new(FLASH_PAGES);
+        let mut store = mount(&mut flash);
+        store.write(RecordKey::Passphrase, &[7; 20]).unwrap();
+        store.remove(RecordKey::Passphrase).unwrap();
+        assert_eq!(store.read(RecordKey::Passphrase), None);
+        drop(store);
+        assert_eq!(mount(&mut flash).read(RecordKey::Passphrase), None);
+    }
+
+    #[test]
+    fn longest_record_fits_across_flash_pages() {
+        let mut flash = RamFlash::new(FLASH_PAGES);
+        let mut store = mount(&mut flash);
+        let long: Vec<u8> = (0..MAX_RECORD_LEN).map(|i| i as u8).collect();
+        store.write(RecordKey::PinFailures, &[1]).unwrap();
+        store.write(RecordKey::Accounts, &long).unwrap();
+        assert_eq!(store.write(RecordKey::Accounts, &[0; MAX_RECORD_LEN + 1]), Err(FlashErr::RecordTooLong));
+        drop(store);
+
+        let mut store = mount(&mut flash);
+        assert_eq!(store.read(RecordKey::Accounts), Some(long));
+        assert_eq!(store.read(RecordKey::PinFailures), Some(vec![1]));
+    }
+
+    #[test]
+    fn writes_wear_all_pages_and_keep_other_records() {
+        let mut flash = RamFlash::new(FLASH_PAGES);
+        let mut store = mount(&mut flash);
+        let accounts = [3u8; 256];
+        store.write(RecordKey::Accounts, &accounts).unwrap();
+        store.write(RecordKey::Companions, &[1; 34]).unwrap();
+        // enough writes to go around the region several times
+        for i in 0..5000u32 {
+            store.write(RecordKey::PinFailures, &[i as u8]).unwrap();
+        }
+        assert_eq!(store.read(RecordKey::Accounts), Some(accounts.to_vec()));
+        drop(store);
+
+        let mut store = mount(&mut flash);
+        assert_eq!(store.read(RecordKey::Accounts), Some(accounts.to_vec()));
+        assert_eq!(store.read(RecordKey::Companions), Some(vec![1; 34]));
+        assert_eq!(store.read(RecordKey::PinFailures), Some(vec![(4999u32 & 0xff) as u8]));
+        drop(store);
+        let mut page = [0u8; STORE_PAGE_SIZE];
+        let blank = (0..STORE_PAGES)
+            .filter(|i| {
+                flash.read_data(page_addr(*i), &mut page).unwrap();
+                page_seq(&page).is_none()
+            })
+            .count();
+        // only page after the one being written is kept erased
+        assert_eq!(blank, 1);
+    }
+
+    #[test]
+    fn torn_record_is_skipped() {
+        let mut flash = RamFlash::new(FLASH_PAGES);
+        let mut store = mount(&mut flash);
+        store.write(RecordKey::PinRecord, &[1; 100]).unwrap();
+        store.write(RecordKey::PinRecord, &[2; 100]).unwrap();
+        let torn = store.index[0].addr as usize + RECORD_HEADER_LEN + 50;
+        drop(store);
+        flash.memory()[torn] = 0;
+
+        let mut store = mount(&mut flash);
+        assert_eq!(store.read(RecordKey::PinRecord), Some(vec![1; 100]));
+        // space after torn record is not reused
+        store.write(RecordKey::PinRecord, &[3; 100]).unwrap();
+        drop(store);
+        assert_eq!(mount(&mut flash).read(RecordKey::PinRecord), Some(vec![3; 100]));
+    }
+
+    #[test]
+    fn clear_forgets_everything() {
+        let mut flash = RamFlash::new(FLASH_PAGES);
+        let mut store = mount(&mut flash);
+        for i in 0..200u8 {
+            store.write(RecordKey::Settings, &[i; 40]).unwrap();
+        }
+        store.write(RecordKey::Accounts, &[1]).unwrap();
+        store.clear().unwrap();
+        assert_eq!(store.read(RecordKey::Accounts), None);
+        store.write(RecordKey::Companions, &[2]).unwrap();
+        drop(store);
+
+        let mut store = mount(&mut flash);
+        assert_eq!(store.read(RecordKey::Accounts), None);
+        assert_eq!(store.read(RecordKey::Settings), None);
+        assert_eq!(store.read(RecordKey::Companions), Some(vec![2]));
+    }
+
+    /// Power cut at any point of writing keeps other records, and the
+    /// record being written has either old or new value
+    #[test]
+    fn power_cut_keeps_records() {
+        let accounts = [9u8; 256];
+        let mut cut = 0;
+        loop {
+            let mut store = RecordStore::mount(CutFlash::new(RamFlash::new(FLASH_PAGES))).unwrap();
+            store.write(RecordKey::Accounts, &accounts).unwrap();
+            store.write(RecordKey::Companions, &[1; 34]).unwrap();
+            store.flash().ops_left = cut;
+            let mut written = 0u32;
+            let completed = (1..=1500u32).all(|i| {
+                let ok = store.write(RecordKey::PinFailures, &i.to_le_bytes()).is_ok();
+                if ok {
+                    written = i;
+                }
+                ok
+            });
+            if completed {
+                break
+            }
+
+            let mut flash = RamFlash::new(FLASH_PAGES);
+            flash.memory().copy_from_slice(store.flash().flash.memory());
+            let mut store = mount(&mut flash);
+            assert_eq!(store.read(RecordKey::Accounts), Some(accounts.to_vec()), "cut {cut}");
+            assert_eq!(store.read(RecordKey::Companions), Some(vec![1; 34]), "cut {cut}");
+            let failures = store.read(RecordKey::PinFailures).map(|f| u32::from_le_bytes(f.try_into().unwrap()));
+            assert!(failures == Some(written) || failures == Some(written + 1) || (written == 0 && failures.is_none()), "cut {cut}");
+            // store keeps working after cut
+            store.write(RecordKey::PinFailures, &[0; 4]).unwrap();
+            drop(store);
+            assert_eq!(mount(&mut flash).read(RecordKey::Accounts), Some(accounts.to_vec()), "cut {cut}");
+            cut += 7;
+        }
+    }
+}
@@ -1,7 +1,8 @@
 //! Operations with AES GCM keys by security element.
 
 use alloc::vec::Vec;
-use core::ptr::addr_of;
+use core::ptr::{addr_of, addr_of_mut};
+use zeroize::{Zeroize, Zeroizing};
 
 use efm32pg23_fix::Peripherals;
 
@@ -11,16 +12,17 @@ use crate::peripherals::se_command::{
     se_command_aes_gsm_decrypt, DataTransfer, RxError, SeCommand, SE_COMMAND_AES_GCM_ENCRYPT,
     SE_COMMAND_CREATE_KEY, SE_DATATRANSFER_REALIGN, SE_DATATRANSFER_STOP,
 };
+use crate::devices::se_rng::random_with_length;
 use crate::in_free;
 
 pub const KEY_META_LEN: usize = 8;
 
 pub const KEY_META: [u8; KEY_META_LEN] = [0; KEY_META_LEN];
 
+/// Length of key wrapped by security element
 pub const KEY_BUFFER_LEN: usize = 60;
 
-pub static mut KEY_BUFFER: [u8; KEY_BUFFER_LEN] = [0; KEY_BUFFER_LEN];
-
+/// Record version, as little-endian `u32`, is authenticated with every record
 pub const AAD_LEN: usize = 4;
 
 pub const IV_LEN: usize = 12;
@@ -31,28 +33,97 @@ pub const KEYSPEC: u32 = 0b00001001000000000000000000100000;
 
 pub const TAG_LEN: usize = 16;
 
-pub const ENCODED_LEN: usize = 1 + SECRET_MAX_LEN + TAG_LEN + KEY_BUFFER_LEN;
+/// Length of PIN-derived value authenticated together with PIN-bound record
+pub const BINDING_LEN: usize = 32;
+
+/// Record version, secret length, IV, encrypted secret, tag and wrapped key
+pub const ENCODED_LEN: usize = 2 + IV_LEN + SECRET_MAX_LEN + TAG_LEN + KEY_BUFFER_LEN;
+
+/// Record of storage version 1: secret length, encrypted secret, tag and
+/// wrapped key, encrypted with zero IV and AAD
+pub const LEGACY_ENCODED_LEN: usize = 1 + SECRET_MAX_LEN + TAG_LEN + KEY_BUFFER_LEN;
+
+/// Record encrypted with zero IV, laid out anew from storage version 1
+pub const RECORD_LEGACY: u8 = 0;
+
+/// Record encrypted with random IV
+pub const RECORD_RANDOM_IV: u8 = 1;
+
+/// Record encrypted with random IV and bound to PIN
+pub const RECORD_PIN_BOUND: u8 = 2;
+
+const LEN_OFFSET: usize = 1;
+const IV_OFFSET: usize = 2;
+const DATA_OFFSET: usize = IV_OFFSET + IV_LEN;
+const TAG_OFFSET: usize = DATA_OFFSET + SECRET_MAX_LEN;
+const KEY_OFFSET: usize = TAG_OFFSET + TAG_LEN;
 
 pub struct Protected(pub [u8; ENCODED_LEN]);
 
+impl Protected {
+    /// Record of storage version 1, keeping its encryption
+    pub fn from_legacy(legacy: &[u8; LEGACY_ENCODED_LEN]) -> Self {
+        let mut protected = [0u8; ENCODED_LEN];
+        protected[0] = RECORD_LEGACY;
+        protected[LEN_OFFSET] = legacy[0];
+        protected[DATA_OFFSET..].copy_from_slice(&legacy[1..]);
+        Protected{ 0: protected }
+    }
+
+    /// One of `RECORD_*` versions
+    pub fn version(&self) -> u8 {
+        self.0[0]
+    }
+
+    /// Length of encrypted secret
+    pub fn secret_len(&self) -> usize {
+        self.0[LEN_OFFSET] as usize
+    }
+
+    /// Erased flash or garbage could not be a record
+    pub fn is_valid(&self) -> bool {
+        self.version() <= RECORD_PIN_BOUND && self.secret_len() <= SECRET_MAX_LEN
+    }
+}
+
 pub struct ProtectedPair {
     pub protected: Protected, 
     pub public: Public,
 }
 
-pub fn encode_entropy(e: &[u8]) -> Protected {
+/// AAD of record: its version, followed by PIN binding if record is bound
+fn record_aad(version: u8, binding: Option<&[u8; BINDING_LEN]>) -> Vec<u8> {
+    let mut aad = Vec::with_capacity(AAD_LEN + BINDING_LEN);
+    aad.extend_from_slice(&(version as u32).to_le_bytes());
+    if let Some(binding) = binding {
+        aad.extend_from_slice(binding);
+    }
+    aad
+}
+
+/// Encrypt secret with fresh key and random IV; with `binding`, record
+/// decrypts only with the same binding
+pub fn encode_entropy(e: &[u8], binding: Option<&[u8; BINDING_LEN]>) -> Protected {
     let mut protected = [0u8; ENCODED_LEN];
 
     let len = e.len();
+    let version = if binding.is_some() { RECORD_PIN_BOUND } else { RECORD_RANDOM_IV };
+    let iv: [u8; IV_LEN] = random_with_length(IV_LEN)
+        .expect("security element rng failed")
+        .try_into()
+        .expect("static length");
+    let aad = record_aad(version, binding);
+    let mut key = [0u8; KEY_BUFFER_LEN];
     // encoding entropy
     in_free(|peripherals| {
         let out = if len != 0 {
-            create_key(peripherals).unwrap();
+            create_key(peripherals, &mut key).unwrap();
             aes_gcm_encrypt(
                 peripherals,
-                [0; AAD_LEN],
-                [0; IV_LEN],
-                e.to_vec(),
+                &key,
+                &aad,
+                iv,
+                e,
             ).unwrap()
         } else {
             Out{
@@ -62,39 +133,52 @@ pub fn encode_entropy(e: &[u8]) -> Protected {
             }
         };
 
-        protected[0] = out.len as u8;
-        protected[1..1+SECRET_MAX_LEN].copy_from_slice(&out.data);
-        protected[1+SECRET_MAX_LEN..1+SECRET_MAX_LEN+TAG_LEN].copy_from_slice(&out.tag);
-        protected[1+SECRET_MAX_LEN+TAG_LEN..].copy_from_slice( unsafe { &KEY_BUFFER });
+        protected[0] = version;
+        protected[LEN_OFFSET] = out.len as u8;
+        protected[IV_OFFSET..DATA_OFFSET].copy_from_slice(&iv);
+        protected[DATA_OFFSET..TAG_OFFSET].copy_from_slice(&out.data);
+        protected[TAG_OFFSET..KEY_OFFSET].copy_from_slice(&out.tag);
+        protected[KEY_OFFSET..].copy_from_slice(&key);
     });
+    key.zeroize();
 
     Protected{ 0: protected }
 }
 
-pub fn decode_entropy(protected: &Protected) -> Vec<u8> {
+/// Decrypt secret; `None` if record is PIN-bound and `binding` is missing
+/// or wrong, or if record does not authenticate
+pub fn decode_entropy(protected: &Protected, binding: Option<&[u8; BINDING_LEN]>) -> Option<Zeroizing<Vec<u8>>> {
     let recovered_out = Out {
-        data: protected.0[1..1 + SECRET_MAX_LEN].try_into().expect("static length"),
-        len: protected.0[0] as usize,
-        tag: protected.0[1+SECRET_MAX_LEN..1 + SECRET_MAX_LEN + TAG_LEN].try_into().expect("static length"),
+        data: protected.0[DATA_OFFSET..TAG_OFFSET].try_into().expect("static length"),
+        len: protected.secret_len(),
+        tag: protected.0[TAG_OFFSET..KEY_OFFSET].try_into().expect("static length"),
+    };
+    if recovered_out.len == 0 {
+        return Some(Zeroizing::new(Vec::new()))
+    }
+    let binding = match protected.version() {
+        RECORD_PIN_BOUND => Some(binding?),
+        _ => None,
     };
-    unsafe { KEY_BUFFER = protected.0[1 + SECRET_MAX_LEN + TAG_LEN..].try_into().expect("static length"); }
+    let aad = record_aad(protected.version(), binding);
+    let iv: [u8; IV_LEN] = protected.0[IV_OFFSET..DATA_OFFSET].try_into().expect("static length");
+    let key: [u8; KEY_BUFFER_LEN] = protected.0[KEY_OFFSET..].try_into().expect("static length");
 
     let mut entropy = None;
-    if recovered_out.len != 0 {
-        in_free(|peripherals| {
-            let out = aes_gcm_decrypt(
-                peripherals,
-                &recovered_out,
-                [0u8; AAD_LEN],
-                [0u8; IV_LEN],
-            ).unwrap();
-            entropy = Some(out.data[..out.len].to_vec());
-        });
-    }
-    entropy.unwrap()
+    in_free(|peripherals| {
+        entropy = aes_gcm_decrypt(
+            peripherals,
+            &key,
+            &recovered_out,
+            &aad,
+            iv,
+        ).ok().map(|out| Zeroizing::new(out.data[..out.len].to_vec()));
+    });
+    entropy
 }
 
-pub fn create_key(peripherals: &mut Peripherals) -> Result<(), RxError> {
+/// Create key in security element and write it, wrapped, in `key`
+pub fn create_key(peripherals: &mut Peripherals, key: &mut [u8; KEY_BUFFER_LEN]) -> Result<(), RxError> {
     let command_word = SE_COMMAND_CREATE_KEY;
 
     let key_meta = KEY_META;
@@ -108,7 +192,7 @@ pub fn create_key(peripherals: &mut Peripherals) -> Result<(), RxError> {
     let data_in = addr_of!(data_transfer_in0) as u32;
 
     let data_transfer_out0 = DataTransfer {
-        data: unsafe { addr_of!(KEY_BUFFER[0]) as u32 },
+        data: addr_of_mut!(key[0]) as u32,
         next: SE_DATATRANSFER_STOP,
         length: KEY_BUFFER_LEN as u32 | SE_DATATRANSFER_REALIGN,
     };
@@ -129,9 +213,10 @@ pub fn create_key(peripherals: &mut Peripherals) -> Result<(), RxError> {
 
 pub fn aes_gcm_encrypt(
     peripherals: &mut Peripherals,
-    aad: [u8; AAD_LEN],
+    key: &[u8; KEY_BUFFER_LEN],
+    aad: &[u8],
     iv: [u8; IV_LEN],
-    secret: Vec<u8>,
+    secret: &[u8],
 ) -> Result<Out, RxError> {
     let command_word = SE_COMMAND_AES_GCM_ENCRYPT;
 
@@ -145,7 +230,7 @@ pub fn aes_gcm_encrypt(
     let data_transfer_in3 = DataTransfer {
         data: addr_of!(aad[0]) as u32,
         next: addr_of!(data_transfer_in4) as u32,
-        length: AAD_LEN as u32 | SE_DATATRANSFER_REALIGN,
+        length: aad.len() as u32 | SE_DATATRANSFER_REALIGN,
     };
     let data_transfer_in2 = DataTransfer {
         data: addr_of!(iv[0]) as u32,
@@ -153,7 +238,7 @@ pub fn aes_gcm_encrypt(
         length: IV_LEN as u32 | SE_DATATRANSFER_REALIGN,
     };
     let data_transfer_in1 = DataTransfer {
-        data: unsafe { addr_of!(KEY_BUFFER[0]) as u32 },
+        data: addr_of!(key[0]) as u32,
         next: addr_of!(data_transfer_in2) as u32,
         length: KEY_BUFFER_LEN as u32 | SE_DATATRANSFER_REALIGN,
     };
@@ -185,7 +270,7 @@ pub fn aes_gcm_encrypt(
 
     let data_out = addr_of!(data_transfer_out0) as u32;
 
-    let parameters = [KEYSPEC, AAD_LEN as u32, len as u32];
+    let parameters = [KEYSPEC, aad.len() as u32, len as u32];
 
     let se_command = SeCommand {
         command_word,
@@ -205,8 +290,9 @@ pub fn aes_gcm_encrypt(
 
 pub fn aes_gcm_decrypt(
     peripherals: &mut Peripherals,
+    key: &[u8; KEY_BUFFER_LEN],
     out_encoded: &Out,
-    aad: [u8; AAD_LEN],
+    aad: &[u8],
     iv: [u8; IV_LEN],
 ) -> Result<Out, RxError> {
     let encoded = out_encoded.data;
@@ -229,7 +315,7 @@ pub fn aes_gcm_decrypt(
     let data_transfer_in3 = DataTransfer {
         data: addr_of!(aad[0]) as u32,
         next: addr_of!(data_transfer_in4) as u32,
-        length: AAD_LEN as u32 | SE_DATATRANSFER_REALIGN,
+        length: aad.len() as u32 | SE_DATATRANSFER_REALIGN,
     };
     let data_transfer_in2 = DataTransfer {
         data: addr_of!(iv[0]) as u32,
@@ -237,7 +323,7 @@ pub fn aes_gcm_decrypt(
         length: IV_LEN as u32 | SE_DATATRANSFER_REALIGN,
     };
     let data_transfer_in1 = DataTransfer {
-        data: unsafe { addr_of!(KEY_BUFFER[0]) as u32 },
+        data: addr_of!(key[0]) as u32,
         next: addr_of!(data_transfer_in2) as u32,
         length: KEY_BUFFER_LEN as u32 | SE_DATATRANSFER_REALIGN,
     };
@@ -249,7 +335,7 @@ pub fn aes_gcm_decrypt(
 
     let data_in = addr_of!(data_transfer_in0) as u32;
 
-    let decoded: [u8; SECRET_MAX_LEN] = [0; SECRET_MAX_LEN];
+    let mut decoded: [u8; SECRET_MAX_LEN] = [0; SECRET_MAX_LEN];
 
     let data_transfer_out0 = DataTransfer {
         data: addr_of!(decoded[0]) as u32,
@@ -259,7 +345,7 @@ pub fn aes_gcm_decrypt(
 
     let data_out = addr_of!(data_transfer_out0) as u32;
 
-    let parameters = [KEYSPEC, AAD_LEN as u32, len as u32];
+    let parameters = [KEYSPEC, aad.len() as u32, len as u32];
 
     let se_command = SeCommand {
         command_word,
@@ -268,18 +354,24 @@ pub fn aes_gcm_decrypt(
         parameters: parameters.as_slice(),
     };
 
-    se_command.execute(peripherals)?;
-
-    Ok(Out {
+    let result = se_command.execute(peripherals).map(|_| Out {
         data: decoded,
         len,
         tag,
-    })
+    });
+    decoded.zeroize();
+    result
 }
 
-#[derive(Debug)]
+/// Encrypted or decrypted secret, wiped when dropped
 pub struct Out {
     pub data: [u8; SECRET_MAX_LEN],
     pub len: usize,
     pub tag: [u8; TAG_LEN],
 }
+
+impl Drop for Out {
+    fn drop(&mut self) {
+        self.data.zeroize();
+    }
+}
@@ -0,0 +1,212 @@
+//! Hashing, HMAC and key derivation by security element.
+//!
+//! Keys are passed to security element in plaintext, command layout follows
+//! `sl_se_hash`, `sl_se_hmac`, `sl_se_derive_key_pbkdf2` and
+//! `sl_se_derive_key_hkdf` of the SDK.
+
+use alloc::{vec, vec::Vec};
+use core::ptr::addr_of;
+use zeroize::Zeroizing;
+
+use efm32pg23_fix::Peripherals;
+
+use crate::peripherals::se_command::{
+    se_command_hash, se_command_hkdf, se_command_hmac, se_command_pbkdf2_hmac,
+    se_keyspec_plaintext, DataTransfer, RxError, SeCommand, SeHash, SE_DATATRANSFER_REALIGN,
+    SE_DATATRANSFER_STOP,
+};
+
+/// Digest of `message`
+pub fn hash(peripherals: &mut Peripherals, hash: SeHash, message: &[u8]) -> Result<Vec<u8>, RxError> {
+    let command_word = se_command_hash(hash);
+
+    let data_transfer_in0 = DataTransfer {
+        data: message.as_ptr() as u32,
+        next: SE_DATATRANSFER_STOP,
+        length: message.len() as u32 | SE_DATATRANSFER_REALIGN,
+    };
+
+    let data_in = addr_of!(data_transfer_in0) as u32;
+
+    let mut digest = vec![0u8; hash.digest_len()];
+
+    let data_transfer_out0 = DataTransfer {
+        data: digest.as_mut_ptr() as u32,
+        next: SE_DATATRANSFER_STOP,
+        length: digest.len() as u32 | SE_DATATRANSFER_REALIGN,
+    };
+
+    let data_out = addr_of!(data_transfer_out0) as u32;
+
+    let parameters = [message.len() as u32];
+
+    let se_command = SeCommand {
+        command_word,
+        data_in,
+        data_out,
+        parameters: parameters.as_slice(),
+    };
+
+    se_command.execute(peripherals)?;
+
+    Ok(digest)
+}
+
+/// HMAC of `message` under `key`
+pub fn hmac(
+    peripherals: &mut Peripherals,
+    hash: SeHash,
+    key: &[u8],
+    message: &[u8],
+) -> Result<Zeroizing<Vec<u8>>, RxError> {
+    let command_word = se_command_hmac(hash);
+
+    let data_transfer_in1 = DataTransfer {
+        data: message.as_ptr() as u32,
+        next: SE_DATATRANSFER_STOP,
+        length: message.len() as u32 | SE_DATATRANSFER_REALIGN,
+    };
+    let data_transfer_in0 = DataTransfer {
+        data: key.as_ptr() as u32,
+        next: addr_of!(data_transfer_in1) as u32,
+        length: key.len() as u32 | SE_DATATRANSFER_REALIGN,
+    };
+
+    let data_in = addr_of!(data_transfer_in0) as u32;
+
+    let mut mac = Zeroizing::new(vec![0u8; hash.digest_len()]);
+
+    let data_transfer_out0 = DataTransfer {
+        data: mac.as_mut_ptr() as u32,
+        next: SE_DATATRANSFER_STOP,
+        length: mac.len() as u32 | SE_DATATRANSFER_REALIGN,
+    };
+
+    let data_out = addr_of!(data_transfer_out0) as u32;
+
+    let parameters = [se_keyspec_plaintext(key.len()), message.len() as u32];
+
+    let se_command = SeCommand {
+        command_word,
+        data_in,
+        data_out,
+        parameters: parameters.as_slice(),
+    };
+
+    se_command.execute(peripherals)?;
+
+    Ok(mac)
+}
+
+/// Key of `out_len` bytes derived from `password` and `salt` by PBKDF2 with
+/// HMAC of given algorithm
+pub fn pbkdf2_hmac(
+    peripherals: &mut Peripherals,
+    hash: SeHash,
+    password: &[u8],
+    salt: &[u8],
+    iterations: u32,
+    out_len: usize,
+) -> Result<Zeroizing<Vec<u8>>, RxError> {
+    let command_word = se_command_pbkdf2_hmac(hash);
+
+    let data_transfer_in1 = DataTransfer {
+        data: salt.as_ptr() as u32,
+        next: SE_DATATRANSFER_STOP,
+        length: salt.len() as u32 | SE_DATATRANSFER_REALIGN,
+    };
+    let data_transfer_in0 = DataTransfer {
+        data: password.as_ptr() as u32,
+        next: addr_of!(data_transfer_in1) as u32,
+        length: password.len() as u32 | SE_DATATRANSFER_REALIGN,
+    };
+
+    let data_in = addr_of!(data_transfer_in0) as u32;
+
+    let mut key = Zeroizing::new(vec![0u8; out_len]);
+
+    let data_transfer_out0 = DataTransfer {
+        data: key.as_mut_ptr() as u32,
+        next: SE_DATATRANSFER_STOP,
+        length: out_len as u32 | SE_DATATRANSFER_REALIGN,
+    };
+
+    let data_out = addr_of!(data_transfer_out0) as u32;
+
+    let parameters = [
+        se_keyspec_plaintext(password.len()),
+        se_keyspec_plaintext(out_len),
+        salt.len() as u32,
+        iterations,
+    ];
+
+    let se_command = SeCommand {
+        command_word,
+        data_in,
+        data_out,
+        parameters: parameters.as_slice(),
+    };
+
+    se_command.execute(peripherals)?;
+
+    Ok(key)
+}
+
+/// Key of `out_len` bytes derived from input `key` by HKDF with given
+/// algorithm
+pub fn hkdf(
+    peripherals: &mut Peripherals,
+    hash: SeHash,
+    key: &[u8],
+    salt: &[u8],
+    info: &[u8],
+    out_len: usize,
+) -> Result<Zeroizing<Vec<u8>>, RxError> {
+    let command_word = se_command_hkdf(hash);
+
+    let data_transfer_in2 = DataTransfer {
+        data: info.as_ptr() as u32,
+        next: SE_DATATRANSFER_STOP,
+        length: info.len() as u32 | SE_DATATRANSFER_REALIGN,
+    };
+    let data_transfer_in1 = DataTransfer {
+        data: salt.as_ptr() as u32,
+        next: addr_of!(data_transfer_in2) as u32,
+        length: salt.len() as u32 | SE_DATATRANSFER_REALIGN,
+    };
+    let data_transfer_in0 = DataTransfer {
+        data: key.as_ptr() as u32,
+        next: addr_of!(data_transfer_in1) as u32,
+        length: key.len() as u32 | SE_DATATRANSFER_REALIGN,
+    };
+
+    let data_in = addr_of!(data_transfer_in0) as u32;
+
+    let mut derived = Zeroizing::new(vec![0u8; out_len]);
+
+    let data_transfer_out0 = DataTransfer {
+        data: derived.as_mut_ptr() as u32,
+        next: SE_DATATRANSFER_STOP,
+        length: out_len as u32 | SE_DATATRANSFER_REALIGN,
+    };
+
+    let data_out = addr_of!(data_transfer_out0) as u32;
+
+    let parameters = [
+        se_keyspec_plaintext(key.len()),
+        se_keyspec_plaintext(out_len),
+        salt.len() as u32,
+        info.len() as u32,
+    ];
+
+    let se_command = SeCommand {
+        command_word,
+        data_in,
+        data_out,
+        parameters: parameters.as_slice(),
+    };
+
+    se_command.execute(peripherals)?;
+
+    Ok(derived)
+}
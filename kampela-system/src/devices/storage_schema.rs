@@ -0,0 +1,295 @@
+//! Version of flash storage layout, and migrations of older layouts run on boot.
+
+use crate::devices::flash::{add_protected_record_version, move_records_to_store, split_single_entropy_copy, FlashErr, PAGE_SIZE};
+use crate::devices::flash_device::FlashDevice;
+use crate::devices::record_store::crc32;
+
+/// Storage layout of this firmware:
+///
+/// - 0: single copy of encoded entropy on page 0 without CRC, no header
+/// - 1: two copies of encoded entropy with sequence numbers and CRC, record
+/// store region
+/// - 2: SE-wrapped secrets carry record version and IV
+/// - 3: records other than encoded entropy are kept in record store
+pub const STORAGE_VERSION: u16 = 3;
+
+/// Header is kept on the page after second copy of encoded entropy; factory
+/// reset does not erase it, as it describes layout, not user data
+const HEADER_ADDR: u32 = 7 * PAGE_SIZE as u32;
+
+const HEADER_MAGIC: [u8; 4] = *b"KSTO";
+
+/// Magic, version and CRC
+const HEADER_LEN: usize = HEADER_MAGIC.len() + 2 + 4;
+
+/// Upgrade of storage from version `from` to the next one. Migration must be
+/// safe to run again on its own output, as power could be cut before the new
+/// version is stored.
+struct Migration<F: FlashDevice> {
+    from: u16,
+    run: fn(&mut F) -> Result<(), FlashErr>,
+}
+
+/// Migrations in order, one for each version older than `STORAGE_VERSION`
+fn migrations<F: FlashDevice>() -> [Migration<F>; STORAGE_VERSION as usize] {
+    [
+        Migration{from: 0, run: split_single_entropy_copy::<F>},
+        Migration{from: 1, run: add_protected_record_version::<F>},
+        Migration{from: 2, run: move_records_to_store::<F>},
+    ]
+}
+
+/// Version of stored layout; storage without valid header is of version 0
+pub fn read_storage_version<F: FlashDevice>(flash: &mut F) -> Result<u16, FlashErr> {
+    let mut data = [0u8; HEADER_LEN];
+    flash.read_data(HEADER_ADDR, &mut data)?;
+    let crc = u32::from_le_bytes(data[HEADER_MAGIC.len() + 2..].try_into().expect("static length"));
+    if data[..HEADER_MAGIC.len()] != HEADER_MAGIC || crc32(&data[..HEADER_MAGIC.len() + 2]) != crc {
+        return Ok(0)
+    }
+    Ok(u16::from_le_bytes(data[HEADER_MAGIC.len()..HEADER_MAGIC.len() + 2].try_into().expect("static length")))
+}
+
+fn store_storage_version<F: FlashDevice>(flash: &mut F, version: u16) -> Result<(), FlashErr> {
+    let mut data = [0u8; HEADER_LEN];
+    data[..HEADER_MAGIC.len()].copy_from_slice(&HEADER_MAGIC);
+    data[HEADER_MAGIC.len()..HEADER_MAGIC.len() + 2].copy_from_slice(&version.to_le_bytes());
+    let crc = crc32(&data[..HEADER_MAGIC.len() + 2]);
+    data[HEADER_MAGIC.len() + 2..].copy_from_slice(&crc.to_le_bytes());
+    flash.store_data(HEADER_ADDR, &data)
+}
+
+/// Bring stored layout to `STORAGE_VERSION`, storing version after each
+/// step; layout of newer firmware is left untouched
+pub fn migrate_storage<F: FlashDevice>(flash: &mut F) -> Result<(), FlashErr> {
+    migrate_storage_to(flash, STORAGE_VERSION)
+}
+
+fn migrate_storage_to<F: FlashDevice>(flash: &mut F, target: u16) -> Result<(), FlashErr> {
+    let mut version = read_storage_version(flash)?;
+    if version > STORAGE_VERSION {
+        return Err(FlashErr::UnsupportedVersion)
+    }
+    while version < target {
+        let migration = migrations::<F>()
+            .into_iter()
+            .find(|m| m.from == version)
+            .expect("migration exists for every older version");
+        (migration.run)(flash)?;
+        version += 1;
+        store_storage_version(flash, version)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::flash::{
+        read_accounts, read_companion_keys, read_encoded_entropy, read_encoded_passphrase, read_pin_failures,
+        read_pin_record, COMPANION_KEY_LEN, PIN_SALT_LEN,
+    };
+    use crate::devices::flash_device::{CutFlash, RamFlash};
+    use crate::devices::record_store::{RecordStore, RECORD_STORE_ADDR, RECORD_STORE_PAGES};
+    use crate::devices::se_aes_gcm::{Protected, ENCODED_LEN, LEGACY_ENCODED_LEN, RECORD_RANDOM_IV};
+
+    /// Flash pages up to the end of record store region
+    const FLASH_PAGES: usize = RECORD_STORE_ADDR as usize / PAGE_SIZE + RECORD_STORE_PAGES;
+
+    const ENTROPY_PAGES: [usize; 2] = [0, 6];
+
+    fn page(flash: &mut RamFlash, page: usize) -> &mut [u8] {
+        &mut flash.memory()[page * PAGE_SIZE..(page + 1) * PAGE_SIZE]
+    }
+
+    fn protected(secret_len: u8) -> [u8; ENCODED_LEN] {
+        let mut protected = [0x42u8; ENCODED_LEN];
+        protected[0] = RECORD_RANDOM_IV;
+        protected[1] = secret_len;
+        protected
+    }
+
+    /// Storage of version 2, with each record on its own page
+    fn version_2_flash() -> RamFlash {
+        let mut flash = RamFlash::new(FLASH_PAGES);
+        for (i, p) in ENTROPY_PAGES.iter().enumerate() {
+            page(&mut flash, *p)[..ENCODED_LEN].fill(i as u8 + 1);
+        }
+        let companions = page(&mut flash, 1);
+        companions.fill(0);
+        companions[0] = 2;
+        companions[1..1 + 2 * COMPANION_KEY_LEN].fill(0x33);
+        let accounts = page(&mut flash, 2);
+        accounts.fill(0);
+        accounts[..4].copy_from_slice(&[1, 0, 0, 0]);
+        page(&mut flash, 3)[..ENCODED_LEN].copy_from_slice(&protected(10));
+        let pin = page(&mut flash, 4);
+        pin[..PIN_SALT_LEN].fill(0x55);
+        pin[PIN_SALT_LEN..PIN_SALT_LEN + ENCODED_LEN].copy_from_slice(&protected(32));
+        page(&mut flash, 5)[0] = 3;
+        store_storage_version(&mut flash, 2).unwrap();
+        flash
+    }
+
+    /// Storage of version 3 holding records of `version_2_flash`
+    fn check_version_3(flash: &mut RamFlash) {
+        assert_eq!(read_storage_version(flash).unwrap(), 3);
+        for (i, p) in ENTROPY_PAGES.iter().enumerate() {
+            assert!(page(flash, *p)[..ENCODED_LEN].iter().all(|b| *b == i as u8 + 1));
+        }
+        for p in 1..=5 {
+            assert!(page(flash, p).iter().all(|b| *b == 0xff));
+        }
+        let mut store = RecordStore::mount(flash).unwrap();
+        assert_eq!(read_companion_keys(&mut store), [[0x33; COMPANION_KEY_LEN]; 2]);
+        assert_eq!(read_accounts(&mut store)[..4], [1, 0, 0, 0]);
+        assert_eq!(read_encoded_passphrase(&mut store).unwrap().0, protected(10));
+        let (salt, pin) = read_pin_record(&mut store).unwrap();
+        assert_eq!((salt, pin.0), ([0x55; PIN_SALT_LEN], protected(32)));
+        assert_eq!(read_pin_failures(&mut store), 3);
+    }
+
+    #[test]
+    fn records_moved_to_store() {
+        let mut flash = version_2_flash();
+        migrate_storage(&mut flash).unwrap();
+        check_version_3(&mut flash);
+        // migrated storage is left as is
+        let before = flash.memory().to_vec();
+        migrate_storage(&mut flash).unwrap();
+        assert_eq!(flash.memory(), &before[..]);
+    }
+
+    #[test]
+    fn records_moved_to_store_after_power_cut() {
+        let mut cut = 0;
+        loop {
+            let mut flash = CutFlash::new(version_2_flash());
+            flash.ops_left = cut;
+            if migrate_storage(&mut flash).is_ok() && flash.ops_left != 0 {
+                break
+            }
+            flash.ops_left = usize::MAX;
+            migrate_storage(&mut flash).unwrap();
+            check_version_3(&mut flash.flash);
+            cut += 1;
+        }
+    }
+
+    /// SE-wrapped secret of storage versions 0 and 1: length, then encrypted
+    /// data, tag and key
+    fn legacy_protected(secret_len: u8, fill: u8) -> [u8; LEGACY_ENCODED_LEN] {
+        let mut legacy = [fill; LEGACY_ENCODED_LEN];
+        legacy[0] = secret_len;
+        legacy
+    }
+
+    const LEGACY_ENTROPY: (u8, u8) = (32, 0x21);
+    const LEGACY_PASSPHRASE: (u8, u8) = (10, 0x43);
+    const LEGACY_PIN: (u8, u8) = (32, 0x44);
+
+    /// Storage without header: single copy of entropy, passphrase and PIN
+    /// records of legacy layout
+    fn version_0_flash() -> RamFlash {
+        let mut flash = RamFlash::new(FLASH_PAGES);
+        page(&mut flash, 0)[..LEGACY_ENCODED_LEN].copy_from_slice(&legacy_protected(LEGACY_ENTROPY.0, LEGACY_ENTROPY.1));
+        page(&mut flash, 3)[..LEGACY_ENCODED_LEN].copy_from_slice(&legacy_protected(LEGACY_PASSPHRASE.0, LEGACY_PASSPHRASE.1));
+        let pin = page(&mut flash, 4);
+        pin[..PIN_SALT_LEN].fill(0x55);
+        pin[PIN_SALT_LEN..PIN_SALT_LEN + LEGACY_ENCODED_LEN].copy_from_slice(&legacy_protected(LEGACY_PIN.0, LEGACY_PIN.1));
+        flash
+    }
+
+    fn upgraded(legacy: (u8, u8)) -> [u8; ENCODED_LEN] {
+        Protected::from_legacy(&legacy_protected(legacy.0, legacy.1)).0
+    }
+
+    /// Length of entropy copy of storage version 1: legacy record, sequence
+    /// number and CRC
+    const LEGACY_ENTROPY_RECORD_LEN: usize = LEGACY_ENCODED_LEN + 8;
+
+    #[test]
+    fn version_0_to_1_splits_entropy_copy() {
+        let mut flash = version_0_flash();
+        split_single_entropy_copy(&mut flash).unwrap();
+        let first = page(&mut flash, 0)[..LEGACY_ENTROPY_RECORD_LEN].to_vec();
+        assert_eq!(first[..LEGACY_ENCODED_LEN], legacy_protected(LEGACY_ENTROPY.0, LEGACY_ENTROPY.1));
+        assert_eq!(first[LEGACY_ENCODED_LEN..LEGACY_ENCODED_LEN + 4], 1u32.to_le_bytes());
+        assert_eq!(page(&mut flash, 6)[..LEGACY_ENTROPY_RECORD_LEN], first[..]);
+
+        // run again on its own output
+        let before = flash.memory().to_vec();
+        split_single_entropy_copy(&mut flash).unwrap();
+        assert_eq!(flash.memory(), &before[..]);
+    }
+
+    #[test]
+    fn version_1_to_2_adds_record_version() {
+        let mut flash = version_0_flash();
+        split_single_entropy_copy(&mut flash).unwrap();
+        add_protected_record_version(&mut flash).unwrap();
+        assert_eq!(read_encoded_entropy(&mut flash).unwrap().unwrap().0, upgraded(LEGACY_ENTROPY));
+        assert_eq!(page(&mut flash, 3)[..ENCODED_LEN], upgraded(LEGACY_PASSPHRASE));
+        assert!(page(&mut flash, 4)[..PIN_SALT_LEN].iter().all(|b| *b == 0x55));
+        assert_eq!(page(&mut flash, 4)[PIN_SALT_LEN..PIN_SALT_LEN + ENCODED_LEN], upgraded(LEGACY_PIN));
+        // backup page is cleared
+        assert!(page(&mut flash, 8).iter().all(|b| *b == 0xff));
+
+        // run again on its own output
+        let before = flash.memory().to_vec();
+        add_protected_record_version(&mut flash).unwrap();
+        assert_eq!(flash.memory(), &before[..]);
+    }
+
+    #[test]
+    fn version_0_to_2() {
+        let mut flash = version_0_flash();
+        migrate_storage_to(&mut flash, 2).unwrap();
+        assert_eq!(read_storage_version(&mut flash).unwrap(), 2);
+        assert_eq!(read_encoded_entropy(&mut flash).unwrap().unwrap().0, upgraded(LEGACY_ENTROPY));
+        assert_eq!(page(&mut flash, 3)[..ENCODED_LEN], upgraded(LEGACY_PASSPHRASE));
+        assert_eq!(page(&mut flash, 4)[PIN_SALT_LEN..PIN_SALT_LEN + ENCODED_LEN], upgraded(LEGACY_PIN));
+
+        let before = flash.memory().to_vec();
+        migrate_storage_to(&mut flash, 2).unwrap();
+        assert_eq!(flash.memory(), &before[..]);
+    }
+
+    /// Storage upgraded from `version_0_flash`
+    fn check_upgraded_from_0(flash: &mut RamFlash) {
+        assert_eq!(read_storage_version(flash).unwrap(), STORAGE_VERSION);
+        assert_eq!(read_encoded_entropy(flash).unwrap().unwrap().0, upgraded(LEGACY_ENTROPY));
+        let mut store = RecordStore::mount(flash).unwrap();
+        assert_eq!(read_encoded_passphrase(&mut store).unwrap().0, upgraded(LEGACY_PASSPHRASE));
+        let (salt, pin) = read_pin_record(&mut store).unwrap();
+        assert_eq!((salt, pin.0), ([0x55; PIN_SALT_LEN], upgraded(LEGACY_PIN)));
+        assert_eq!(read_pin_failures(&mut store), 0);
+        assert!(read_companion_keys(&mut store).is_empty());
+    }
+
+    #[test]
+    fn version_0_upgraded_after_power_cut() {
+        let mut cut = 0;
+        loop {
+            let mut flash = CutFlash::new(version_0_flash());
+            flash.ops_left = cut;
+            if migrate_storage(&mut flash).is_ok() && flash.ops_left != 0 {
+                check_upgraded_from_0(&mut flash.flash);
+                break
+            }
+            flash.ops_left = usize::MAX;
+            migrate_storage(&mut flash).unwrap();
+            check_upgraded_from_0(&mut flash.flash);
+            cut += 1;
+        }
+    }
+
+    #[test]
+    fn newer_version_left_untouched() {
+        let mut flash = version_0_flash();
+        store_storage_version(&mut flash, STORAGE_VERSION + 1).unwrap();
+        let before = flash.memory().to_vec();
+        assert_eq!(migrate_storage(&mut flash), Err(FlashErr::UnsupportedVersion));
+        assert_eq!(flash.memory(), &before[..]);
+    }
+}
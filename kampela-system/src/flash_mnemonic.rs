@@ -6,7 +6,7 @@ use core::cell::RefCell;
 //use crate::wordlist::WORDLIST_ENGLISH;
 use mnemonic_external::{AsWordList, Bits11, WordListElement, TOTAL_WORDS, WORD_MAX_LEN, error::ErrorWordList};
 
-use crate::devices::flash::read_data;
+use crate::devices::{flash::SpiNorFlash, flash_device::FlashDevice};
 const WORDLIST_STARTS: [usize; 26] = [
     0, 4, 7, 13, 17, 20, 23,
     26, 28, 29, 30, 31, 33, 36,
@@ -42,7 +42,7 @@ impl FlashWordList {
             }
         }
         let mut c = CachedChunk { chunk_index, cache: [0; 256]};
-        if let Err(_) = read_data(WORDLIST_BASE + chunk_index as u32 * 256, &mut c.cache) {
+        if let Err(_) = SpiNorFlash.read_data(WORDLIST_BASE + chunk_index as u32 * 256, &mut c.cache) {
             panic!("couldn't read from flash wordlist chunk №{}", chunk_index)
         };
         if cached_chunk.len() >= CACHE_SIZE {
@@ -17,8 +17,10 @@ pub mod peripherals;
 pub mod devices;
 pub mod draw;
 pub mod flash_mnemonic;
+pub mod merkleized_metadata;
 pub mod debug_display;
 pub mod parallel;
+pub mod nfc;
 
 use efm32pg23_fix::{CorePeripherals, Peripherals};
 
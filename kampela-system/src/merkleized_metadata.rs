@@ -0,0 +1,775 @@
+//! RFC-0078 merkleized metadata proofs
+//!
+//! Metadata proof is received along with transaction, digest calculated from
+//! it is compared with metadata hash in `CheckMetadataHash` signed extension.
+//! The signed extension is part of signed data, so the chain rejects the
+//! signature if the proof was not made for actual chain metadata.
+//!
+//! Types mirror the ones in `merkleized-metadata` crate and are encoded the
+//! same way, hashes are calculated over their SCALE encoding.
+//!
+//! Once the proof is checked, its leaves are the only type information that
+//! chain has committed to, so transaction is decoded with these leaves only.
+
+use alloc::{borrow::ToOwned, collections::BTreeMap, format, string::String, vec, vec::Vec};
+use core::fmt::{Display, Formatter, Result as FmtResult};
+use parity_scale_codec::{Compact, Decode, Encode};
+use scale_info::{
+    form::PortableForm, Field as ScaleField, Path, Type as ScaleType, TypeDef as ScaleTypeDef,
+    TypeDefArray as ScaleTypeDefArray, TypeDefBitSequence as ScaleTypeDefBitSequence,
+    TypeDefCompact, TypeDefComposite, TypeDefPrimitive, TypeDefSequence, TypeDefTuple,
+    TypeDefVariant, Variant,
+};
+
+pub type Hash = [u8; 32];
+
+/// Identifier of signed extension carrying metadata hash.
+pub const CHECK_METADATA_HASH: &str = "CheckMetadataHash";
+
+/// Types that are not leaves in types tree (primitives, compacts, void and
+/// bit order markers) get ids starting from here.
+pub const SYNTHETIC_TYPES_START: u32 = u32::MAX - 0xff;
+
+/// Offsets of bit order marker types, after the offsets of type references.
+const LSB0_OFFSET: u32 = 22;
+const MSB0_OFFSET: u32 = 23;
+
+#[derive(Clone, Copy, Debug, Decode, Encode, Eq, PartialEq)]
+pub enum TypeRef {
+    #[codec(index = 0)]
+    Bool,
+    #[codec(index = 1)]
+    Char,
+    #[codec(index = 2)]
+    Str,
+    #[codec(index = 3)]
+    U8,
+    #[codec(index = 4)]
+    U16,
+    #[codec(index = 5)]
+    U32,
+    #[codec(index = 6)]
+    U64,
+    #[codec(index = 7)]
+    U128,
+    #[codec(index = 8)]
+    U256,
+    #[codec(index = 9)]
+    I8,
+    #[codec(index = 10)]
+    I16,
+    #[codec(index = 11)]
+    I32,
+    #[codec(index = 12)]
+    I64,
+    #[codec(index = 13)]
+    I128,
+    #[codec(index = 14)]
+    I256,
+    #[codec(index = 15)]
+    CompactU8,
+    #[codec(index = 16)]
+    CompactU16,
+    #[codec(index = 17)]
+    CompactU32,
+    #[codec(index = 18)]
+    CompactU64,
+    #[codec(index = 19)]
+    CompactU128,
+    #[codec(index = 20)]
+    CompactU256,
+    #[codec(index = 21)]
+    Void,
+    #[codec(index = 22)]
+    ById(Compact<u32>),
+}
+
+#[derive(Clone, Debug, Decode, Encode, Eq, PartialEq)]
+pub enum TypeDef {
+    #[codec(index = 0)]
+    Composite(Vec<Field>),
+    #[codec(index = 1)]
+    Enumeration(EnumerationVariant),
+    #[codec(index = 2)]
+    Sequence(TypeRef),
+    #[codec(index = 3)]
+    Array(TypeDefArray),
+    #[codec(index = 4)]
+    Tuple(Vec<TypeRef>),
+    #[codec(index = 5)]
+    BitSequence(TypeDefBitSequence),
+}
+
+#[derive(Clone, Debug, Decode, Encode, Eq, PartialEq)]
+pub struct Field {
+    pub name: Option<String>,
+    pub ty: TypeRef,
+    pub type_name: Option<String>,
+}
+
+#[derive(Clone, Debug, Decode, Encode, Eq, PartialEq)]
+pub struct EnumerationVariant {
+    pub name: String,
+    pub fields: Vec<Field>,
+    pub index: Compact<u32>,
+}
+
+#[derive(Clone, Debug, Decode, Encode, Eq, PartialEq)]
+pub struct TypeDefArray {
+    pub len: u32,
+    pub type_param: TypeRef,
+}
+
+#[derive(Clone, Debug, Decode, Encode, Eq, PartialEq)]
+pub struct TypeDefBitSequence {
+    pub num_bytes: u8,
+    pub least_significant_bit_first: bool,
+}
+
+/// Leaf of types tree.
+#[derive(Clone, Debug, Decode, Encode, Eq, PartialEq)]
+pub struct Type {
+    pub path: Vec<String>,
+    pub type_def: TypeDef,
+    pub type_id: Compact<u32>,
+}
+
+#[derive(Clone, Debug, Decode, Encode, Eq, PartialEq)]
+pub struct ExtrinsicMetadata {
+    pub version: u8,
+    pub address_ty: TypeRef,
+    pub call_ty: TypeRef,
+    pub signature_ty: TypeRef,
+    pub signed_extensions: Vec<SignedExtensionMetadata>,
+}
+
+#[derive(Clone, Debug, Decode, Encode, Eq, PartialEq)]
+pub struct SignedExtensionMetadata {
+    pub identifier: String,
+    pub included_in_extrinsic: TypeRef,
+    pub included_in_signed_data: TypeRef,
+}
+
+/// Types tree leaves needed to decode transaction, with hashes of the nodes
+/// that could not be calculated from the leaves.
+///
+/// Node indices are in complete binary tree with root at `0`. Leaves go
+/// left-most first, nodes are sorted left to right, from root to leaf.
+#[derive(Clone, Debug, Decode, Encode, Eq, PartialEq)]
+pub struct Proof {
+    pub leaves: Vec<Type>,
+    pub leaf_indices: Vec<u32>,
+    pub nodes: Vec<Hash>,
+}
+
+/// Chain information included in metadata digest.
+#[derive(Clone, Debug, Decode, Encode, Eq, PartialEq)]
+pub struct ExtraInfo {
+    pub spec_version: u32,
+    pub spec_name: String,
+    pub base58_prefix: u16,
+    pub decimals: u8,
+    pub token_symbol: String,
+}
+
+/// Everything needed to calculate metadata digest, as sent to offline signer.
+#[derive(Clone, Debug, Decode, Encode, Eq, PartialEq)]
+pub struct MetadataProof {
+    pub proof: Proof,
+    pub extrinsic: ExtrinsicMetadata,
+    pub extra_info: ExtraInfo,
+}
+
+/// Metadata digest, its hash is the metadata hash.
+///
+/// Only enabled variant is ever constructed here; disabled one has index `0`.
+#[derive(Encode)]
+enum MetadataDigest {
+    #[codec(index = 1)]
+    V1 {
+        types_tree_root: Hash,
+        extrinsic_metadata_hash: Hash,
+        spec_version: u32,
+        spec_name: String,
+        base58_prefix: u16,
+        decimals: u8,
+        token_symbol: String,
+    },
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum MetadataProofError {
+    DigestMismatch,
+    ExtensionsMismatch,
+    Format,
+    NoMetadataHash,
+    SpecsMismatch,
+    TreeStructure,
+}
+
+impl MetadataProofError {
+    pub fn error_text(&self) -> String {
+        match &self {
+            MetadataProofError::DigestMismatch => String::from("Metadata proof does not match metadata hash in transaction."),
+            MetadataProofError::ExtensionsMismatch => String::from("Signed extensions in metadata proof and in metadata do not match."),
+            MetadataProofError::Format => String::from("Unable to decode metadata proof."),
+            MetadataProofError::NoMetadataHash => format!("Transaction does not commit to metadata hash through {CHECK_METADATA_HASH}."),
+            MetadataProofError::SpecsMismatch => String::from("Chain specs in metadata proof and in metadata do not match."),
+            MetadataProofError::TreeStructure => String::from("Metadata proof has damaged types tree."),
+        }
+    }
+}
+
+impl Display for MetadataProofError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "{}", self.error_text())
+    }
+}
+
+fn blake3_hash(data: &[u8]) -> Hash {
+    blake3::hash(data).into()
+}
+
+fn node_hash(left: &Hash, right: &Hash) -> Hash {
+    blake3_hash(&(left, right).encode())
+}
+
+/// Node index in complete binary tree.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct NodeIndex(usize);
+
+impl NodeIndex {
+    fn parent(self) -> Self {
+        if self.0 == 0 { self } else { Self((self.0 - 1) / 2) }
+    }
+    fn is_left_child(self) -> bool {
+        self.0 % 2 == 1
+    }
+    fn level(self) -> u32 {
+        (self.0 + 1).ilog2()
+    }
+    fn right_child(self) -> Self {
+        Self(self.0 * 2 + 2)
+    }
+    fn is_descendent(self, other: Self) -> bool {
+        if self.0 == 0 { return true }
+        if self.0 > other.0 { return false }
+        let level_difference = other.level() - self.level();
+        self.0 + 1 == (other.0 + 1) >> level_difference
+    }
+}
+
+type LeafIter<'a> = core::iter::Peekable<core::iter::Zip<core::slice::Iter<'a, u32>, core::slice::Iter<'a, Type>>>;
+
+impl Proof {
+    /// Root of types tree, recalculated in the same order the proof was built.
+    pub fn root(&self) -> Result<Hash, MetadataProofError> {
+        if self.leaves.len() != self.leaf_indices.len() {
+            return Err(MetadataProofError::TreeStructure)
+        }
+        let mut leaves: LeafIter = self.leaf_indices.iter().zip(self.leaves.iter()).peekable();
+        let mut nodes = self.nodes.iter();
+        let (first_index, first_leaf) = leaves.next().ok_or(MetadataProofError::TreeStructure)?;
+        let root = if *first_index == 0 {
+            blake3_hash(&first_leaf.encode())
+        } else {
+            subtree_root(NodeIndex(0), NodeIndex(*first_index as usize), first_leaf, &mut leaves, &mut nodes)?
+        };
+        if leaves.next().is_some() | nodes.next().is_some() {
+            return Err(MetadataProofError::TreeStructure)
+        }
+        Ok(root)
+    }
+}
+
+/// Hash of node `stop`, calculated going up from leaf `start` in its subtree.
+///
+/// Hashes of nodes to the left of the path are taken first, closest to `stop`
+/// first; then the right side is processed bottom to top, going down into
+/// right subtrees that contain other leaves.
+fn subtree_root<'a>(
+    stop: NodeIndex,
+    start: NodeIndex,
+    start_leaf: &Type,
+    leaves: &mut LeafIter<'a>,
+    nodes: &mut core::slice::Iter<'a, Hash>,
+) -> Result<Hash, MetadataProofError> {
+    let mut path = Vec::new();
+    let mut node_index = start;
+    loop {
+        if node_index.0 == 0 {
+            return Err(MetadataProofError::TreeStructure)
+        }
+        path.push(node_index);
+        let parent = node_index.parent();
+        if parent == stop { break }
+        node_index = parent;
+    }
+
+    let left_count = path.iter().filter(|a| !a.is_left_child()).count();
+    let mut left_hashes = Vec::with_capacity(left_count);
+    for _ in 0..left_count {
+        left_hashes.push(*nodes.next().ok_or(MetadataProofError::TreeStructure)?);
+    }
+    let mut left_hashes = left_hashes.into_iter().rev();
+
+    let mut hash = blake3_hash(&start_leaf.encode());
+    for node_index in path.into_iter() {
+        if node_index.is_left_child() {
+            let right_child = node_index.parent().right_child();
+            let right = match leaves.peek() {
+                Some((i, _)) if **i as usize == right_child.0 => {
+                    let (_, leaf) = leaves.next().expect("just peeked");
+                    blake3_hash(&leaf.encode())
+                },
+                Some((i, _)) if right_child.is_descendent(NodeIndex(**i as usize)) => {
+                    let (i, leaf) = leaves.next().expect("just peeked");
+                    subtree_root(right_child, NodeIndex(*i as usize), leaf, leaves, nodes)?
+                },
+                _ => *nodes.next().ok_or(MetadataProofError::TreeStructure)?,
+            };
+            hash = node_hash(&hash, &right);
+        } else {
+            let left = left_hashes.next().expect("counted left siblings");
+            hash = node_hash(&left, &hash);
+        }
+    }
+    Ok(hash)
+}
+
+impl MetadataProof {
+    /// Metadata hash, as committed to by `CheckMetadataHash`.
+    pub fn metadata_hash(&self) -> Result<Hash, MetadataProofError> {
+        let digest = MetadataDigest::V1 {
+            types_tree_root: self.proof.root()?,
+            extrinsic_metadata_hash: blake3_hash(&self.extrinsic.encode()),
+            spec_version: self.extra_info.spec_version,
+            spec_name: self.extra_info.spec_name.to_owned(),
+            base58_prefix: self.extra_info.base58_prefix,
+            decimals: self.extra_info.decimals,
+            token_symbol: self.extra_info.token_symbol.to_owned(),
+        };
+        Ok(blake3_hash(&digest.encode()))
+    }
+
+    /// Check metadata hash in signed extensions data against the proof.
+    ///
+    /// Signed extensions data ends with parts included in signed data, in
+    /// order of signed extensions. Metadata hash, encoded as `Some(hash)`, is
+    /// thus at the very end, if `CheckMetadataHash` is followed only by signed
+    /// extensions with nothing included in signed data.
+    ///
+    /// Signed extensions order is taken from the proof itself, and is
+    /// trustworthy only if the metadata hash matches.
+    pub fn check_extensions(&self, extensions: &[u8]) -> Result<(), MetadataProofError> {
+        let signed_extensions = &self.extrinsic.signed_extensions;
+        let position = signed_extensions
+            .iter()
+            .position(|a| a.identifier == CHECK_METADATA_HASH)
+            .ok_or(MetadataProofError::NoMetadataHash)?;
+        if signed_extensions[position + 1..].iter().any(|a| a.included_in_signed_data != TypeRef::Void) {
+            return Err(MetadataProofError::NoMetadataHash)
+        }
+        if extensions.len() < 1 + 32 {
+            return Err(MetadataProofError::NoMetadataHash)
+        }
+        let (option, hash) = extensions[extensions.len() - 33..].split_at(1);
+        if option != [1] {
+            return Err(MetadataProofError::NoMetadataHash)
+        }
+        if hash != self.metadata_hash()? {
+            return Err(MetadataProofError::DigestMismatch)
+        }
+        Ok(())
+    }
+}
+
+impl TypeRef {
+    /// Id of referenced type in registry made of proof leaves.
+    ///
+    /// Types that are not leaves are numbered after `SYNTHETIC_TYPES_START`
+    /// by the codec index of the reference.
+    pub fn id(&self) -> Result<u32, MetadataProofError> {
+        match self {
+            TypeRef::ById(a) if a.0 < SYNTHETIC_TYPES_START => Ok(a.0),
+            TypeRef::ById(_) => Err(MetadataProofError::Format),
+            a => Ok(SYNTHETIC_TYPES_START + a.encode()[0] as u32),
+        }
+    }
+}
+
+fn scale_type(segments: Vec<String>, type_def: ScaleTypeDef<PortableForm>) -> ScaleType<PortableForm> {
+    ScaleType {
+        path: Path { segments },
+        type_params: Vec::new(),
+        type_def,
+        docs: Vec::new(),
+    }
+}
+
+fn scale_fields(fields: &[Field]) -> Result<Vec<ScaleField<PortableForm>>, MetadataProofError> {
+    fields
+        .iter()
+        .map(|a| Ok(ScaleField {
+            name: a.name.to_owned(),
+            ty: a.ty.id()?.into(),
+            type_name: a.type_name.to_owned(),
+            docs: Vec::new(),
+        }))
+        .collect()
+}
+
+/// Types referenced from leaves, but not present in types tree.
+fn synthetic_types() -> Vec<(u32, ScaleType<PortableForm>)> {
+    let primitives = [
+        TypeDefPrimitive::Bool,
+        TypeDefPrimitive::Char,
+        TypeDefPrimitive::Str,
+        TypeDefPrimitive::U8,
+        TypeDefPrimitive::U16,
+        TypeDefPrimitive::U32,
+        TypeDefPrimitive::U64,
+        TypeDefPrimitive::U128,
+        TypeDefPrimitive::U256,
+        TypeDefPrimitive::I8,
+        TypeDefPrimitive::I16,
+        TypeDefPrimitive::I32,
+        TypeDefPrimitive::I64,
+        TypeDefPrimitive::I128,
+        TypeDefPrimitive::I256,
+    ];
+    let mut types = Vec::new();
+    for (offset, primitive) in primitives.into_iter().enumerate() {
+        types.push((SYNTHETIC_TYPES_START + offset as u32, scale_type(Vec::new(), ScaleTypeDef::Primitive(primitive))));
+    }
+    // `CompactU8` to `CompactU256` wrap primitives `U8` to `U256`
+    for offset in 15..21 {
+        let type_param = (SYNTHETIC_TYPES_START + offset - 12).into();
+        types.push((SYNTHETIC_TYPES_START + offset, scale_type(Vec::new(), ScaleTypeDef::Compact(TypeDefCompact { type_param }))));
+    }
+    types.push((SYNTHETIC_TYPES_START + 21, scale_type(Vec::new(), ScaleTypeDef::Tuple(TypeDefTuple { fields: Vec::new() }))));
+    for (offset, name) in [(LSB0_OFFSET, "Lsb0"), (MSB0_OFFSET, "Msb0")] {
+        let path = vec![String::from("bitvec"), String::from("order"), String::from(name)];
+        types.push((SYNTHETIC_TYPES_START + offset, scale_type(path, ScaleTypeDef::Composite(TypeDefComposite { fields: Vec::new() }))));
+    }
+    types
+}
+
+impl Proof {
+    /// Types registry made of proof leaves only.
+    ///
+    /// Enumeration leaves with same type id are joined into single variant
+    /// type; variants that are not in the proof are not in the registry
+    /// either, and transaction using them fails to decode.
+    pub fn types(&self) -> Result<BTreeMap<u32, ScaleType<PortableForm>>, MetadataProofError> {
+        let mut types: BTreeMap<u32, ScaleType<PortableForm>> = synthetic_types().into_iter().collect();
+        for leaf in self.leaves.iter() {
+            let id = TypeRef::ById(leaf.type_id).id()?;
+            let type_def = match &leaf.type_def {
+                TypeDef::Enumeration(variant) => {
+                    let variant = Variant {
+                        name: variant.name.to_owned(),
+                        fields: scale_fields(&variant.fields)?,
+                        index: u8::try_from(variant.index.0).map_err(|_| MetadataProofError::Format)?,
+                        docs: Vec::new(),
+                    };
+                    match types.get_mut(&id) {
+                        Some(ScaleType { type_def: ScaleTypeDef::Variant(a), .. }) => {
+                            if a.variants.iter().any(|b| b.index == variant.index) {
+                                return Err(MetadataProofError::Format)
+                            }
+                            a.variants.push(variant);
+                            continue
+                        },
+                        Some(_) => return Err(MetadataProofError::Format),
+                        None => ScaleTypeDef::Variant(TypeDefVariant { variants: vec![variant] }),
+                    }
+                },
+                TypeDef::Composite(fields) => ScaleTypeDef::Composite(TypeDefComposite { fields: scale_fields(fields)? }),
+                TypeDef::Sequence(a) => ScaleTypeDef::Sequence(TypeDefSequence { type_param: a.id()?.into() }),
+                TypeDef::Array(a) => ScaleTypeDef::Array(ScaleTypeDefArray { len: a.len, type_param: a.type_param.id()?.into() }),
+                TypeDef::Tuple(a) => ScaleTypeDef::Tuple(TypeDefTuple {
+                    fields: a.iter().map(|b| b.id().map(Into::into)).collect::<Result<_, _>>()?,
+                }),
+                TypeDef::BitSequence(a) => {
+                    let bit_store_type = match a.num_bytes {
+                        1 => TypeRef::U8,
+                        2 => TypeRef::U16,
+                        4 => TypeRef::U32,
+                        8 => TypeRef::U64,
+                        _ => return Err(MetadataProofError::Format),
+                    };
+                    let bit_order_offset = if a.least_significant_bit_first { LSB0_OFFSET } else { MSB0_OFFSET };
+                    ScaleTypeDef::BitSequence(ScaleTypeDefBitSequence {
+                        bit_store_type: bit_store_type.id()?.into(),
+                        bit_order_type: (SYNTHETIC_TYPES_START + bit_order_offset).into(),
+                    })
+                },
+            };
+            if types.insert(id, scale_type(leaf.path.to_owned(), type_def)).is_some() {
+                return Err(MetadataProofError::Format)
+            }
+        }
+        Ok(types)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use frame_metadata::{
+        v15::{CustomMetadata, ExtrinsicMetadata as FrameExtrinsicMetadata, OuterEnums, RuntimeMetadataV15, SignedExtensionMetadata as FrameSignedExtensionMetadata},
+        RuntimeMetadata,
+    };
+    use parity_scale_codec::DecodeAll;
+    use ::merkleized_metadata::{
+        generate_metadata_digest, generate_proof_for_extrinsic_parts, FrameMetadataPrepared,
+        SignedExtrinsicData,
+    };
+    use scale_info::{meta_type, TypeInfo};
+
+    #[allow(dead_code)]
+    #[derive(Encode, TypeInfo)]
+    enum MultiAddress {
+        Id([u8; 32]),
+        Index(#[codec(compact)] u32),
+        Raw(Vec<u8>),
+    }
+
+    #[allow(dead_code, non_camel_case_types)]
+    #[derive(Encode, TypeInfo)]
+    enum SystemCall {
+        remark { remark: Vec<u8> },
+        set_heap_pages { pages: u64 },
+    }
+
+    #[allow(dead_code, non_camel_case_types)]
+    #[derive(Encode, TypeInfo)]
+    enum BalancesCall {
+        transfer_allow_death { dest: MultiAddress, #[codec(compact)] value: u128 },
+        transfer_keep_alive { dest: MultiAddress, #[codec(compact)] value: u128 },
+        transfer_all { dest: MultiAddress, keep_alive: bool },
+    }
+
+    #[allow(dead_code)]
+    #[derive(Encode, TypeInfo)]
+    enum RuntimeCall {
+        #[codec(index = 0)]
+        System(SystemCall),
+        #[codec(index = 5)]
+        Balances(BalancesCall),
+        #[codec(index = 7)]
+        Utility(Vec<RuntimeCall>),
+    }
+
+    #[allow(dead_code)]
+    #[derive(Encode, TypeInfo)]
+    enum Mode {
+        Disabled,
+        Enabled,
+    }
+
+    #[allow(dead_code)]
+    #[derive(Encode, TypeInfo)]
+    enum MultiSignature {
+        Ed25519([u8; 64]),
+        Sr25519([u8; 64]),
+        Ecdsa([u8; 65]),
+    }
+
+    fn extra_info() -> ExtraInfo {
+        ExtraInfo {
+            spec_version: 1_002_000,
+            spec_name: String::from("westend"),
+            base58_prefix: 42,
+            decimals: 12,
+            token_symbol: String::from("WND"),
+        }
+    }
+
+    fn metadata() -> RuntimeMetadata {
+        let signed_extensions = vec![
+            FrameSignedExtensionMetadata {
+                identifier: "CheckSpecVersion",
+                ty: meta_type::<()>(),
+                additional_signed: meta_type::<u32>(),
+            },
+            FrameSignedExtensionMetadata {
+                identifier: "CheckNonce",
+                ty: meta_type::<Compact<u32>>(),
+                additional_signed: meta_type::<()>(),
+            },
+            FrameSignedExtensionMetadata {
+                identifier: "CheckGenesis",
+                ty: meta_type::<()>(),
+                additional_signed: meta_type::<[u8; 32]>(),
+            },
+            FrameSignedExtensionMetadata {
+                identifier: CHECK_METADATA_HASH,
+                ty: meta_type::<Mode>(),
+                additional_signed: meta_type::<Option<[u8; 32]>>(),
+            },
+        ];
+        let extrinsic = FrameExtrinsicMetadata {
+            version: 4,
+            address_ty: meta_type::<MultiAddress>(),
+            call_ty: meta_type::<RuntimeCall>(),
+            signature_ty: meta_type::<MultiSignature>(),
+            extra_ty: meta_type::<()>(),
+            signed_extensions,
+        };
+        let outer_enums = OuterEnums {
+            call_enum_ty: meta_type::<RuntimeCall>(),
+            event_enum_ty: meta_type::<()>(),
+            error_enum_ty: meta_type::<()>(),
+        };
+        RuntimeMetadata::V15(RuntimeMetadataV15::new(
+            Vec::new(),
+            extrinsic,
+            meta_type::<()>(),
+            Vec::new(),
+            outer_enums,
+            CustomMetadata { map: BTreeMap::new() },
+        ))
+    }
+
+    /// Metadata hash as calculated by `merkleized-metadata` crate.
+    fn metadata_hash(metadata: &RuntimeMetadata) -> Hash {
+        let extra_info = extra_info();
+        generate_metadata_digest(metadata, ::merkleized_metadata::ExtraInfo {
+            spec_version: extra_info.spec_version,
+            spec_name: extra_info.spec_name,
+            base58_prefix: extra_info.base58_prefix,
+            decimals: extra_info.decimals,
+            token_symbol: extra_info.token_symbol,
+        })
+        .unwrap()
+        .hash()
+    }
+
+    fn extensions_parts(metadata_hash: Option<Hash>) -> (Vec<u8>, Vec<u8>) {
+        let included_in_extrinsic = (Compact(3u32), 1u8).encode();
+        let included_in_signed_data = (extra_info().spec_version, [0x91u8; 32], metadata_hash).encode();
+        (included_in_extrinsic, included_in_signed_data)
+    }
+
+    /// Proof for `call`, made by `merkleized-metadata` crate and transcoded
+    /// into local types.
+    fn metadata_proof(metadata: &RuntimeMetadata, call: &[u8]) -> MetadataProof {
+        let (included_in_extrinsic, included_in_signed_data) = extensions_parts(Some([0; 32]));
+        let proof = generate_proof_for_extrinsic_parts(
+            call,
+            Some(SignedExtrinsicData {
+                included_in_extrinsic: &included_in_extrinsic,
+                included_in_signed_data: &included_in_signed_data,
+            }),
+            metadata,
+        )
+        .unwrap();
+        let extrinsic = FrameMetadataPrepared::prepare(metadata)
+            .unwrap()
+            .as_type_information()
+            .unwrap()
+            .extrinsic_metadata;
+        MetadataProof {
+            proof: Proof::decode_all(&mut &proof.encode()[..]).unwrap(),
+            extrinsic: ExtrinsicMetadata::decode_all(&mut &extrinsic.encode()[..]).unwrap(),
+            extra_info: extra_info(),
+        }
+    }
+
+    fn calls() -> Vec<Vec<u8>> {
+        let dest = MultiAddress::Id([0x2a; 32]);
+        vec![
+            RuntimeCall::System(SystemCall::remark { remark: b"kampela".to_vec() }).encode(),
+            RuntimeCall::Balances(BalancesCall::transfer_keep_alive { dest, value: 1_000_000_000_000 }).encode(),
+            RuntimeCall::Utility(vec![
+                RuntimeCall::System(SystemCall::set_heap_pages { pages: 8 }),
+                RuntimeCall::Balances(BalancesCall::transfer_all { dest: MultiAddress::Index(7), keep_alive: true }),
+            ]).encode(),
+        ]
+    }
+
+    #[test]
+    fn proof_root_matches_metadata_digest() {
+        let metadata = metadata();
+        let expected = metadata_hash(&metadata);
+        for call in calls() {
+            let metadata_proof = metadata_proof(&metadata, &call);
+            assert_eq!(metadata_proof.metadata_hash().unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn extensions_checked_against_proof() {
+        let metadata = metadata();
+        let metadata_proof = metadata_proof(&metadata, &calls()[1]);
+
+        let (mut extensions, signed_data) = extensions_parts(Some(metadata_hash(&metadata)));
+        extensions.extend_from_slice(&signed_data);
+        assert_eq!(metadata_proof.check_extensions(&extensions), Ok(()));
+
+        let (mut extensions, signed_data) = extensions_parts(Some([0; 32]));
+        extensions.extend_from_slice(&signed_data);
+        assert_eq!(metadata_proof.check_extensions(&extensions), Err(MetadataProofError::DigestMismatch));
+
+        let (mut extensions, signed_data) = extensions_parts(None);
+        extensions.extend_from_slice(&signed_data);
+        assert_eq!(metadata_proof.check_extensions(&extensions), Err(MetadataProofError::NoMetadataHash));
+    }
+
+    #[test]
+    fn damaged_proof_rejected() {
+        let metadata = metadata();
+        let expected = metadata_hash(&metadata);
+        let metadata_proof = metadata_proof(&metadata, &calls()[2]);
+        assert!(!metadata_proof.proof.nodes.is_empty());
+
+        let mut changed_leaf = metadata_proof.to_owned();
+        changed_leaf.proof.leaves[0].path.push(String::from("fake"));
+        assert_ne!(changed_leaf.metadata_hash().unwrap(), expected);
+
+        let mut extra_node = metadata_proof.to_owned();
+        extra_node.proof.nodes.push([0; 32]);
+        assert_eq!(extra_node.metadata_hash(), Err(MetadataProofError::TreeStructure));
+
+        let mut missing_node = metadata_proof.to_owned();
+        missing_node.proof.nodes.pop();
+        assert_eq!(missing_node.metadata_hash(), Err(MetadataProofError::TreeStructure));
+
+        let mut missing_leaf = metadata_proof.to_owned();
+        missing_leaf.proof.leaves.pop();
+        assert_eq!(missing_leaf.metadata_hash(), Err(MetadataProofError::TreeStructure));
+    }
+
+    #[test]
+    fn types_registry_has_proven_variants_only() {
+        let metadata = metadata();
+        let metadata_proof = metadata_proof(&metadata, &calls()[1]);
+        let types = metadata_proof.proof.types().unwrap();
+
+        let call_ty = metadata_proof.extrinsic.call_ty.id().unwrap();
+        let ScaleTypeDef::Variant(ref runtime_call) = types[&call_ty].type_def else { panic!("call is enum") };
+        assert_eq!(runtime_call.variants.len(), 1);
+        assert_eq!(runtime_call.variants[0].name, "Balances");
+        assert_eq!(runtime_call.variants[0].index, 5);
+
+        let balances_call_ty = runtime_call.variants[0].fields[0].ty.id;
+        let ScaleTypeDef::Variant(ref balances_call) = types[&balances_call_ty].type_def else { panic!("call is enum") };
+        assert_eq!(balances_call.variants.len(), 1);
+        let transfer = &balances_call.variants[0];
+        assert_eq!(transfer.name, "transfer_keep_alive");
+        assert_eq!(transfer.index, 1);
+
+        let value_ty = transfer.fields[1].ty.id;
+        let ScaleTypeDef::Compact(ref value) = types[&value_ty].type_def else { panic!("value is compact") };
+        assert_eq!(types[&value.type_param.id].type_def, ScaleTypeDef::Primitive(TypeDefPrimitive::U128));
+    }
+
+    #[test]
+    fn references_to_synthetic_ids_rejected() {
+        let mut metadata_proof = metadata_proof(&metadata(), &calls()[0]);
+        metadata_proof.proof.leaves[0].type_id = Compact(SYNTHETIC_TYPES_START);
+        assert_eq!(metadata_proof.proof.types(), Err(MetadataProofError::Format));
+    }
+}
@@ -32,6 +32,75 @@ pub fn se_command_aes_gsm_decrypt(tag: &[u8]) -> u32 {
 /// Get random command word.
 pub const SE_COMMAND_TRNG_GET_RANDOM: u32 = 0x07000000;
 
+/// Hash command word base.
+pub const SE_COMMAND_HASH: u32 = 0x03000000;
+
+/// HMAC command word base.
+pub const SE_COMMAND_HMAC: u32 = 0x03020000;
+
+/// PBKDF2 with HMAC pseudorandom function command word base.
+pub const SE_COMMAND_DERIVE_KEY_PBKDF2_HMAC: u32 = 0x02020002;
+
+/// HKDF command word base.
+pub const SE_COMMAND_DERIVE_KEY_HKDF: u32 = 0x02020003;
+
+/// SHA-256 command option.
+pub const SE_COMMAND_OPTION_HASH_SHA256: u32 = 0x00000400;
+
+/// SHA-512 command option.
+pub const SE_COMMAND_OPTION_HASH_SHA512: u32 = 0x00000600;
+
+/// Hash algorithms of security element in use.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SeHash {
+    Sha256,
+    Sha512,
+}
+
+impl SeHash {
+    /// Command option selecting the algorithm.
+    pub fn command_option(&self) -> u32 {
+        match self {
+            SeHash::Sha256 => SE_COMMAND_OPTION_HASH_SHA256,
+            SeHash::Sha512 => SE_COMMAND_OPTION_HASH_SHA512,
+        }
+    }
+
+    /// Digest length in bytes.
+    pub fn digest_len(&self) -> usize {
+        match self {
+            SeHash::Sha256 => 32,
+            SeHash::Sha512 => 64,
+        }
+    }
+}
+
+/// Hash command word for provided algorithm.
+pub fn se_command_hash(hash: SeHash) -> u32 {
+    SE_COMMAND_HASH | hash.command_option()
+}
+
+/// HMAC command word for provided algorithm.
+pub fn se_command_hmac(hash: SeHash) -> u32 {
+    SE_COMMAND_HMAC | hash.command_option()
+}
+
+/// PBKDF2 command word for HMAC with provided algorithm.
+pub fn se_command_pbkdf2_hmac(hash: SeHash) -> u32 {
+    SE_COMMAND_DERIVE_KEY_PBKDF2_HMAC | hash.command_option()
+}
+
+/// HKDF command word for provided algorithm.
+pub fn se_command_hkdf(hash: SeHash) -> u32 {
+    SE_COMMAND_DERIVE_KEY_HKDF | hash.command_option()
+}
+
+/// Key specification of symmetric key passed in plaintext: type, storage and
+/// restriction bits are all zero, and low 15 bits hold key length.
+pub fn se_keyspec_plaintext(len: usize) -> u32 {
+    len as u32 & 0x7FFF
+}
+
 /// Command for SE mailbox.
 ///
 /// Contains pointers. All input and output elements must remain in scope when command goes into FIFO.
@@ -220,3 +289,47 @@ fn expect_rembytes(peripherals: &mut Peripherals, rembytes: u16) {
         .ne(&rembytes)
     {}
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Command words built by `sl_se_hash`, `sl_se_hmac`,
+    /// `sl_se_derive_key_pbkdf2` and `sl_se_derive_key_hkdf` of the SDK
+    #[test]
+    fn command_words_match_sdk() {
+        assert_eq!(se_command_hash(SeHash::Sha256), 0x03000400);
+        assert_eq!(se_command_hash(SeHash::Sha512), 0x03000600);
+        assert_eq!(se_command_hmac(SeHash::Sha256), 0x03020400);
+        assert_eq!(se_command_hmac(SeHash::Sha512), 0x03020600);
+        assert_eq!(se_command_pbkdf2_hmac(SeHash::Sha256), 0x02020402);
+        assert_eq!(se_command_pbkdf2_hmac(SeHash::Sha512), 0x02020602);
+        assert_eq!(se_command_hkdf(SeHash::Sha256), 0x02020403);
+        assert_eq!(se_command_hkdf(SeHash::Sha512), 0x02020603);
+    }
+
+    #[test]
+    fn digest_lengths() {
+        assert_eq!(SeHash::Sha256.digest_len(), 32);
+        assert_eq!(SeHash::Sha512.digest_len(), 64);
+    }
+
+    /// Key specifications of `sli_se_key_to_keyspec` for symmetric key in
+    /// plaintext, e.g. BIP39 mnemonic and 64-byte seed of PBKDF2
+    #[test]
+    fn plaintext_keyspec_matches_sdk() {
+        assert_eq!(se_keyspec_plaintext(0), 0x00000000);
+        assert_eq!(se_keyspec_plaintext(8), 0x00000008);
+        assert_eq!(se_keyspec_plaintext(32), 0x00000020);
+        assert_eq!(se_keyspec_plaintext(64), 0x00000040);
+        assert_eq!(se_keyspec_plaintext(128), 0x00000080);
+        // length field is 15 bits, type and storage bits stay zero
+        assert_eq!(se_keyspec_plaintext(0x7FFF), 0x00007FFF);
+        assert_eq!(se_keyspec_plaintext(0x8020), 0x00000020);
+    }
+
+    #[test]
+    fn aes_gcm_decrypt_word_holds_tag_length() {
+        assert_eq!(se_command_aes_gsm_decrypt(&[0; 16]), 0x04031000);
+    }
+}
@@ -13,8 +13,8 @@ use embedded_graphics_simulator::{
 use rand::{rngs::ThreadRng, thread_rng};
 use std::{collections::VecDeque, thread::sleep, time::Duration};
 use clap::Parser;
-use substrate_crypto_light::sr25519::Public;
 use mnemonic_external::regular::InternalWordList;
+use zeroize::Zeroizing;
 
 /// Amount of time required for full screen update; debounce
 ///  should be quite large as screen takes this much to clean
@@ -28,9 +28,10 @@ const UPDATE_DELAY_TIME: Duration = Duration::new(0, 500000000);
 const MAX_TOUCH_QUEUE: usize = 2;
 
 use kampela_ui::{
+    account::{default_accounts, Account},
     data_state::{AppStateInit, NFCState, DataInit, StorageState},
     display_def::*,
-    platform::{PinCode, Platform},
+    platform::{CompanionKey, MultiPublic, PinCheck, PinCode, Platform, Scheme, MAX_PIN_ATTEMPTS},
     uistate::{UIState, UpdateRequest, UpdateRequestMutate},
 };
 
@@ -38,7 +39,7 @@ use kampela_ui::{
 pub struct NfcTransactionData {
     pub call: String,
     pub extension: String,
-    pub signature: [u8; 130],
+    pub signature: Vec<u8>,
 }
 
 #[derive(Parser, Debug)]
@@ -86,33 +87,50 @@ impl HALHandle {
 #[derive(Debug)]
 struct DesktopSimulator {
     pin: PinCode,
+    pin_failures: u8,
     entropy: Option<Vec<u8>>,
     address: Option<[u8; 76]>,
     transaction: Option<NfcTransactionData>,
     stored_entropy: Option<Vec<u8>>,
+    companions: Vec<CompanionKey>,
+    accounts: Vec<Account>,
+    passphrase: Zeroizing<String>,
 }
 
 impl DesktopSimulator {
     pub fn new(init_state: &AppStateInit) -> Self {
-        let pin = [0; 4];
+        let pin = vec![0; 4];
         let transaction = match init_state.nfc {
             NFCState::Empty => None,
             NFCState::Transaction => Some(NfcTransactionData{
                 call: String::from("Hello, this is a transaction!"),
                 extension: String::from("Hello, this is a transaction!"),
-                signature: [0u8; 130],
+                signature: vec![b'0'; 130],
             }),
         };
         Self {
             pin,
+            pin_failures: 0,
             entropy: None,
             address: None,
             transaction: transaction,
             stored_entropy: None,
+            companions: Vec::new(),
+            accounts: emulated_accounts(),
+            passphrase: Zeroizing::new(String::new()),
         }
     }
 }
 
+/// Root accounts and a few derived ones, as if added from companion app
+fn emulated_accounts() -> Vec<Account> {
+    let mut accounts = default_accounts();
+    accounts.push(Account{scheme: Scheme::Sr25519, base58prefix: 0, path: String::from("//polkadot//0")});
+    accounts.push(Account{scheme: Scheme::Sr25519, base58prefix: 2, path: String::from("//kusama")});
+    accounts.push(Account{scheme: Scheme::Ed25519, base58prefix: 42, path: String::from("//westend/soft")});
+    accounts
+}
+
 impl Platform for DesktopSimulator {
     type HAL = HALHandle;
     type Rng<'a> = &'a mut ThreadRng;
@@ -127,12 +145,43 @@ impl Platform for DesktopSimulator {
         &mut h.rng
     }
 
-    fn pin(&self) -> &PinCode {
-        &self.pin
+    fn check_pin(&mut self, pin: &[u8]) -> PinCheck {
+        if pin == self.pin.as_slice() {
+            self.pin_failures = 0;
+            PinCheck::Ok
+        } else {
+            self.pin_failures += 1;
+            if self.pin_failures >= MAX_PIN_ATTEMPTS {
+                self.pin_failures = 0;
+                self.entropy = None;
+                self.stored_entropy = None;
+                println!("too many wrong pins, entropy wiped (not really, this is emulator)");
+                PinCheck::Wiped
+            } else {
+                PinCheck::Wrong{attempts_left: MAX_PIN_ATTEMPTS - self.pin_failures}
+            }
+        }
     }
 
-    fn pin_mut(&mut self) -> &mut PinCode {
-        &mut self.pin
+    fn pin_attempts_left(&self) -> u8 {
+        MAX_PIN_ATTEMPTS - self.pin_failures
+    }
+
+    fn set_pin(&mut self, pin: &[u8]) {
+        self.pin = pin.to_vec();
+        println!("pin set: {:?}", pin);
+    }
+
+    fn factory_reset(&mut self) -> bool {
+        self.pin = vec![0; 4];
+        self.pin_failures = 0;
+        self.entropy = None;
+        self.stored_entropy = None;
+        self.companions = Vec::new();
+        self.accounts = emulated_accounts();
+        self.passphrase = Zeroizing::new(String::new());
+        println!("all data erased (not really, this is emulator)");
+        true
     }
 
     fn store_entropy(&mut self, e: &[u8]) {
@@ -140,17 +189,22 @@ impl Platform for DesktopSimulator {
         println!("entropy stored (not really, this is emulator)");
     }
 
-    fn read_entropy(&mut self) {
+    fn read_entropy(&mut self) -> bool {
         self.entropy = self.stored_entropy.clone();
         println!("entropy read from emulated storage: {:?}", &self.entropy);
+        true
+    }
+
+    fn public(&self, scheme: Scheme) -> Option<MultiPublic> {
+        self.pair(scheme).map(|pair| pair.public())
     }
 
-    fn public(&self) -> Option<Public> {
-        self.pair().map(|pair| pair.public())
+    fn has_entropy(&self) -> bool {
+        self.entropy.as_ref().is_some_and(|e| !e.is_empty())
     }
 
-    fn entropy(&self) -> Option<Vec<u8>> {
-        self.entropy.clone()
+    fn entropy(&self) -> Option<Zeroizing<Vec<u8>>> {
+        self.entropy.clone().map(Zeroizing::new)
     }
 
     fn set_address(&mut self, addr: [u8; 76]) {
@@ -175,9 +229,9 @@ impl Platform for DesktopSimulator {
         }
     }
 
-    fn signature(&mut self) -> [u8; 130] {
+    fn signature(&mut self) -> Option<Vec<u8>> {
         match self.transaction {
-            Some(ref a) => a.signature,
+            Some(ref a) => Some(a.signature.clone()),
             None =>  panic!("qr not ready!"),
         }
     }
@@ -189,6 +243,32 @@ impl Platform for DesktopSimulator {
             panic!("address qr not ready!");
         }
     }
+
+    fn trust_companion(&mut self, key: CompanionKey) {
+        self.companions.push(key);
+        println!("companion key trusted: {:?}", key);
+    }
+
+    fn accounts(&self) -> &[Account] {
+        &self.accounts
+    }
+
+    fn add_account(&mut self, account: Account) -> bool {
+        if self.accounts.contains(&account) {
+            return false
+        }
+        self.accounts.push(account);
+        true
+    }
+
+    fn passphrase(&self) -> &str {
+        &self.passphrase
+    }
+
+    fn set_passphrase(&mut self, passphrase: Zeroizing<String>, store: bool) {
+        println!("wallet switched, passphrase {} (not really, this is emulator)", if store { "stored" } else { "not stored" });
+        self.passphrase = passphrase;
+    }
 }
 
 
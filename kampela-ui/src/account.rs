@@ -0,0 +1,288 @@
+//! Accounts derived from the seed
+
+#[cfg(not(feature="std"))]
+use alloc::{format, string::String, vec::Vec};
+#[cfg(feature="std")]
+use std::{format, string::String, vec::Vec};
+
+use blake2::{digest::consts::U32, Blake2b, Blake2b512, Digest};
+use substrate_crypto_light::common::{cut_path, FullDerivation};
+
+use crate::platform::{MultiPublic, Scheme};
+
+/// Generic substrate network prefix, used for accounts not bound to a network
+pub const DEFAULT_BASE58_PREFIX: u16 = 42;
+
+/// Longest derivation path kept in account store
+pub const MAX_PATH_LEN: usize = 64;
+
+/// Key derived from the seed with derivation path, such as `//polkadot//0` or `//kampela/soft`
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Account {
+    pub scheme: Scheme,
+    /// SS58 prefix of network account is used in
+    pub base58prefix: u16,
+    /// Derivation path, empty for root key
+    pub path: String,
+}
+
+impl Account {
+    pub fn root(scheme: Scheme) -> Self {
+        Account {
+            scheme,
+            base58prefix: DEFAULT_BASE58_PREFIX,
+            path: String::new(),
+        }
+    }
+
+    /// Parsed derivation path; `None` if path is malformed
+    pub fn derivation(&self) -> Option<FullDerivation<'_>> {
+        parse_path(&self.path)
+    }
+
+    /// Record in account store: scheme id, prefix, path length and path
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(4 + self.path.len());
+        out.push(self.scheme.id());
+        out.extend_from_slice(&self.base58prefix.to_le_bytes());
+        out.push(self.path.len() as u8);
+        out.extend_from_slice(self.path.as_bytes());
+        out
+    }
+
+    /// Decode one record from the start of `data`, returning it with its length
+    pub fn decode(data: &[u8]) -> Option<(Self, usize)> {
+        let scheme = Scheme::from_id(*data.first()?)?;
+        let base58prefix = u16::from_le_bytes(data.get(1..3)?.try_into().ok()?);
+        let path_len = *data.get(3)? as usize;
+        let path = core::str::from_utf8(data.get(4..4 + path_len)?).ok()?;
+        parse_path(path)?;
+        Some((
+            Account {
+                scheme,
+                base58prefix,
+                path: String::from(path),
+            },
+            4 + path_len,
+        ))
+    }
+}
+
+/// Parse derivation path of hard (`//`) and soft (`/`) junctions.
+///
+/// Password part (`///`) is not accepted in stored paths.
+pub fn parse_path(path: &str) -> Option<FullDerivation<'_>> {
+    if path.len() > MAX_PATH_LEN {
+        return None
+    }
+    let derivation = cut_path(path)?;
+    if derivation.password.is_some() {
+        return None
+    }
+    Some(derivation)
+}
+
+/// Account store contents: number of accounts followed by their records
+pub fn encode_accounts(accounts: &[Account]) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.push(accounts.len() as u8);
+    for account in accounts.iter() {
+        out.extend_from_slice(&account.encode());
+    }
+    out
+}
+
+/// Decode account store; `None` if store is empty or corrupted. Bytes after
+/// the records are ignored, as store moved from page of older firmware is
+/// padded with erased flash.
+pub fn decode_accounts(data: &[u8]) -> Option<Vec<Account>> {
+    let count = *data.first()? as usize;
+    let mut position = 1;
+    let mut accounts = Vec::with_capacity(count);
+    for _ in 0..count {
+        let (account, len) = Account::decode(data.get(position..)?)?;
+        accounts.push(account);
+        position += len;
+    }
+    Some(accounts)
+}
+
+/// Root accounts in all schemes, present on any seed; sr25519 one goes first
+/// as it is shown by default
+pub fn default_accounts() -> Vec<Account> {
+    [Scheme::Sr25519, Scheme::Ed25519, Scheme::Ecdsa]
+        .iter()
+        .map(|scheme| Account::root(*scheme))
+        .collect()
+}
+
+const SS58_CHECKSUM_PREFIX: &[u8] = b"SS58PRE";
+const SS58_CHECKSUM_LEN: usize = 2;
+
+/// SS58 address of public key in network with given prefix.
+///
+/// Ecdsa keys are addressed by blake2b-256 hash of compressed key, as Substrate
+/// does for account ids.
+pub fn ss58(public: &MultiPublic, base58prefix: u16) -> String {
+    let mut data = match base58prefix {
+        0..=63 => Vec::from([base58prefix as u8]),
+        _ => {
+            let ident = base58prefix & 0b0011_1111_1111_1111;
+            let first = ((ident & 0b0000_0000_1111_1100) as u8 >> 2) | 0b0100_0000;
+            let second = ((ident >> 8) as u8) | (((ident & 0b0000_0000_0000_0011) as u8) << 6);
+            Vec::from([first, second])
+        },
+    };
+    match public.scheme {
+        Scheme::Ecdsa => data.extend_from_slice(&Blake2b::<U32>::digest(&public.key)),
+        Scheme::Ed25519 | Scheme::Sr25519 => data.extend_from_slice(&public.key),
+    }
+    let mut hasher = Blake2b512::new();
+    hasher.update(SS58_CHECKSUM_PREFIX);
+    hasher.update(&data);
+    let checksum = hasher.finalize();
+    data.extend_from_slice(&checksum[..SS58_CHECKSUM_LEN]);
+    bs58::encode(data).into_string()
+}
+
+/// Address shortened to its ends, to fit a line
+pub fn short_address(address: &str) -> String {
+    const END_LEN: usize = 6;
+    if address.len() <= 2 * END_LEN + 3 {
+        return String::from(address)
+    }
+    format!("{}...{}", &address[..END_LEN], &address[address.len() - END_LEN..])
+}
+
+/// Name of network with given SS58 prefix, for well-known ones
+pub fn network_name(base58prefix: u16) -> String {
+    match base58prefix {
+        0 => String::from("Polkadot"),
+        2 => String::from("Kusama"),
+        42 => String::from("Substrate"),
+        a => format!("prefix {a}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use substrate_crypto_light::common::DeriveJunction;
+
+    /// Public key of `//Alice` in sr25519
+    const ALICE_SR25519: &str = "d43593c715fdd31c61141abd04a99fd6822c8558854ccde39a5684e7a56da27d";
+
+    /// Public key of `//Alice` in ecdsa, compressed
+    const ALICE_ECDSA: &str = "020a1091341fe5664bfa1782d5e04779689068c916b04cb365ec3153755684d9a1";
+
+    fn public(scheme: Scheme, key: &str) -> MultiPublic {
+        MultiPublic { scheme, key: hex::decode(key).unwrap() }
+    }
+
+    fn account(scheme: Scheme, base58prefix: u16, path: &str) -> Account {
+        Account { scheme, base58prefix, path: String::from(path) }
+    }
+
+    #[test]
+    fn account_record() {
+        let polkadot = account(Scheme::Sr25519, 0, "//polkadot//0");
+        let mut expected = Vec::from([1u8, 0, 0, 13]);
+        expected.extend_from_slice(b"//polkadot//0");
+        assert_eq!(polkadot.encode(), expected);
+        assert_eq!(Account::decode(&expected), Some((polkadot, 17)));
+
+        let root = Account::root(Scheme::Ecdsa);
+        assert_eq!(root.encode(), [2u8, 42, 0, 0]);
+        assert_eq!(Account::decode(&[2u8, 42, 0, 0, 0xff]), Some((root, 4)));
+    }
+
+    #[test]
+    fn bad_account_record() {
+        // unknown scheme
+        assert_eq!(Account::decode(&[3u8, 42, 0, 0]), None);
+        // path shorter than its length
+        assert_eq!(Account::decode(&[1u8, 42, 0, 5, b'/', b'/', b'a']), None);
+        // path that is not utf-8, and path with password
+        assert_eq!(Account::decode(&[1u8, 42, 0, 3, b'/', b'/', 0xff]), None);
+        let mut password = Vec::from([1u8, 42, 0, 9]);
+        password.extend_from_slice(b"//a///pwd");
+        assert_eq!(Account::decode(&password), None);
+        assert_eq!(Account::decode(&[]), None);
+    }
+
+    #[test]
+    fn account_store() {
+        let mut accounts = default_accounts();
+        accounts.push(account(Scheme::Ed25519, 2, "//kusama/soft"));
+        accounts.push(account(Scheme::Ecdsa, 1284, "//moonbeam//1"));
+        let encoded = encode_accounts(&accounts);
+        assert_eq!(encoded[0], 5);
+        assert_eq!(decode_accounts(&encoded), Some(accounts.clone()));
+
+        // erased flash after the records
+        let mut padded = encoded.clone();
+        padded.extend_from_slice(&[0xff; 32]);
+        assert_eq!(decode_accounts(&padded), Some(accounts));
+
+        // each truncation loses a record
+        for len in 1..encoded.len() {
+            assert_eq!(decode_accounts(&encoded[..len]), None);
+        }
+        assert_eq!(decode_accounts(&[]), None);
+        assert_eq!(decode_accounts(&[0]), Some(Vec::new()));
+    }
+
+    #[test]
+    fn path_junctions() {
+        let derivation = parse_path("//polkadot/soft//0").unwrap();
+        assert!(matches!(
+            derivation.junctions[..],
+            [DeriveJunction::Hard(_), DeriveJunction::Soft(_), DeriveJunction::Hard(_)]
+        ));
+        assert!(derivation.password.is_none());
+        assert!(parse_path("").unwrap().junctions.is_empty());
+    }
+
+    #[test]
+    fn path_limits() {
+        let longest = String::from("//") + &"a".repeat(MAX_PATH_LEN - 2);
+        assert_eq!(longest.len(), MAX_PATH_LEN);
+        assert!(parse_path(&longest).is_some());
+        assert!(parse_path(&(longest + "a")).is_none());
+
+        assert!(parse_path("//polkadot///password").is_none());
+        assert!(parse_path("///password").is_none());
+    }
+
+    #[test]
+    fn ss58_one_byte_prefix() {
+        let alice = public(Scheme::Sr25519, ALICE_SR25519);
+        assert_eq!(ss58(&alice, 0), "15oF4uVJwmo4TdGW7VfQxNLavjCXviqxT9S1MgbjMNHr6Sp5");
+        assert_eq!(ss58(&alice, 2), "HNZata7iMYWmk5RvZRTiAsSDhV8366zq2YGb3tLH5Upf74F");
+        assert_eq!(ss58(&alice, DEFAULT_BASE58_PREFIX), "5GrwvaEF5zXb26Fz9rcQpDWS57CtERHpNehXCPcNoHGKutQY");
+        // ed25519 keys are addressed as they are
+        let ed25519 = public(Scheme::Ed25519, ALICE_SR25519);
+        assert_eq!(ss58(&ed25519, DEFAULT_BASE58_PREFIX), ss58(&alice, DEFAULT_BASE58_PREFIX));
+    }
+
+    #[test]
+    fn ss58_two_byte_prefix() {
+        let alice = public(Scheme::Sr25519, ALICE_SR25519);
+        assert_eq!(ss58(&alice, 64), "cEaNSpz4PxFcZ7nT1VEKrKewH67rfx6MfcM6yKojyyPz7qaqp");
+        assert_eq!(ss58(&alice, 255), "yGHXkYLYqxijLKKfd9Q2CB9shRVu8rPNBS53wvwGTutYg4zTg");
+        assert_eq!(ss58(&alice, 1284), "VdvKmYJfD4VXA9fzz1SbmCo2eYHSzUFbaDCZSuaNKJAe8YNg6");
+        assert_eq!(ss58(&alice, 16383), "yNa8JpqfFB3q8A29rCwSgxvdU94ufJw2yKKxDgznS5m1PoFvn");
+    }
+
+    #[test]
+    fn ss58_of_ecdsa_key_hashed() {
+        let alice = public(Scheme::Ecdsa, ALICE_ECDSA);
+        assert_eq!(ss58(&alice, DEFAULT_BASE58_PREFIX), "5C7C2Z5sWbytvHpuLTvzKunnnRwQxft1jiqrLD5rhucQ5S9X");
+    }
+
+    #[test]
+    fn short_addresses() {
+        assert_eq!(short_address("5GrwvaEF5zXb26Fz9rcQpDWS57CtERHpNehXCPcNoHGKutQY"), "5Grwva...GKutQY");
+        assert_eq!(short_address("15oF4uVJwmo4T"), "15oF4uVJwmo4T");
+    }
+}
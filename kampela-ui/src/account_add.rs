@@ -0,0 +1,137 @@
+//! Screen to confirm account sent by companion app
+
+#[cfg(not(feature="std"))]
+use alloc::format;
+#[cfg(feature="std")]
+use std::format;
+
+use embedded_graphics::{
+    draw_target::DrawTarget,
+    geometry::{Point, Size},
+    pixelcolor::BinaryColor,
+    mono_font::{
+        ascii::{FONT_8X13, FONT_8X13_BOLD},
+        MonoTextStyle,
+    },
+    primitives::Rectangle,
+    Drawable,
+};
+use embedded_text::{
+    alignment::{HorizontalAlignment, VerticalAlignment},
+    style::TextBoxStyleBuilder,
+    TextBox,
+};
+
+use crate::{account::{network_name, Account}, display_def::*};
+
+use crate::widget::{view::{ViewScreen, Widget}, nav_bar::nav_bar::{NavBar, NavCommand, NAV_BAR_WIDGET}};
+
+use crate::uistate::{EventResult, UpdateRequest, UnitScreen};
+
+const VERTICAL_GAP: u32 = 4;
+
+const HEADER_WIDGET: Widget = Widget::new(
+    Rectangle{
+        top_left: Point{
+            x: 0,
+            y: VERTICAL_GAP as i32,
+        },
+        size: Size{
+            width: SCREEN_SIZE_X,
+            height: 48,
+        }
+    },
+    SCREEN_ZERO
+);
+
+const BODY_TOP_LEFT: Point = Point{
+    x: 0,
+    y: (VERTICAL_GAP + HEADER_WIDGET.bounds.size.height + VERTICAL_GAP) as i32,
+};
+const BODY_WIDGET: Widget = Widget::new(
+    Rectangle{
+        top_left: BODY_TOP_LEFT,
+        size: Size{
+            width: SCREEN_SIZE_X,
+            height: SCREEN_SIZE_Y - BODY_TOP_LEFT.y as u32 - NAV_BAR_WIDGET.bounds.size.height - VERTICAL_GAP,
+        }
+    },
+    SCREEN_ZERO
+);
+
+pub struct AccountAdd {
+    account: Option<Account>,
+    navbar: NavBar,
+}
+
+impl AccountAdd {
+    pub fn new(account: Account) -> Self {
+        AccountAdd {
+            account: Some(account),
+            navbar: NavBar::new(("reject", "add")),
+        }
+    }
+}
+
+impl ViewScreen for AccountAdd {
+    type DrawInput<'a> = ();
+    type DrawOutput = ();
+    type TapInput<'a> = ();
+    type TapOutput = Option<Account>;
+
+    fn draw_screen<'a, D>(&mut self, target: &mut D, _: ()) -> Result<(EventResult, ()), D::Error>
+    where
+        D: DrawTarget<Color = BinaryColor>,
+        Self: 'a,
+    {
+        let header_style = MonoTextStyle::new(&FONT_8X13_BOLD, BinaryColor::On);
+        let body_style = MonoTextStyle::new(&FONT_8X13, BinaryColor::On);
+        let textbox_style = TextBoxStyleBuilder::new()
+            .alignment(HorizontalAlignment::Center)
+            .vertical_alignment(VerticalAlignment::Middle)
+            .build();
+
+        TextBox::with_textbox_style(
+            "Companion app asks to add account:",
+            HEADER_WIDGET.bounds,
+            header_style,
+            textbox_style,
+        ).draw(target)?;
+        if let Some(ref account) = self.account {
+            let path = if account.path.is_empty() { "root key" } else { account.path.as_str() };
+            TextBox::with_textbox_style(
+                &format!("{}\n{}\n{}", path, network_name(account.base58prefix), account.scheme.name()),
+                BODY_WIDGET.bounds,
+                body_style,
+                textbox_style,
+            ).draw(target)?;
+        }
+        self.navbar.draw(target, false)?;
+
+        Ok((EventResult{request: None, state: None}, ()))
+    }
+
+    fn handle_tap_screen<'a>(&mut self, point: Point, _: ()) -> (EventResult, Self::TapOutput)
+    where
+        Self: 'a
+    {
+        let mut state = None;
+        let mut request = None;
+        let mut added = None;
+
+        if let Some(Some(c)) = self.navbar.handle_tap(point, ()) {
+            match c {
+                NavCommand::Left => {
+                    state = Some(UnitScreen::QRAddress);
+                    request = Some(UpdateRequest::Slow);
+                },
+                NavCommand::Right => {
+                    added = self.account.take();
+                    request = Some(UpdateRequest::Fast);
+                },
+            }
+        }
+
+        (EventResult{state, request}, added)
+    }
+}
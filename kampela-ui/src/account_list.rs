@@ -0,0 +1,182 @@
+//! Screen listing accounts derived from the seed
+
+#[cfg(not(feature="std"))]
+use alloc::{boxed::Box, format, string::String};
+#[cfg(feature="std")]
+use std::{boxed::Box, format, string::String};
+
+use embedded_graphics::{
+    draw_target::DrawTarget,
+    geometry::{Point, Size},
+    pixelcolor::BinaryColor,
+    mono_font::{
+        ascii::FONT_6X10,
+        MonoTextStyle,
+    },
+    primitives::{Line, Primitive, PrimitiveStyle, Rectangle},
+    transform::Transform,
+    Drawable,
+};
+use embedded_text::{
+    alignment::{HorizontalAlignment, VerticalAlignment},
+    style::TextBoxStyleBuilder,
+    TextBox,
+};
+
+use crate::{
+    account::{network_name, short_address, ss58, Account},
+    display_def::*,
+    platform::MultiPublic,
+};
+
+use crate::widget::{view::{ViewScreen, Widget}, nav_bar::nav_bar::{NavBar, NavCommand, NAV_BAR_WIDGET}};
+
+use crate::uistate::{EventResult, UpdateRequest, UnitScreen};
+
+pub const ACCOUNTS_PER_PAGE: usize = 3;
+
+const ROW_SIZE: Size = Size{
+    width: SCREEN_SIZE_X,
+    height: (SCREEN_SIZE_Y - NAV_BAR_WIDGET.bounds.size.height) / ACCOUNTS_PER_PAGE as u32,
+};
+
+const ROW_WIDGET: Widget = Widget::new(
+    Rectangle{
+        top_left: Point{
+            x: GAP as i32,
+            y: 0,
+        },
+        size: Size{
+            width: ROW_SIZE.width - 2 * GAP,
+            height: ROW_SIZE.height,
+        }
+    },
+    SCREEN_ZERO
+);
+
+pub struct AccountList {
+    page: usize,
+    count: usize,
+    navbar: NavBar,
+}
+
+impl AccountList {
+    pub fn new(page: usize, count: usize) -> Self {
+        AccountList {
+            page,
+            count,
+            navbar: Self::navbar(page, count),
+        }
+    }
+
+    pub fn get_page(&self) -> usize {
+        self.page
+    }
+
+    fn pages(count: usize) -> usize {
+        count.div_ceil(ACCOUNTS_PER_PAGE)
+    }
+
+    fn navbar(page: usize, count: usize) -> NavBar {
+        let left = if page == 0 { "back" } else { "previous" };
+        let right = if page + 1 < Self::pages(count) { "next" } else { "" };
+        NavBar::new((left, right))
+    }
+
+    fn row_text(account: &Account, public: Option<MultiPublic>) -> String {
+        let path = if account.path.is_empty() { "root" } else { &account.path };
+        let address = match public {
+            Some(public) => short_address(&ss58(&public, account.base58prefix)),
+            None => String::from("no key"),
+        };
+        format!(
+            "{} ({})\n{}: {}",
+            path,
+            account.scheme.name(),
+            network_name(account.base58prefix),
+            address,
+        )
+    }
+}
+
+impl ViewScreen for AccountList {
+    type DrawInput<'a> = (&'a [Account], Box<dyn Fn(&Account) -> Option<MultiPublic> + 'a>);
+    type DrawOutput = ();
+    type TapInput<'a> = ();
+    /// Index of account picked to be shown
+    type TapOutput = Option<usize>;
+
+    fn draw_screen<'a, D>(&mut self, target: &mut D, (accounts, get_public): Self::DrawInput<'a>) -> Result<(EventResult, ()), D::Error>
+    where
+        D: DrawTarget<Color = BinaryColor>,
+        Self: 'a,
+    {
+        let character_style = MonoTextStyle::new(&FONT_6X10, BinaryColor::On);
+        let textbox_style = TextBoxStyleBuilder::new()
+            .alignment(HorizontalAlignment::Left)
+            .vertical_alignment(VerticalAlignment::Middle)
+            .build();
+        let linestyle = PrimitiveStyle::with_stroke(BinaryColor::On, 1);
+
+        let page_accounts = accounts
+            .iter()
+            .skip(self.page * ACCOUNTS_PER_PAGE)
+            .take(ACCOUNTS_PER_PAGE);
+        for (i, account) in page_accounts.enumerate() {
+            let offset = Point::new(0, (i as u32 * ROW_SIZE.height) as i32);
+            TextBox::with_textbox_style(
+                &Self::row_text(account, get_public(account)),
+                ROW_WIDGET.bounds.translate(offset),
+                character_style,
+                textbox_style,
+            ).draw(target)?;
+            if i != 0 {
+                Line::new(offset, offset + Point::new(SCREEN_SIZE_X as i32, 0))
+                    .into_styled(linestyle)
+                    .draw(target)?;
+            }
+        }
+        self.navbar.draw(target, false)?;
+
+        Ok((EventResult{request: None, state: None}, ()))
+    }
+
+    fn handle_tap_screen<'a>(&mut self, point: Point, _: ()) -> (EventResult, Self::TapOutput)
+    where
+        Self: 'a
+    {
+        let mut state = None;
+        let mut request = None;
+        let mut picked = None;
+
+        if let Some(c) = self.navbar.handle_tap(point, ()) {
+            match c {
+                Some(NavCommand::Left) => {
+                    if self.page == 0 {
+                        state = Some(UnitScreen::Settings);
+                        request = Some(UpdateRequest::Fast);
+                    } else {
+                        state = Some(UnitScreen::AccountList(self.page - 1));
+                        request = Some(UpdateRequest::Fast);
+                    }
+                },
+                Some(NavCommand::Right) => {
+                    if self.page + 1 < Self::pages(self.count) {
+                        state = Some(UnitScreen::AccountList(self.page + 1));
+                        request = Some(UpdateRequest::Fast);
+                    }
+                },
+                None => {},
+            }
+        } else if point.y >= 0 {
+            let index = self.page * ACCOUNTS_PER_PAGE + point.y as usize / ROW_SIZE.height as usize;
+            if index < self.count {
+                picked = Some(index);
+                state = Some(UnitScreen::QRAddress);
+                request = Some(UpdateRequest::Slow);
+            }
+        }
+
+        (EventResult{state, request}, picked)
+    }
+}
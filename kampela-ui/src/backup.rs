@@ -1,20 +1,21 @@
 //! Screen for seed phrase display
 
 #[cfg(not(feature="std"))]
-use alloc::vec::Vec;
+use alloc::{format, string::String, vec::Vec};
 use core::marker::PhantomData;
 #[cfg(feature="std")]
-use std::vec::Vec;
+use std::{format, string::String, vec::Vec};
 
 use embedded_graphics::{
     draw_target::DrawTarget,
     geometry::{Point, Size},
     pixelcolor::BinaryColor,
     mono_font::{
-        ascii::FONT_8X13_BOLD,
+        ascii::{FONT_8X13_BOLD, FONT_10X20},
         MonoTextStyle,
     },
-    primitives::Rectangle,
+    primitives::{Line, Primitive, PrimitiveStyle, Rectangle},
+    transform::Transform,
     Drawable,
 };
 use embedded_text::{
@@ -23,8 +24,9 @@ use embedded_text::{
     TextBox,
 };
 use mnemonic_external::{AsWordList, Bits11, WordListElement, WordSet};
+use zeroize::Zeroizing;
 
-use crate::{display_def::*, message, platform::Platform, uistate::UnitScreen, widget::nav_bar::nav_bar::NavCommand};
+use crate::{display_def::*, message, platform::Platform, seed_entry::quiz::{Quiz, QuizOutcome}, slip39::{shamir::MAX_SHARE_COUNT, share::generate_shares, wordlist::Slip39WordList}, uistate::UnitScreen, widget::nav_bar::nav_bar::NavCommand};
 
 use crate::widget::{view::{ViewScreen, View, Widget}, nav_bar::nav_bar::{NavBar, NAV_BAR_WIDGET}};
 
@@ -40,7 +42,7 @@ const HEADER_WIDGET: Widget = Widget::new(
         },
         size: Size{
             width: SCREEN_SIZE_X,
-            height: 24,
+            height: 28,
         }
     },
     SCREEN_ZERO
@@ -61,8 +63,64 @@ const BODY_WIDGET: Widget = Widget::new(
     SCREEN_ZERO
 );
 
-enum BackupState {
+/// Words shown on one page of seed phrase, in two columns
+const WORDS_PER_PAGE: usize = 6;
+const WORDS_PER_COLUMN: usize = WORDS_PER_PAGE / 2;
+
+const COLUMN_WIDGET: Widget = Widget::new(
+    Rectangle{
+        top_left: Point{
+            x: GAP as i32,
+            y: BODY_TOP_LEFT.y,
+        },
+        size: Size{
+            width: SCREEN_SIZE_X / 2 - GAP,
+            height: BODY_WIDGET.bounds.size.height,
+        }
+    },
+    SCREEN_ZERO
+);
+
+/// Rows of backup format and share setup menus
+const ROWS_PER_SCREEN: u32 = 3;
+
+const ROW_SIZE: Size = Size{
+    width: SCREEN_SIZE_X,
+    height: (SCREEN_SIZE_Y - NAV_BAR_WIDGET.bounds.size.height) / ROWS_PER_SCREEN,
+};
+
+const ROW_WIDGET: Widget = Widget::new(
+    Rectangle{
+        top_left: Point{
+            x: GAP as i32,
+            y: 0,
+        },
+        size: Size{
+            width: ROW_SIZE.width - 2 * GAP,
+            height: ROW_SIZE.height,
+        }
+    },
+    SCREEN_ZERO
+);
+
+/// Fewest SLIP-39 shares offered, and fewest needed to restore
+const MIN_SHARES: u8 = 2;
+const DEFAULT_SHARE_COUNT: u8 = 3;
+
+enum BackupState<P: Platform> {
+    /// Choose between seed phrase and SLIP-39 shares
+    Format,
     ShowSeed,
+    /// Choose number of shares and how many of them restore the seed
+    ShareSetup,
+    /// Shares are shown one after another
+    ShowShares,
+    /// User is asked for some words of the phrase before it is stored
+    Quiz(Quiz<P>),
+    QuizFailed,
+    /// Shares are checked one after another, same way as seed phrase
+    ShareQuiz(Quiz<P, Slip39WordList>),
+    ShareQuizFailed,
     Message,
     Error,
     Storing,
@@ -71,18 +129,26 @@ enum BackupState {
 pub struct Backup<P> where
     P: Platform
 {
-    state: BackupState,
+    state: BackupState<P>,
     phrase: Vec<WordListElement<P::AsWordList>>,
+    /// Words of generated SLIP-39 shares
+    shares: Vec<Vec<&'static str>>,
+    /// Share shown
+    share: usize,
+    share_count: u8,
+    share_threshold: u8,
+    /// Page of seed phrase or share shown
+    page: usize,
     navbar: NavBar,
     prev_screen: UnitScreen,
     platform_type: PhantomData<P>,
 }
 
 impl<P: Platform> Backup<P> {
-    pub fn new(e: Vec<u8>, prev_screen: UnitScreen) -> Self
+    pub fn new(e: Zeroizing<Vec<u8>>, prev_screen: UnitScreen) -> Self
     where <P as Platform>::AsWordList: Sized{
         let wordlist = P::get_wordlist();
-        let phrase_result = WordSet::from_entropy(&e)
+        let phrase_result = WordSet::from_entropy(e.as_slice())
             .map(|ws| {
                 ws.bits11_set
                     .iter()
@@ -91,19 +157,24 @@ impl<P: Platform> Backup<P> {
             })
             .ok();
         let (state, phrase) = match phrase_result {
-            Some(w) => (BackupState::ShowSeed, w),
+            Some(w) => (BackupState::Format, w),
             None => (BackupState::Error, Vec::new())
         };
         Backup {
             state,
             phrase,
-            navbar: NavBar::new(("back", "store")),
+            shares: Vec::new(),
+            share: 0,
+            share_count: DEFAULT_SHARE_COUNT,
+            share_threshold: MIN_SHARES,
+            page: 0,
+            navbar: NavBar::new(("back", "")),
             prev_screen,
             platform_type: PhantomData::<P>::default(),
         }
     }
 
-    pub fn get_entropy(&self) -> Option<Vec<u8>> {
+    pub fn get_entropy(&self) -> Option<Zeroizing<Vec<u8>>> {
         WordSet {
             bits11_set: self.phrase
             .iter()
@@ -112,37 +183,157 @@ impl<P: Platform> Backup<P> {
         }
         .to_entropy()
         .ok()
+        .map(Zeroizing::new)
     }
     
-    fn draw_backup_screen<D: DrawTarget<Color = BinaryColor>>(&mut self, target: &mut D) -> Result<(), D::Error> {
-        let character_style = MonoTextStyle::new(&FONT_8X13_BOLD, BinaryColor::On);
+    /// Words shown in pages: seed phrase or current share
+    fn shown_words(&self) -> Vec<&str> {
+        match self.state {
+            BackupState::ShowShares => self.shares[self.share].clone(),
+            _ => self.phrase.iter().map(|w| w.word.as_ref()).collect(),
+        }
+    }
+
+    fn pages(&self) -> usize {
+        self.shown_words().len().div_ceil(WORDS_PER_PAGE)
+    }
+
+    fn navbar(&self) -> NavBar {
+        match self.state {
+            BackupState::Format => NavBar::new(("back", "")),
+            BackupState::ShareSetup => NavBar::new(("back", "make")),
+            _ => {
+                let left = if self.page == 0 { "back" } else { "previous" };
+                let last_share = !matches!(self.state, BackupState::ShowShares) || self.share + 1 == self.shares.len();
+                let right = if self.page + 1 < self.pages() || !last_share { "next" } else { "store" };
+                NavBar::new((left, right))
+            },
+        }
+    }
+
+    fn switch_page(&mut self, page: usize) {
+        self.page = page;
+        self.navbar = self.navbar();
+    }
+
+    fn switch_state(&mut self, state: BackupState<P>) {
+        self.state = state;
+        self.switch_page(0);
+    }
+
+    fn make_shares(&mut self, h: &mut <P as Platform>::HAL) {
+        let Some(entropy) = self.get_entropy() else {
+            self.state = BackupState::Error;
+            return
+        };
+        match generate_shares(&entropy, self.share_threshold, self.share_count, &mut P::rng(h)) {
+            Ok(shares) => {
+                self.shares = shares.iter().map(|share| share.to_words()).collect();
+                self.share = 0;
+                self.switch_state(BackupState::ShowShares);
+            },
+            Err(_) => self.state = BackupState::Error,
+        }
+    }
+
+    /// Ask some words of share `share`
+    fn start_share_quiz(&mut self, share: usize, h: &mut <P as Platform>::HAL) {
+        self.share = share;
+        let quiz = Quiz::new(&format!("share {}", share + 1), &self.shares[share], Slip39WordList, h);
+        self.state = BackupState::ShareQuiz(quiz);
+    }
+
+    fn draw_rows<D: DrawTarget<Color = BinaryColor>>(&mut self, target: &mut D, rows: &[&str]) -> Result<(), D::Error> {
+        let character_style = MonoTextStyle::new(&FONT_10X20, BinaryColor::On);
         let textbox_style = TextBoxStyleBuilder::new()
+            .alignment(HorizontalAlignment::Left)
+            .vertical_alignment(VerticalAlignment::Middle)
+            .build();
+        let linestyle = PrimitiveStyle::with_stroke(BinaryColor::On, 1);
+
+        for (i, row) in rows.iter().enumerate() {
+            let offset = Point::new(0, (i as u32 * ROW_SIZE.height) as i32);
+            TextBox::with_textbox_style(
+                row,
+                ROW_WIDGET.bounds.translate(offset),
+                character_style,
+                textbox_style,
+            ).draw(target)?;
+            if i != 0 {
+                Line::new(offset, offset + Point::new(SCREEN_SIZE_X as i32, 0))
+                    .into_styled(linestyle)
+                    .draw(target)?;
+            }
+        }
+        self.navbar.draw(target, false)?;
+
+        Ok(())
+    }
+
+    /// Row of menu tapped, if any
+    fn tapped_row(point: Point) -> Option<usize> {
+        if point.y >= 0 && point.y < (ROWS_PER_SCREEN * ROW_SIZE.height) as i32 {
+            Some(point.y as usize / ROW_SIZE.height as usize)
+        } else {
+            None
+        }
+    }
+
+    fn draw_backup_screen<D: DrawTarget<Color = BinaryColor>>(&mut self, target: &mut D) -> Result<(), D::Error> {
+        let header_style = MonoTextStyle::new(&FONT_8X13_BOLD, BinaryColor::On);
+        let character_style = MonoTextStyle::new(&FONT_10X20, BinaryColor::On);
+        let header_textbox_style = TextBoxStyleBuilder::new()
             .alignment(HorizontalAlignment::Center)
             .vertical_alignment(VerticalAlignment::Middle)
             .build();
-    
+        let textbox_style = TextBoxStyleBuilder::new()
+            .alignment(HorizontalAlignment::Left)
+            .vertical_alignment(VerticalAlignment::Middle)
+            .build();
+
+        let header = match self.state {
+            BackupState::ShowShares => format!(
+                "Share {} of {}, {} needed\npage {} of {}",
+                self.share + 1,
+                self.shares.len(),
+                self.share_threshold,
+                self.page + 1,
+                self.pages(),
+            ),
+            _ => format!("Please write down seed phrase\npage {} of {}", self.page + 1, self.pages()),
+        };
         TextBox::with_textbox_style(
-            "Please write down seed phrase",
+            &header,
             HEADER_WIDGET.bounds,
-            character_style,
-            textbox_style
-        ).draw(target)?;
-        TextBox::with_textbox_style(
-            &self.phrase.iter().map(|w| w.word.as_ref()).collect::<Vec<&str>>().join(" "),
-            BODY_WIDGET.bounds,
-            character_style,
-            textbox_style
+            header_style,
+            header_textbox_style
         ).draw(target)?;
+        let first = self.page * WORDS_PER_PAGE;
+        let page_words = self.shown_words()
+            .iter()
+            .enumerate()
+            .skip(first)
+            .take(WORDS_PER_PAGE)
+            .map(|(i, w)| format!("{:>2}.{}", i + 1, w))
+            .collect::<Vec<String>>();
+        for (column, words) in page_words.chunks(WORDS_PER_COLUMN).enumerate() {
+            TextBox::with_textbox_style(
+                &words.join("\n"),
+                COLUMN_WIDGET.bounds.translate(Point::new(column as i32 * (SCREEN_SIZE_X / 2) as i32, 0)),
+                character_style,
+                textbox_style
+            ).draw(target)?;
+        }
         self.navbar.draw(target, false)?;
-        
+
         Ok(())
     }
 }
 
 impl<P: Platform> ViewScreen for Backup<P> {
     type DrawInput<'a> = () where P: 'a;
-    type DrawOutput = Option<Vec<u8>>;
-    type TapInput<'a> = () where P: 'a;
+    type DrawOutput = Option<Zeroizing<Vec<u8>>>;
+    type TapInput<'a> = &'a mut <P as Platform>::HAL where P: 'a;
     type TapOutput = ();
 
     fn draw_screen<'a, D>(&mut self, target: &mut D, _: ()) -> Result<(EventResult, Self::DrawOutput), D::Error>
@@ -155,9 +346,30 @@ impl<P: Platform> ViewScreen for Backup<P> {
         let mut entropy = None;
         
         match self.state {
-            BackupState::ShowSeed => {
+            BackupState::Format => {
+                self.draw_rows(target, &["Seed phrase", "SLIP-39 shares"])?;
+            },
+            BackupState::ShowSeed |
+            BackupState::ShowShares => {
                 self.draw_backup_screen(target)?;
             },
+            BackupState::ShareSetup => {
+                let count = format!("Shares: {}", self.share_count);
+                let threshold = format!("Needed: {}", self.share_threshold);
+                self.draw_rows(target, &[&count, &threshold, "(tap to change)"])?;
+            },
+            BackupState::Quiz(ref mut quiz) => {
+                let (res, _) = quiz.draw_screen(target, ())?;
+                request = res.request;
+            },
+            BackupState::ShareQuiz(ref mut quiz) => {
+                let (res, _) = quiz.draw_screen(target, ())?;
+                request = res.request;
+            },
+            BackupState::QuizFailed |
+            BackupState::ShareQuizFailed => {
+                message::draw(target, "Wrong word\nPlease check your backup", false)?;
+            },
             BackupState::Message => {
                 message::draw(target, "Storing into flash...", true)?;
                 request = Some(UpdateRequest::Hidden);
@@ -172,33 +384,158 @@ impl<P: Platform> ViewScreen for Backup<P> {
             },
             BackupState::Storing => {
                 entropy = Some(self.get_entropy().unwrap());
-                state = Some(UnitScreen::QRAddress);
-                request = Some(UpdateRequest::Slow);
+                state = Some(UnitScreen::PinSetup);
+                request = Some(UpdateRequest::Fast);
             },
         }
 
         Ok((EventResult { request, state }, entropy))
     }
-    fn handle_tap_screen<'a>(&mut self, point: Point, _: ()) -> (EventResult, ()) 
+    fn handle_tap_screen<'a>(&mut self, point: Point, h: Self::TapInput<'a>) -> (EventResult, ())
     where
         Self: 'a
     {
         let mut state = None;
         let mut request = None;
 
-        if matches!(self.state, BackupState::ShowSeed) {
-            if let Some(Some(c)) = self.navbar.handle_tap(point, ()) {
-                match c {
-                    NavCommand::Left => {
+        match self.state {
+            BackupState::Format => {
+                if let Some(c) = self.navbar.handle_tap(point, ()) {
+                    if let Some(NavCommand::Left) = c {
                         state = Some(core::mem::take(&mut self.prev_screen));
                         request = Some(UpdateRequest::Fast);
+                    }
+                } else {
+                    match Self::tapped_row(point) {
+                        Some(0) => {
+                            self.switch_state(BackupState::ShowSeed);
+                            request = Some(UpdateRequest::Fast);
+                        },
+                        Some(1) => {
+                            self.switch_state(BackupState::ShareSetup);
+                            request = Some(UpdateRequest::Fast);
+                        },
+                        _ => {},
+                    }
+                }
+            },
+            BackupState::ShareSetup => {
+                if let Some(c) = self.navbar.handle_tap(point, ()) {
+                    match c {
+                        Some(NavCommand::Left) => {
+                            self.switch_state(BackupState::Format);
+                            request = Some(UpdateRequest::Fast);
+                        },
+                        Some(NavCommand::Right) => {
+                            self.make_shares(h);
+                            request = Some(UpdateRequest::Fast);
+                        },
+                        None => {},
+                    }
+                } else {
+                    match Self::tapped_row(point) {
+                        Some(0) => {
+                            self.share_count = if self.share_count < MAX_SHARE_COUNT { self.share_count + 1 } else { MIN_SHARES };
+                            self.share_threshold = self.share_threshold.min(self.share_count);
+                            request = Some(UpdateRequest::Fast);
+                        },
+                        Some(1) => {
+                            self.share_threshold = if self.share_threshold < self.share_count { self.share_threshold + 1 } else { MIN_SHARES };
+                            request = Some(UpdateRequest::Fast);
+                        },
+                        _ => {},
+                    }
+                }
+            },
+            BackupState::ShowSeed |
+            BackupState::ShowShares => {
+                let shares = matches!(self.state, BackupState::ShowShares);
+                if let Some(Some(c)) = self.navbar.handle_tap(point, ()) {
+                    match c {
+                        NavCommand::Left if self.page != 0 => {
+                            self.switch_page(self.page - 1);
+                        },
+                        NavCommand::Left if shares && self.share != 0 => {
+                            self.share -= 1;
+                            self.switch_page(self.pages() - 1);
+                        },
+                        NavCommand::Left if shares => {
+                            self.switch_state(BackupState::ShareSetup);
+                        },
+                        NavCommand::Left => {
+                            self.switch_state(BackupState::Format);
+                        },
+                        NavCommand::Right if self.page + 1 < self.pages() => {
+                            self.switch_page(self.page + 1);
+                        },
+                        NavCommand::Right if shares && self.share + 1 < self.shares.len() => {
+                            self.share += 1;
+                            self.switch_page(0);
+                        },
+                        NavCommand::Right if shares => {
+                            self.start_share_quiz(0, h);
+                        },
+                        NavCommand::Right => {
+                            let quiz = Quiz::new("backup", &self.shown_words(), P::get_wordlist(), h);
+                            self.state = BackupState::Quiz(quiz);
+                        }
+                    }
+                    request = Some(UpdateRequest::Fast);
+                }
+            },
+            BackupState::Quiz(ref mut quiz) => {
+                let (res, outcome) = quiz.handle_tap_screen(point, ());
+                request = res.request;
+                match outcome {
+                    Some(QuizOutcome::Passed) => {
+                        self.state = BackupState::Message;
+                        request = Some(UpdateRequest::UltraFast);
+                    },
+                    Some(QuizOutcome::Failed) => {
+                        self.state = BackupState::QuizFailed;
+                        request = Some(UpdateRequest::Fast);
                     },
-                    NavCommand::Right => {
+                    Some(QuizOutcome::Cancelled) => {
+                        self.state = BackupState::ShowSeed;
+                        request = Some(UpdateRequest::Fast);
+                    },
+                    None => {},
+                }
+            },
+            BackupState::QuizFailed => {
+                self.state = BackupState::ShowSeed;
+                self.switch_page(0);
+                request = Some(UpdateRequest::Fast);
+            },
+            BackupState::ShareQuiz(ref mut quiz) => {
+                let (res, outcome) = quiz.handle_tap_screen(point, ());
+                request = res.request;
+                match outcome {
+                    Some(QuizOutcome::Passed) if self.share + 1 < self.shares.len() => {
+                        self.start_share_quiz(self.share + 1, h);
+                        request = Some(UpdateRequest::Fast);
+                    },
+                    Some(QuizOutcome::Passed) => {
                         self.state = BackupState::Message;
                         request = Some(UpdateRequest::UltraFast);
-                    }
+                    },
+                    Some(QuizOutcome::Failed) => {
+                        self.state = BackupState::ShareQuizFailed;
+                        request = Some(UpdateRequest::Fast);
+                    },
+                    Some(QuizOutcome::Cancelled) => {
+                        self.switch_state(BackupState::ShowShares);
+                        request = Some(UpdateRequest::Fast);
+                    },
+                    None => {},
                 }
-            }
+            },
+            BackupState::ShareQuizFailed => {
+                // failed share is shown again
+                self.switch_state(BackupState::ShowShares);
+                request = Some(UpdateRequest::Fast);
+            },
+            _ => {},
         }
 
         (EventResult{ request, state }, ())
@@ -0,0 +1,196 @@
+//! Optional dice rolls mixed into entropy of new seed
+
+#[cfg(not(feature="std"))]
+use alloc::{format, string::String, vec::Vec};
+#[cfg(feature="std")]
+use std::{format, string::String, vec::Vec};
+
+use embedded_graphics::{
+    draw_target::DrawTarget,
+    geometry::{Point, Size},
+    pixelcolor::BinaryColor,
+    mono_font::{
+        ascii::{FONT_8X13_BOLD, FONT_10X20},
+        MonoTextStyle,
+    },
+    primitives::{CornerRadii, Primitive, PrimitiveStyleBuilder, Rectangle, RoundedRectangle, StrokeAlignment},
+    transform::Transform,
+    Drawable,
+};
+use embedded_text::{
+    alignment::{HorizontalAlignment, VerticalAlignment},
+    style::TextBoxStyleBuilder,
+    TextBox,
+};
+
+use zeroize::Zeroizing;
+
+use crate::display_def::*;
+
+use crate::widget::{view::{ViewScreen, Widget}, nav_bar::nav_bar::{NavBar, NavCommand, NAV_BAR_WIDGET}};
+
+use crate::uistate::{EventResult, UpdateRequest, UnitScreen};
+
+const DICE_FACES: u8 = 6;
+/// Entropy of one d6 roll, log2(6), in thousandths of bit
+const MILLIBITS_PER_ROLL: usize = 2585;
+/// Most recent rolls shown in header
+const SHOWN_ROLLS: usize = 16;
+
+const HEADER_WIDGET: Widget = Widget::new(
+    Rectangle{
+        top_left: Point{
+            x: GAP as i32,
+            y: GAP as i32,
+        },
+        size: Size{
+            width: SCREEN_SIZE_X - 2 * GAP,
+            height: 36,
+        }
+    },
+    SCREEN_ZERO
+);
+
+const FACES_TOP: u32 = GAP + HEADER_WIDGET.bounds.size.height + GAP;
+const FACE_COLUMNS: u32 = 3;
+const FACE_SIZE: Size = Size{
+    width: SCREEN_SIZE_X / FACE_COLUMNS,
+    height: (SCREEN_SIZE_Y - FACES_TOP - NAV_BAR_WIDGET.bounds.size.height) / 2,
+};
+const FACE_WIDGET: Widget = Widget::new(
+    Rectangle{
+        top_left: Point{
+            x: 0,
+            y: FACES_TOP as i32,
+        },
+        size: FACE_SIZE,
+    },
+    SCREEN_ZERO
+);
+
+pub struct Dice {
+    /// Words in seed phrase to be generated
+    words: usize,
+    /// Entered rolls, 1 to 6
+    rolls: Zeroizing<Vec<u8>>,
+    navbar: NavBar,
+}
+
+impl Dice {
+    pub fn new(words: usize) -> Self {
+        Dice {
+            words,
+            rolls: Zeroizing::new(Vec::new()),
+            navbar: NavBar::new(("back", "skip")),
+        }
+    }
+    pub fn words(&self) -> usize {
+        self.words
+    }
+    fn update_navbar(&mut self) {
+        if self.rolls.is_empty() {
+            self.navbar = NavBar::new(("back", "skip"));
+        } else {
+            self.navbar = NavBar::new(("undo", "done"));
+        }
+    }
+    fn face_bounds(face: u8) -> Rectangle {
+        let i = (face - 1) as u32;
+        FACE_WIDGET.bounds.translate(Point::new(
+            ((i % FACE_COLUMNS) * FACE_SIZE.width) as i32,
+            ((i / FACE_COLUMNS) * FACE_SIZE.height) as i32,
+        ))
+    }
+    fn header(&self) -> String {
+        let bits = self.rolls.len() * MILLIBITS_PER_ROLL / 1000;
+        let seed_bits = self.words / 3 * 32;
+        let mut header = format!("Rolls: {}, {} of {} bits\n", self.rolls.len(), bits.min(seed_bits), seed_bits);
+        let first = self.rolls.len().saturating_sub(SHOWN_ROLLS);
+        if first != 0 {
+            header.push_str("...");
+        }
+        for roll in self.rolls[first..].iter() {
+            header.push_str(&format!("{roll}"));
+        }
+        header
+    }
+}
+
+impl ViewScreen for Dice {
+    type DrawInput<'a> = ();
+    type DrawOutput = ();
+    type TapInput<'a> = ();
+    type TapOutput = ();
+
+    fn draw_screen<'a, D>(&mut self, target: &mut D, _: ()) -> Result<(EventResult, ()), D::Error>
+    where
+        D: DrawTarget<Color = BinaryColor>,
+        Self: 'a,
+    {
+        let header_style = MonoTextStyle::new(&FONT_8X13_BOLD, BinaryColor::On);
+        let character_style = MonoTextStyle::new(&FONT_10X20, BinaryColor::On);
+        let textbox_style = TextBoxStyleBuilder::new()
+            .alignment(HorizontalAlignment::Center)
+            .vertical_alignment(VerticalAlignment::Middle)
+            .build();
+        let thin_stroke = PrimitiveStyleBuilder::new()
+            .stroke_color(BinaryColor::On)
+            .stroke_width(2)
+            .stroke_alignment(StrokeAlignment::Inside)
+            .build();
+
+        TextBox::with_textbox_style(
+            &self.header(),
+            HEADER_WIDGET.bounds,
+            header_style,
+            textbox_style,
+        ).draw(target)?;
+        for face in 1..=DICE_FACES {
+            let bounds = Self::face_bounds(face);
+            RoundedRectangle::new(
+                bounds.offset(-(GAP as i32)),
+                CornerRadii::new(Size::new(6, 6)),
+            ).into_styled(thin_stroke).draw(target)?;
+            TextBox::with_textbox_style(
+                &format!("{face}"),
+                bounds,
+                character_style,
+                textbox_style,
+            ).draw(target)?;
+        }
+        self.navbar.draw(target, false)?;
+
+        Ok((EventResult{request: None, state: None}, ()))
+    }
+
+    fn handle_tap_screen<'a>(&mut self, point: Point, _: ()) -> (EventResult, ())
+    where
+        Self: 'a
+    {
+        let mut state = None;
+        let mut request = None;
+
+        if let Some(c) = self.navbar.handle_tap(point, ()) {
+            match c {
+                Some(NavCommand::Left) => {
+                    if self.rolls.pop().is_none() {
+                        state = Some(UnitScreen::OnboardingLength);
+                    }
+                    self.update_navbar();
+                    request = Some(UpdateRequest::Fast);
+                },
+                Some(NavCommand::Right) => {
+                    state = Some(UnitScreen::OnboardingGenerate(self.words, core::mem::take(&mut self.rolls)));
+                    request = Some(UpdateRequest::Fast);
+                },
+                None => {},
+            }
+        } else if let Some(face) = (1..=DICE_FACES).find(|face| Self::face_bounds(*face).contains(point)) {
+            self.rolls.push(face);
+            self.update_navbar();
+            request = Some(UpdateRequest::Fast);
+        }
+
+        (EventResult{state, request}, ())
+    }
+}
@@ -3,6 +3,9 @@
 
 pub mod uistate;
 pub mod platform;
+pub mod account;
+pub mod account_list;
+pub mod account_add;
 pub mod widget{
     pub mod view;
     pub mod nav_bar{
@@ -17,6 +20,7 @@ pub mod pin{
     pub mod pindots;
     pub mod pinpad;
     pub mod pinbutton;
+    pub mod setup;
 }
 
 pub mod seed_entry{
@@ -26,6 +30,8 @@ pub mod seed_entry{
     pub mod phrase;
     pub mod keyboard;
     pub mod key;
+    pub mod quiz;
+    pub mod share_entry;
 }
 
 pub mod backup;
@@ -33,6 +39,16 @@ mod message;
 mod dialog;
 
 pub mod transaction;
+pub mod pairing;
+pub mod passphrase;
+pub mod mnemonic_length;
+pub mod dice;
+pub mod settings;
+pub mod slip39{
+    pub mod shamir;
+    pub mod share;
+    pub mod wordlist;
+}
 pub mod qr;
 
 #[macro_use]
@@ -0,0 +1,118 @@
+//! Screen to choose number of words in new seed phrase
+
+#[cfg(not(feature="std"))]
+use alloc::format;
+#[cfg(feature="std")]
+use std::format;
+
+use embedded_graphics::{
+    draw_target::DrawTarget,
+    geometry::{Point, Size},
+    pixelcolor::BinaryColor,
+    mono_font::{
+        ascii::FONT_10X20,
+        MonoTextStyle,
+    },
+    primitives::{Line, Primitive, PrimitiveStyle, Rectangle},
+    transform::Transform,
+    Drawable,
+};
+use embedded_text::{
+    alignment::{HorizontalAlignment, VerticalAlignment},
+    style::TextBoxStyleBuilder,
+    TextBox,
+};
+
+use crate::{display_def::*, platform::MNEMONIC_LENGTHS};
+
+use crate::widget::{view::{ViewScreen, Widget}, nav_bar::nav_bar::{NavBar, NavCommand, NAV_BAR_WIDGET}};
+
+use crate::uistate::{EventResult, UpdateRequest, UnitScreen};
+
+const ROW_SIZE: Size = Size{
+    width: SCREEN_SIZE_X,
+    height: (SCREEN_SIZE_Y - NAV_BAR_WIDGET.bounds.size.height) / MNEMONIC_LENGTHS.len() as u32,
+};
+
+const ROW_WIDGET: Widget = Widget::new(
+    Rectangle{
+        top_left: Point{
+            x: 0,
+            y: 0,
+        },
+        size: ROW_SIZE,
+    },
+    SCREEN_ZERO
+);
+
+pub struct MnemonicLength {
+    navbar: NavBar,
+}
+
+impl MnemonicLength {
+    pub fn new() -> Self {
+        MnemonicLength {
+            navbar: NavBar::new(("back", "")),
+        }
+    }
+}
+
+impl ViewScreen for MnemonicLength {
+    type DrawInput<'a> = ();
+    type DrawOutput = ();
+    type TapInput<'a> = ();
+    type TapOutput = ();
+
+    fn draw_screen<'a, D>(&mut self, target: &mut D, _: ()) -> Result<(EventResult, ()), D::Error>
+    where
+        D: DrawTarget<Color = BinaryColor>,
+        Self: 'a,
+    {
+        let character_style = MonoTextStyle::new(&FONT_10X20, BinaryColor::On);
+        let textbox_style = TextBoxStyleBuilder::new()
+            .alignment(HorizontalAlignment::Center)
+            .vertical_alignment(VerticalAlignment::Middle)
+            .build();
+        let linestyle = PrimitiveStyle::with_stroke(BinaryColor::On, 1);
+
+        for (i, words) in MNEMONIC_LENGTHS.iter().enumerate() {
+            let offset = Point::new(0, (i as u32 * ROW_SIZE.height) as i32);
+            TextBox::with_textbox_style(
+                &format!("{words} words"),
+                ROW_WIDGET.bounds.translate(offset),
+                character_style,
+                textbox_style,
+            ).draw(target)?;
+            if i != 0 {
+                Line::new(offset, offset + Point::new(SCREEN_SIZE_X as i32, 0))
+                    .into_styled(linestyle)
+                    .draw(target)?;
+            }
+        }
+        self.navbar.draw(target, false)?;
+
+        Ok((EventResult{request: None, state: None}, ()))
+    }
+
+    fn handle_tap_screen<'a>(&mut self, point: Point, _: ()) -> (EventResult, ())
+    where
+        Self: 'a
+    {
+        let mut state = None;
+        let mut request = None;
+
+        if let Some(c) = self.navbar.handle_tap(point, ()) {
+            if let Some(NavCommand::Left) = c {
+                state = Some(UnitScreen::OnboardingRestoreOrGenerate);
+                request = Some(UpdateRequest::Fast);
+            }
+        } else if point.y >= 0 {
+            if let Some(words) = MNEMONIC_LENGTHS.get(point.y as usize / ROW_SIZE.height as usize) {
+                state = Some(UnitScreen::OnboardingDice(*words));
+                request = Some(UpdateRequest::Fast);
+            }
+        }
+
+        (EventResult{state, request}, ())
+    }
+}
@@ -0,0 +1,164 @@
+//! Screen for companion app pairing
+
+#[cfg(not(feature="std"))]
+use alloc::{boxed::Box, string::String, vec::Vec};
+#[cfg(feature="std")]
+use std::{boxed::Box, string::String, vec::Vec};
+
+use embedded_graphics::{
+    draw_target::DrawTarget,
+    geometry::{Point, Size},
+    pixelcolor::BinaryColor,
+    mono_font::{
+        ascii::{FONT_8X13, FONT_8X13_BOLD},
+        MonoTextStyle,
+    },
+    primitives::Rectangle,
+    Drawable,
+};
+use embedded_text::{
+    alignment::{HorizontalAlignment, VerticalAlignment},
+    style::TextBoxStyleBuilder,
+    TextBox,
+};
+
+use crate::{display_def::*, platform::CompanionKey, transaction::TransactionPage};
+
+use crate::widget::{view::{ViewScreen, Widget}, nav_bar::nav_bar::{NavBar, NavCommand, NAV_BAR_WIDGET}};
+
+use crate::uistate::{EventResult, UpdateRequest, UnitScreen};
+
+const VERTICAL_GAP: u32 = 4;
+
+const HEADER_WIDGET: Widget = Widget::new(
+    Rectangle{
+        top_left: Point{
+            x: 0,
+            y: VERTICAL_GAP as i32,
+        },
+        size: Size{
+            width: SCREEN_SIZE_X,
+            height: 64,
+        }
+    },
+    SCREEN_ZERO
+);
+
+const BODY_TOP_LEFT: Point = Point{
+    x: 0,
+    y: (VERTICAL_GAP + HEADER_WIDGET.bounds.size.height + VERTICAL_GAP) as i32,
+};
+const BODY_WIDGET: Widget = Widget::new(
+    Rectangle{
+        top_left: BODY_TOP_LEFT,
+        size: Size{
+            width: SCREEN_SIZE_X,
+            height: SCREEN_SIZE_Y - BODY_TOP_LEFT.y as u32 - NAV_BAR_WIDGET.bounds.size.height - VERTICAL_GAP,
+        }
+    },
+    SCREEN_ZERO
+);
+
+/// Warning shown when payload could not be attributed to any companion app
+pub fn unverified_source() -> UnitScreen {
+    UnitScreen::ShowDialog(
+        "Unverified source! Transaction is not signed by paired companion app.",
+        ("reject", "continue"),
+        (
+            Box::new(|| EventResult {
+                request: Some(UpdateRequest::Slow),
+                state: Some(UnitScreen::QRAddress),
+            }),
+            Box::new(|| EventResult {
+                request: Some(UpdateRequest::Fast),
+                state: Some(UnitScreen::ShowTransaction(TransactionPage::Call)),
+            }),
+        ),
+        true,
+    )
+}
+
+pub struct Pairing {
+    key: CompanionKey,
+    navbar: NavBar,
+}
+
+impl Pairing {
+    pub fn new(key: CompanionKey) -> Self {
+        Pairing {
+            key,
+            navbar: NavBar::new(("reject", "trust")),
+        }
+    }
+
+    /// Key in hex, grouped to be compared with the one shown by companion app
+    fn fingerprint(&self) -> String {
+        hex::encode(self.key)
+            .as_bytes()
+            .chunks(4)
+            .map(|chunk| core::str::from_utf8(chunk).expect("hex is ascii"))
+            .collect::<Vec<&str>>()
+            .join(" ")
+    }
+}
+
+impl ViewScreen for Pairing {
+    type DrawInput<'a> = ();
+    type DrawOutput = ();
+    type TapInput<'a> = ();
+    type TapOutput = Option<CompanionKey>;
+
+    fn draw_screen<'a, D>(&mut self, target: &mut D, _: ()) -> Result<(EventResult, ()), D::Error>
+    where
+        D: DrawTarget<Color = BinaryColor>,
+        Self: 'a,
+    {
+        let header_style = MonoTextStyle::new(&FONT_8X13_BOLD, BinaryColor::On);
+        let body_style = MonoTextStyle::new(&FONT_8X13, BinaryColor::On);
+        let textbox_style = TextBoxStyleBuilder::new()
+            .alignment(HorizontalAlignment::Center)
+            .vertical_alignment(VerticalAlignment::Middle)
+            .build();
+
+        TextBox::with_textbox_style(
+            "Unknown companion app. Trust it if the key matches the one shown in the app:",
+            HEADER_WIDGET.bounds,
+            header_style,
+            textbox_style,
+        ).draw(target)?;
+        TextBox::with_textbox_style(
+            &self.fingerprint(),
+            BODY_WIDGET.bounds,
+            body_style,
+            textbox_style,
+        ).draw(target)?;
+        self.navbar.draw(target, false)?;
+
+        Ok((EventResult{request: None, state: None}, ()))
+    }
+
+    fn handle_tap_screen<'a>(&mut self, point: Point, _: ()) -> (EventResult, Self::TapOutput)
+    where
+        Self: 'a
+    {
+        let mut state = None;
+        let mut request = None;
+        let mut trusted = None;
+
+        if let Some(Some(c)) = self.navbar.handle_tap(point, ()) {
+            match c {
+                NavCommand::Left => {
+                    state = Some(unverified_source());
+                    request = Some(UpdateRequest::Fast);
+                },
+                NavCommand::Right => {
+                    trusted = Some(self.key);
+                    state = Some(UnitScreen::ShowTransaction(TransactionPage::Call));
+                    request = Some(UpdateRequest::Fast);
+                },
+            }
+        }
+
+        (EventResult{state, request}, trusted)
+    }
+}
@@ -0,0 +1,194 @@
+//! Screen for BIP39 passphrase of hidden wallet
+
+#[cfg(not(feature="std"))]
+use alloc::string::String;
+#[cfg(feature="std")]
+use std::string::String;
+
+use embedded_graphics::{
+    draw_target::DrawTarget,
+    geometry::{Point, Size},
+    pixelcolor::BinaryColor,
+    mono_font::{
+        ascii::{FONT_8X13, FONT_8X13_BOLD},
+        MonoTextStyle,
+    },
+    primitives::Rectangle,
+    Drawable,
+};
+use embedded_text::{
+    alignment::{HorizontalAlignment, VerticalAlignment},
+    style::TextBoxStyleBuilder,
+    TextBox,
+};
+use zeroize::Zeroizing;
+
+use crate::{
+    display_def::*,
+    message,
+    seed_entry::{key::Key, keyboard::{Keyboard, KEYBOARD_AREA, REMOVE_KEY_WIDGET}},
+    uistate::{EventResult, UpdateRequest},
+    widget::{view::{View, ViewScreen, Widget}, nav_bar::nav_bar::{NavBar, NavCommand}},
+};
+
+/// Longest passphrase that could be wrapped by security element
+pub const MAX_PASSPHRASE_LEN: usize = 32;
+
+const HEADER_WIDGET: Widget = Widget::new(
+    Rectangle{
+        top_left: Point{
+            x: 0,
+            y: GAP as i32,
+        },
+        size: Size{
+            width: SCREEN_SIZE_X,
+            height: 13,
+        }
+    },
+    SCREEN_ZERO
+);
+
+const TEXT_TOP_LEFT: Point = Point{
+    x: 0,
+    y: HEADER_WIDGET.bounds.top_left.y + HEADER_WIDGET.bounds.size.height as i32,
+};
+const TEXT_WIDGET: Widget = Widget::new(
+    Rectangle{
+        top_left: TEXT_TOP_LEFT,
+        size: Size{
+            width: SCREEN_SIZE_X,
+            height: KEYBOARD_AREA.top_left.y as u32 - TEXT_TOP_LEFT.y as u32,
+        }
+    },
+    SCREEN_ZERO
+);
+
+enum Stage {
+    Entry,
+    Remember,
+}
+
+/// Wallet chosen by user
+pub enum WalletChoice {
+    /// Keep currently active wallet
+    Keep,
+    /// Open wallet with passphrase, empty for main wallet; keep passphrase in flash if set
+    Open(Zeroizing<String>, bool),
+}
+
+pub struct PassphraseEntry {
+    passphrase: Zeroizing<String>,
+    keyboard: Keyboard,
+    remove: Key,
+    navbar: NavBar,
+    stage: Stage,
+}
+
+impl PassphraseEntry {
+    pub fn new() -> Self {
+        PassphraseEntry {
+            // reserved in full, so that typing never leaves copies in freed memory
+            passphrase: Zeroizing::new(String::with_capacity(MAX_PASSPHRASE_LEN)),
+            keyboard: Keyboard::new(),
+            remove: Key::new("DEL", &REMOVE_KEY_WIDGET),
+            navbar: NavBar::new(("skip", "open")),
+            stage: Stage::Entry,
+        }
+    }
+}
+
+impl ViewScreen for PassphraseEntry {
+    type DrawInput<'a> = ();
+    type DrawOutput = ();
+    type TapInput<'a> = ();
+    type TapOutput = Option<WalletChoice>;
+
+    fn draw_screen<'a, D>(&mut self, target: &mut D, _: ()) -> Result<(EventResult, ()), D::Error>
+    where
+        D: DrawTarget<Color = BinaryColor>,
+        Self: 'a,
+    {
+        match self.stage {
+            Stage::Entry => {
+                let header_style = MonoTextStyle::new(&FONT_8X13_BOLD, BinaryColor::On);
+                let text_style = MonoTextStyle::new(&FONT_8X13, BinaryColor::On);
+                let textbox_style = TextBoxStyleBuilder::new()
+                    .alignment(HorizontalAlignment::Center)
+                    .vertical_alignment(VerticalAlignment::Middle)
+                    .build();
+
+                TextBox::with_textbox_style(
+                    "Hidden wallet passphrase:",
+                    HEADER_WIDGET.bounds,
+                    header_style,
+                    textbox_style,
+                ).draw(target)?;
+                TextBox::with_textbox_style(
+                    &self.passphrase,
+                    TEXT_WIDGET.bounds,
+                    text_style,
+                    textbox_style,
+                ).draw(target)?;
+                self.remove.draw(target, false)?;
+                self.keyboard.draw(target, false)?;
+            },
+            Stage::Remember => {
+                message::draw(target, "Remember passphrase on this device?", false)?;
+            },
+        }
+        self.navbar.draw(target, false)?;
+
+        Ok((EventResult{request: None, state: None}, ()))
+    }
+
+    fn handle_tap_screen<'a>(&mut self, point: Point, _: ()) -> (EventResult, Self::TapOutput)
+    where
+        Self: 'a
+    {
+        let mut request = None;
+        let mut choice = None;
+
+        match self.stage {
+            Stage::Entry => {
+                if let Some(Some(c)) = self.keyboard.handle_tap(point, ()) {
+                    if self.passphrase.len() < MAX_PASSPHRASE_LEN {
+                        self.passphrase.push(c[0]);
+                    }
+                    request = Some(UpdateRequest::UltraFast);
+                }
+                if self.remove.handle_tap(point, ()).is_some() {
+                    self.passphrase.pop();
+                    request = Some(UpdateRequest::UltraFast);
+                }
+                match self.navbar.handle_tap(point, ()) {
+                    Some(Some(NavCommand::Left)) => {
+                        choice = Some(WalletChoice::Keep);
+                    },
+                    Some(Some(NavCommand::Right)) => {
+                        if self.passphrase.is_empty() {
+                            choice = Some(WalletChoice::Open(Zeroizing::new(String::new()), false));
+                        } else {
+                            self.stage = Stage::Remember;
+                            self.navbar = NavBar::new(("no", "yes"));
+                            request = Some(UpdateRequest::Fast);
+                        }
+                    },
+                    _ => {},
+                }
+            },
+            Stage::Remember => {
+                match self.navbar.handle_tap(point, ()) {
+                    Some(Some(NavCommand::Left)) => {
+                        choice = Some(WalletChoice::Open(core::mem::take(&mut self.passphrase), false));
+                    },
+                    Some(Some(NavCommand::Right)) => {
+                        choice = Some(WalletChoice::Open(core::mem::take(&mut self.passphrase), true));
+                    },
+                    _ => {},
+                }
+            },
+        }
+
+        (EventResult{state: None, request}, choice)
+    }
+}
@@ -1,24 +1,26 @@
 #[cfg(not(feature="std"))]
-use alloc::vec::Vec;
+use alloc::{boxed::Box, format, vec::Vec};
 #[cfg(feature="std")]
-use std::vec::Vec;
+use std::{boxed::Box, format, vec::Vec};
 
 use embedded_graphics::{
-    pixelcolor::BinaryColor, prelude::{Drawable, DrawTarget, Point}, primitives::{Primitive, PrimitiveStyle}
+    mono_font::{ascii::FONT_6X10, MonoTextStyle},
+    pixelcolor::BinaryColor, prelude::{Drawable, DrawTarget, Point}, primitives::{Primitive, PrimitiveStyle},
+    text::{Baseline, Text},
 };
 
-use crate::{message, uistate::UpdateRequest, widget::view::ViewScreen};
+use crate::{message, uistate::{UnitScreen, UpdateRequest}, widget::view::ViewScreen};
 use crate::uistate::EventResult;
 use crate::widget::view::View;
-use crate::platform::{PinCode, Platform};
+use crate::platform::{PinCheck, PinCode, Platform, MAX_PIN_ATTEMPTS, MAX_PIN_LEN, MIN_PIN_LEN};
+use crate::display_def::*;
 
 use crate::pin::{
+    pinbutton::PinKey,
     pinpad::Pinpad,
     pindots::Pindots,
 };
 
-pub const PIN_LEN: usize = 4;
-
 #[derive(Debug)]
 enum PinpadState {
     Initial,
@@ -27,6 +29,7 @@ enum PinpadState {
     DrawTapped,
     DrawWrong,
     DrawOk,
+    DrawWiped,
 }
 
 pub struct Pincode<P> where
@@ -34,35 +37,38 @@ pub struct Pincode<P> where
 {
     pinpad: Pinpad<P>,
     pindots: Pindots,
-    entered_nums: Vec<u8>,
+    entered_nums: PinCode,
     tapped: PinpadState,
-    pinok: bool,
+    check: Option<PinCheck>,
+    attempts_left: u8,
 }
 
 impl<P> Pincode<P> where
     P: Platform
 {
-    pub fn new(h: &mut <P as Platform>::HAL) -> Self {
+    pub fn new(h: &mut <P as Platform>::HAL, attempts_left: u8) -> Self {
         Self {
             pinpad: Pinpad::new(h),
             pindots: Pindots::new(),
-            entered_nums: Vec::new(),
+            entered_nums: PinCode::default(),
             tapped: PinpadState::Initial,
-            pinok: false,
+            check: None,
+            attempts_left,
         }
     }
-    fn check_pin(&mut self, pin: &PinCode) {
-        // TODO: proper attempt counter and pin check
-        if self.entered_nums.len() == pin.len() {
-            if self.entered_nums == pin {
-                self.pinok = true;
+    fn check_pin(&mut self, platform: &mut P) {
+        if self.entered_nums.len() >= MIN_PIN_LEN {
+            let check = platform.check_pin(&self.entered_nums);
+            if let PinCheck::Wrong{attempts_left} = check {
+                self.attempts_left = attempts_left;
             }
+            self.check = Some(check);
             self.tapped = PinpadState::TappedLast;
-            self.entered_nums = Vec::new();
+            self.entered_nums = PinCode::default();
         }
     }
     fn push_entered(&mut self, num: u8) {
-        if self.entered_nums.len() < PIN_LEN {
+        if self.entered_nums.len() < MAX_PIN_LEN {
             self.entered_nums.push(num);
         }
     }
@@ -74,11 +80,11 @@ impl<P> Pincode<P> where
                 true
             },
             PinpadState::TappedLast => {
-                if self.pinok {
-                    self.tapped = PinpadState::DrawOk;
-                } else {
-                    self.tapped = PinpadState::DrawWrong;
-                }
+                self.tapped = match self.check {
+                    Some(PinCheck::Ok) => PinpadState::DrawOk,
+                    Some(PinCheck::Wiped) => PinpadState::DrawWiped,
+                    _ => PinpadState::DrawWrong,
+                };
                 true
             },
             PinpadState::DrawTapped => {
@@ -95,7 +101,7 @@ impl<P> ViewScreen for Pincode<P> where
 {
     type DrawInput<'a> = &'a mut <P as Platform>::HAL where P: 'a;
     type DrawOutput = bool;
-    type TapInput<'a> = &'a PinCode where P: 'a;
+    type TapInput<'a> = &'a mut P where P: 'a;
     type TapOutput = ();
     fn draw_screen<'a, D>(&mut self, target: &mut D, h: Self::DrawInput<'a>) -> Result<(EventResult, Self::DrawOutput), D::Error>
     where
@@ -103,14 +109,37 @@ impl<P> ViewScreen for Pincode<P> where
         Self: 'a,
     {
         let mut request = None;
-        let state = None;
+        let mut state = None;
 
         if matches!(self.tapped, PinpadState::DrawWrong) {
-            message::draw(target, "Pin is wrong", false)?;
+            let attempts_left = match self.check {
+                Some(PinCheck::Wrong{attempts_left}) => attempts_left,
+                _ => 0,
+            };
+            message::draw(target, &format!("Pin is wrong\n{attempts_left} attempts left"), false)?;
             request = Some(UpdateRequest::Fast);
             self.tapped = PinpadState::Initial;
             return Ok((EventResult {request, state}, false))
         }
+        if matches!(self.tapped, PinpadState::DrawWiped) {
+            request = Some(UpdateRequest::Fast);
+            state = Some(UnitScreen::ShowDialog(
+                "Too many wrong PIN attempts. Seed is erased.",
+                ("", "ok"),
+                (
+                    Box::new(|| EventResult {
+                        request: Some(UpdateRequest::Fast),
+                        state: Some(UnitScreen::OnboardingRestoreOrGenerate),
+                    }),
+                    Box::new(|| EventResult {
+                        request: Some(UpdateRequest::Fast),
+                        state: Some(UnitScreen::OnboardingRestoreOrGenerate),
+                    }),
+                ),
+                true,
+            ));
+            return Ok((EventResult {request, state}, false))
+        }
         if matches!(self.tapped, PinpadState::DrawOk) {
             message::draw(target, "Pin is Ok", false)?;
             return Ok((EventResult {request, state}, true))
@@ -127,24 +156,35 @@ impl<P> ViewScreen for Pincode<P> where
         self.pindots.draw(target, (self.entered_nums.len(), t))?;
         self.pinpad.draw(target, (t, h))?;
 
+        if !t && self.attempts_left < MAX_PIN_ATTEMPTS {
+            Text::with_baseline(
+                &format!("{} tries left", self.attempts_left),
+                Point::new(GAP as i32, 0),
+                MonoTextStyle::new(&FONT_6X10, BinaryColor::On),
+                Baseline::Top,
+            ).draw(target)?;
+        }
+
         if t {
             request = Some(UpdateRequest::UltraFast);
         }
 
         Ok((EventResult { request, state }, false))
     }
-    fn handle_tap_screen<'a>(&mut self, point: Point, pin: Self::TapInput<'a>) -> (EventResult, Self::TapOutput)
+    fn handle_tap_screen<'a>(&mut self, point: Point, platform: Self::TapInput<'a>) -> (EventResult, Self::TapOutput)
     where Self: 'a {
         let state = None;
         let mut request = None;
         if !matches!(self.tapped, PinpadState::Initial) { // ignore taps until permutated
             return (EventResult{ request, state }, ());
         }
-        if let Some(b) = self.pinpad.handle_tap(point, ()) {
+        if let Some(Some(b)) = self.pinpad.handle_tap(point, ()) {
             self.tapped = PinpadState::Tapped;
             request = Some(UpdateRequest::UltraFast);
-            self.push_entered(self.pinpad.buttons[b].num());
-            self.check_pin(pin);
+            match self.pinpad.buttons[b].key() {
+                PinKey::Num(num) => self.push_entered(num),
+                PinKey::Ok => self.check_pin(platform),
+            }
         }
 
         (EventResult{ request, state }, ())
@@ -1,7 +1,7 @@
 #[cfg(not(feature="std"))]
-use alloc::string::ToString;
+use alloc::string::{String, ToString};
 #[cfg(feature="std")]
-use std::string::ToString;
+use std::string::{String, ToString};
 
 use embedded_graphics::{
 	pixelcolor::BinaryColor,
@@ -29,17 +29,24 @@ const BUTTON_FONT: MonoFont = FONT_10X20;
 const BUTTON_RADIUS: u32 = 6;
 const BUTTON_BORDER_OFFSET: i32 = -2;
 
+#[derive(Clone, Copy, Debug)]
+pub enum PinKey {
+    Num(u8),
+    /// Submit PIN of variable length
+    Ok,
+}
+
 #[derive(Debug)]
 pub struct PinButton {
-	num: u8,
+	key: PinKey,
 	widget: &'static Widget,
     this_tapped: bool,
 }
 
 impl PinButton {
-	pub fn new(num: u8, widget: &'static Widget) -> Self {
+	pub fn new(key: PinKey, widget: &'static Widget) -> Self {
 		Self {
-			num,
+			key,
 			widget,
             this_tapped: false,
 		}
@@ -52,8 +59,14 @@ impl PinButton {
             false
         }
     }
-    pub fn num(&self) -> u8 {
-        self.num
+    pub fn key(&self) -> PinKey {
+        self.key
+    }
+    fn label(&self) -> String {
+        match self.key {
+            PinKey::Num(num) => num.to_string(),
+            PinKey::Ok => "OK".to_string(),
+        }
     }
 }
 
@@ -114,7 +127,7 @@ impl PinButton {
                 .build();
     
             TextBox::with_textbox_style(
-                &self.num.to_string(),
+                &self.label(),
                 bounds,
                 character_style,
                 textbox_style,
@@ -141,7 +154,7 @@ impl PinButton {
             .build();
     
         TextBox::with_textbox_style(
-            &self.num.to_string(),
+            &self.label(),
             area,
             character_style,
             textbox_style,
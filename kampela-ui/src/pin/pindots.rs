@@ -9,12 +9,12 @@ use embedded_graphics::{
 };
 
 use crate::{display_def::*, widget::view::{View, Widget, DrawView}};
-use crate::pin::pin::PIN_LEN;
+use crate::platform::{MAX_PIN_LEN, MIN_PIN_LEN};
 
-const DOT_DIAMETER: u32 = 16;
+const DOT_DIAMETER: u32 = 12;
 const DOT_SPACING: u32 = 2;
 pub const PINDOT_SIZE: Size = Size {
-    width: DOT_DIAMETER * PIN_LEN as u32 + DOT_SPACING * 3,
+    width: DOT_DIAMETER * MAX_PIN_LEN as u32 + DOT_SPACING * (MAX_PIN_LEN as u32 - 1),
     height: DOT_DIAMETER,
 };
 
@@ -67,10 +67,14 @@ impl View for Pindots {
             .build();
         let filled = PrimitiveStyle::with_fill(on);
         let area = self.bounding_box_view();
-        for i in 0..PIN_LEN {
+        // empty dots for shortest PIN, then one more dot for each extra digit
+        let count = core::cmp::max(dots, MIN_PIN_LEN);
+        let width = DOT_DIAMETER * count as u32 + DOT_SPACING * (count as u32 - 1);
+        let left = area.top_left.x + (area.size.width - width) as i32 / 2;
+        for i in 0..count {
             let dot = Circle::new(
                 Point {
-                    x: area.top_left.x + i as i32 * (DOT_DIAMETER as i32 + DOT_SPACING as i32),
+                    x: left + i as i32 * (DOT_DIAMETER as i32 + DOT_SPACING as i32),
                     y: area.top_left.y
                 },
                 DOT_DIAMETER
@@ -8,7 +8,7 @@ use embedded_graphics::{
 
 use rand::seq::SliceRandom;
 use crate::{display_def::*, platform::Platform, widget::view::{DrawView, View, Widget}};
-use crate::pin::{pinbutton::PinButton, pindots::PINDOT_SIZE};
+use crate::pin::{pinbutton::{PinButton, PinKey}, pindots::PINDOT_SIZE};
 
 const PAD_SIZE_WIDTH: u32 = 200;
 
@@ -30,21 +30,25 @@ const BUTTON_SIZE: Size = Size {
     height: PINPAD_AREA.size.height / 4,
 };
 
-const fn get_pinbutton_widgets() -> [Widget; 10] {
-    let mut widgets = [Widget::zero(); 10];
+/// Digit keys take first 10 places, OK key is the last one, right of 0
+const PINPAD_KEYS: usize = 11;
+
+const fn get_pinbutton_widgets() -> [Widget; PINPAD_KEYS] {
+    let mut widgets = [Widget::zero(); PINPAD_KEYS];
     let mut i = 0;
-    while i < 10 {
+    while i < PINPAD_KEYS {
         widgets[i] = Widget::new(Rectangle{
             top_left: Point {
                 x: {
                     match i {
                         0 => BUTTON_SIZE.width as i32,
+                        10 => 2 * BUTTON_SIZE.width as i32,
                         _ => (i as i32 - 1) % 3 * BUTTON_SIZE.width as i32,
                     }
                 },
                 y: {
                     match i {
-                        0 => 3 * BUTTON_SIZE.height as i32,
+                        0 | 10 => 3 * BUTTON_SIZE.height as i32,
                         _ => (i as i32 - 1) / 3 * BUTTON_SIZE.height as i32,
                     }
                 }
@@ -58,17 +62,20 @@ const fn get_pinbutton_widgets() -> [Widget; 10] {
     widgets
 }
 
-const PIN_BUTTON_WIDGETS: [Widget; 10] = get_pinbutton_widgets();
-/// Shuffle keys
-fn get_pinbuttons<P: Platform>(h: &mut <P as Platform>::HAL) -> [PinButton; 10] {
+const PIN_BUTTON_WIDGETS: [Widget; PINPAD_KEYS] = get_pinbutton_widgets();
+/// Shuffle digit keys; OK key stays in place
+fn get_pinbuttons<P: Platform>(h: &mut <P as Platform>::HAL) -> [PinButton; PINPAD_KEYS] {
     let mut pinnums: [u8; 10] = core::array::from_fn(|i| {
         (i).try_into()
             .expect("static initialization of numbers 0..15")
     });
     pinnums.shuffle(&mut P::rng(h));
-    let pinset: [PinButton; 10] = array::from_fn(
+    let pinset: [PinButton; PINPAD_KEYS] = array::from_fn(
         |i| PinButton::new(
-            pinnums[i],
+            match pinnums.get(i) {
+                Some(num) => PinKey::Num(*num),
+                None => PinKey::Ok,
+            },
             &PIN_BUTTON_WIDGETS[i],
         )
     );
@@ -79,7 +86,7 @@ fn get_pinbuttons<P: Platform>(h: &mut <P as Platform>::HAL) -> [PinButton; 10]
 pub struct Pinpad<P> where
     P: Platform
 {
-    pub buttons: [PinButton; 10],
+    pub buttons: [PinButton; PINPAD_KEYS],
     input_type: PhantomData<P>,
 }
 
@@ -87,7 +94,7 @@ impl<P> Pinpad<P> where
     P: Platform
 {
 	pub fn new(h: &mut <P as Platform>::HAL) -> Self {
-        let buttons: [PinButton; 10] = get_pinbuttons::<P>(h);
+        let buttons: [PinButton; PINPAD_KEYS] = get_pinbuttons::<P>(h);
 		Self {
             buttons,
             input_type: PhantomData::<P>::default(),
@@ -105,7 +112,8 @@ impl<P> View for Pinpad<P> where
     type DrawInput<'a> = (bool, &'a mut <P as Platform>::HAL) where Self: 'a;
     type DrawOutput = ();
     type TapInput<'a> = () where Self: 'a,;
-    type TapOutput = usize;
+    /// Index of tapped button, if any
+    type TapOutput = Option<usize>;
     fn bounding_box(&self) -> Rectangle {
         PINPAD_WIDGET.bounding_box()
     }
@@ -122,12 +130,12 @@ impl<P> View for Pinpad<P> where
         }
         Ok(())
 	}
-    fn handle_tap_view<'a>(&mut self, point: Point, _: ()) -> usize
+    fn handle_tap_view<'a>(&mut self, point: Point, _: ()) -> Option<usize>
     where Self: 'a {
-        let mut tapped = 0;
+        let mut tapped = None;
         for (i, button) in self.buttons.iter_mut().enumerate() {
             if button.handle_tap(point, ()).is_some() {
-                tapped = i;
+                tapped = Some(i);
             }
         }
         tapped
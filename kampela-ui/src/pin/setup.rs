@@ -0,0 +1,177 @@
+//! Screen to create new PIN, entered twice; PIN length is chosen by user
+
+#[cfg(not(feature="std"))]
+use alloc::vec::Vec;
+#[cfg(feature="std")]
+use std::vec::Vec;
+
+use embedded_graphics::{
+    mono_font::{ascii::FONT_6X10, MonoTextStyle},
+    pixelcolor::BinaryColor, prelude::{Drawable, DrawTarget, Point}, primitives::{Primitive, PrimitiveStyle},
+    text::{Alignment, Baseline, Text, TextStyleBuilder},
+};
+
+use crate::{message, uistate::UpdateRequest, widget::view::ViewScreen};
+use crate::uistate::EventResult;
+use crate::widget::view::View;
+use crate::platform::{PinCode, Platform, MAX_PIN_LEN, MIN_PIN_LEN};
+use crate::display_def::*;
+
+use crate::pin::{
+    pinbutton::PinKey,
+    pinpad::Pinpad,
+    pindots::Pindots,
+};
+
+#[derive(Debug)]
+enum SetupState {
+    Initial,
+    Tapped,
+    TappedMismatch,
+    DrawTapped,
+    DrawMismatch,
+}
+
+pub struct PinSetup<P> where
+    P: Platform
+{
+    pinpad: Pinpad<P>,
+    pindots: Pindots,
+    entered_nums: PinCode,
+    /// PIN entered first time, waiting for confirmation
+    first: Option<PinCode>,
+    tapped: SetupState,
+}
+
+impl<P> PinSetup<P> where
+    P: Platform
+{
+    pub fn new(h: &mut <P as Platform>::HAL) -> Self {
+        Self {
+            pinpad: Pinpad::new(h),
+            pindots: Pindots::new(),
+            entered_nums: PinCode::default(),
+            first: None,
+            tapped: SetupState::Initial,
+        }
+    }
+    fn push_entered(&mut self, num: u8) {
+        if self.entered_nums.len() < MAX_PIN_LEN {
+            self.entered_nums.push(num);
+        }
+    }
+    /// Take complete PIN; returns it once confirmed
+    fn take_entered(&mut self) -> Option<PinCode> {
+        if self.entered_nums.len() < MIN_PIN_LEN {
+            return None
+        }
+        let pin = core::mem::take(&mut self.entered_nums);
+        match self.first.take() {
+            None => {
+                self.first = Some(pin);
+                None
+            },
+            Some(first) if first == pin => Some(pin),
+            Some(_) => {
+                self.tapped = SetupState::TappedMismatch;
+                None
+            },
+        }
+    }
+    fn switch_tapped(&mut self) -> bool {
+        match self.tapped {
+            SetupState::Initial => false,
+            SetupState::Tapped => {
+                self.tapped = SetupState::DrawTapped;
+                true
+            },
+            SetupState::TappedMismatch => {
+                self.tapped = SetupState::DrawMismatch;
+                true
+            },
+            SetupState::DrawTapped => {
+                self.tapped = SetupState::Initial;
+                false
+            },
+            SetupState::DrawMismatch => false,
+        }
+    }
+}
+
+impl<P> ViewScreen for PinSetup<P> where
+    P: Platform
+{
+    type DrawInput<'a> = &'a mut <P as Platform>::HAL where P: 'a;
+    type DrawOutput = ();
+    type TapInput<'a> = () where P: 'a;
+    /// New PIN, entered twice
+    type TapOutput = Option<PinCode>;
+    fn draw_screen<'a, D>(&mut self, target: &mut D, h: Self::DrawInput<'a>) -> Result<(EventResult, ()), D::Error>
+    where
+        D: DrawTarget<Color = BinaryColor>,
+        Self: 'a,
+    {
+        let state = None;
+        let mut request = None;
+
+        if matches!(self.tapped, SetupState::DrawMismatch) {
+            message::draw(target, "PINs do not match\ntry again", false)?;
+            request = Some(UpdateRequest::Fast);
+            self.tapped = SetupState::Initial;
+            return Ok((EventResult {request, state}, ()))
+        }
+
+        let t = self.switch_tapped();
+        let filled = if t {
+            PrimitiveStyle::with_fill(BinaryColor::On)
+        } else {
+            PrimitiveStyle::with_fill(BinaryColor::Off)
+        };
+        target.bounding_box().into_styled(filled).draw(target)?;
+
+        self.pindots.draw(target, (self.entered_nums.len(), t))?;
+        self.pinpad.draw(target, (t, h))?;
+
+        if !t {
+            let character_style = MonoTextStyle::new(&FONT_6X10, BinaryColor::On);
+            let header = if self.first.is_none() { "new PIN" } else { "repeat PIN" };
+            Text::with_baseline(
+                header,
+                Point::new(GAP as i32, 0),
+                character_style,
+                Baseline::Top,
+            ).draw(target)?;
+            Text::with_text_style(
+                "4-8 digits",
+                Point::new((SCREEN_SIZE_X - GAP) as i32, 0),
+                character_style,
+                TextStyleBuilder::new().alignment(Alignment::Right).baseline(Baseline::Top).build(),
+            ).draw(target)?;
+        }
+
+        if t {
+            request = Some(UpdateRequest::UltraFast);
+        }
+
+        Ok((EventResult { request, state }, ()))
+    }
+    fn handle_tap_screen<'a>(&mut self, point: Point, _: ()) -> (EventResult, Self::TapOutput)
+    where Self: 'a {
+        let state = None;
+        let mut request = None;
+        let mut pin = None;
+        if !matches!(self.tapped, SetupState::Initial) { // ignore taps until permutated
+            return (EventResult{ request, state }, pin);
+        }
+        if let Some(Some(b)) = self.pinpad.handle_tap(point, ()) {
+            self.tapped = SetupState::Tapped;
+            request = Some(UpdateRequest::UltraFast);
+            match self.pinpad.buttons[b].key() {
+                PinKey::Num(num) => self.push_entered(num),
+                PinKey::Ok => pin = self.take_entered(),
+            }
+        }
+
+        (EventResult{ request, state }, pin)
+    }
+}
@@ -1,19 +1,55 @@
 //! Platform definitions
 
 #[cfg(not(feature="std"))]
-use alloc::{string::String, vec::Vec};
+use alloc::{string::String, vec, vec::Vec};
 #[cfg(feature="std")]
-use std::{string::String, vec::Vec};
+use std::{string::String, vec, vec::Vec};
 
+use blake2::{digest::consts::U32, Blake2b, Digest};
 use rand::{CryptoRng, Rng};
+use zeroize::{Zeroize, Zeroizing};
 
-use substrate_crypto_light::sr25519::{Pair, Public};
+use substrate_crypto_light::{common::FullDerivation, ecdsa, ed25519, sr25519};
 use substrate_parser::{TransactionUnmarkedParsed, ShortSpecs};
 
 use mnemonic_external::AsWordList;
 
-pub type PinCode = [u8; 4];
-const ENTROPY_LEN: usize = 32; //TODO: move to appropriate place
+use crate::account::Account;
+
+/// PIN digits, `MIN_PIN_LEN` to `MAX_PIN_LEN` of them
+pub type PinCode = Zeroizing<Vec<u8>>;
+
+pub const MIN_PIN_LEN: usize = 4;
+pub const MAX_PIN_LEN: usize = 8;
+
+/// Failed PIN attempts before seed is erased
+pub const MAX_PIN_ATTEMPTS: u8 = 10;
+
+/// Outcome of PIN check
+pub enum PinCheck {
+    Ok,
+    Wrong{attempts_left: u8},
+    /// Last attempt failed, seed is erased
+    Wiped,
+}
+
+/// Compressed SEC1 public key of companion app
+pub type CompanionKey = [u8; 33];
+
+/// Companion app signature status of received payload
+pub enum CompanionCheck {
+    /// Signed by paired companion app
+    Trusted,
+    /// Signed correctly, but by unknown companion app
+    Untrusted(CompanionKey),
+    /// No companion signature in payload
+    Unsigned,
+}
+/// Number of words in seed phrases that could be generated or restored
+pub const MNEMONIC_LENGTHS: [usize; 5] = [12, 15, 18, 21, 24];
+
+/// Longer payloads are signed as their blake2b-256 hash
+pub const MAX_UNHASHED_PAYLOAD_LEN: usize = 256;
 
 /// Implement this on platform to make crate work
 pub trait Platform {
@@ -36,23 +72,34 @@ pub trait Platform {
     /// RNG getter
     fn rng(h: &mut Self::HAL) -> Self::Rng<'_>;
 
-    /// Device-specific "global" storage and management of pincode state RO
-    fn pin(&self) -> &PinCode;
+    /// Check entered PIN against stored one, counting failed attempts
+    fn check_pin(&mut self, pin: &[u8]) -> PinCheck;
+
+    /// Number of PIN attempts left before seed is erased
+    fn pin_attempts_left(&self) -> u8;
+
+    /// Store new PIN
+    fn set_pin(&mut self, pin: &[u8]);
 
-    /// Device-specific "global" storage and management of pincode state RW
-    fn pin_mut(&mut self) -> &mut PinCode;
+    /// Erase seed, PIN and all other stored data; `false` if storage did not
+    /// read back blank
+    fn factory_reset(&mut self) -> bool;
 
     /// Put entropy in flash
     fn store_entropy(&mut self, e: &[u8]);
 
-    /// Read entropy from flash
-    fn read_entropy(&mut self);
+    /// Read entropy from flash; `false` if stored seed is damaged and could
+    /// not be read
+    fn read_entropy(&mut self) -> bool;
 
-    /// Getter for public address
-    fn public(&self) -> Option<Public>;
+    /// Getter for public key of given scheme
+    fn public(&self, scheme: Scheme) -> Option<MultiPublic>;
     
-    /// Getter for seed
-    fn entropy(&self) -> Option<Vec<u8>>;
+    /// Seed is stored, whether or not it could be decrypted before unlock
+    fn has_entropy(&self) -> bool;
+
+    /// Getter for seed; could be `None` until PIN is entered
+    fn entropy(&self) -> Option<Zeroizing<Vec<u8>>>;
 
     fn set_address(&mut self, addr: [u8; 76]);
 
@@ -62,25 +109,171 @@ pub trait Platform {
 
     fn extensions(&mut self) -> Option<String>;
 
-    fn signature(&mut self) -> [u8; 130];
+    /// Hex-encoded MultiSignature over transaction in scheme requested by payload;
+    /// `None` if signer is not an account of active wallet
+    fn signature(&mut self) -> Option<Vec<u8>>;
 
     fn address(&mut self) -> &[u8; 76];
 
+    /// Add companion app key to trusted ones
+    fn trust_companion(&mut self, key: CompanionKey);
+
+    /// Accounts derived from the seed
+    fn accounts(&self) -> &[Account];
+
+    /// Store new account; `false` if it is known already or does not fit the store
+    fn add_account(&mut self, account: Account) -> bool;
+
+    /// BIP39 passphrase of active hidden wallet, empty for main wallet
+    fn passphrase(&self) -> &str;
+
+    /// Switch to wallet with given passphrase; it is kept SE-wrapped in flash
+    /// if `store` is set, and forgotten on power off otherwise
+    fn set_passphrase(&mut self, passphrase: Zeroizing<String>, store: bool);
+
     //----derivatives----
 
-    fn generate_seed_entropy(h: &mut Self::HAL) -> [u8; ENTROPY_LEN] {
-        let mut entropy: [u8; ENTROPY_LEN]= [0; ENTROPY_LEN];
-        Self::rng(h).fill(&mut entropy);
+    /// Entropy for seed phrase of `words` words, 4 bytes per 3 words;
+    /// dice rolls, if any, are hashed together with RNG output
+    fn generate_seed_entropy(h: &mut Self::HAL, words: usize, dice: &[u8]) -> Zeroizing<Vec<u8>> {
+        let mut entropy = Zeroizing::new(vec![0; words / 3 * 4]);
+        Self::rng(h).fill(entropy.as_mut_slice());
+        if !dice.is_empty() {
+            let mut hasher = Blake2b::<U32>::new();
+            hasher.update(entropy.as_slice());
+            hasher.update(dice);
+            let mut hash = hasher.finalize();
+            let len = entropy.len();
+            entropy.copy_from_slice(&hash[..len]);
+            hash.as_mut_slice().zeroize();
+        }
         entropy
     }
 
-    fn pair(&self) -> Option<Pair> {
+    fn pair(&self, scheme: Scheme) -> Option<MultiPair> {
+        let e = self.entropy()?;
+        if e.is_empty() { None } else {
+            MultiPair::from_entropy_and_pwd(scheme, &e, self.passphrase())
+        }
+    }
+
+    fn account_pair(&self, account: &Account) -> Option<MultiPair> {
         let e = self.entropy()?;
         if e.is_empty() { None } else {
-            Pair::from_entropy_and_pwd(&e, "").ok()
+            let mut derivation = account.derivation()?;
+            if !self.passphrase().is_empty() {
+                derivation.password = Some(self.passphrase());
+            }
+            MultiPair::from_entropy_and_full_derivation(account.scheme, &e, derivation)
+        }
+    }
+
+    fn account_public(&self, account: &Account) -> Option<MultiPublic> {
+        self.account_pair(account).map(|pair| pair.public())
+    }
+
+}
+
+/// Signature schemes, numbered as variants of MultiSignature and MultiSigner
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Scheme {
+    Ed25519 = 0,
+    Sr25519 = 1,
+    Ecdsa = 2,
+}
+
+impl Scheme {
+    pub const ALL: [Scheme; 3] = [Scheme::Ed25519, Scheme::Sr25519, Scheme::Ecdsa];
+
+    pub fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0 => Some(Scheme::Ed25519),
+            1 => Some(Scheme::Sr25519),
+            2 => Some(Scheme::Ecdsa),
+            _ => None,
         }
     }
 
+    pub fn id(&self) -> u8 {
+        *self as u8
+    }
+
+    pub fn public_len(&self) -> usize {
+        match self {
+            Scheme::Ed25519 | Scheme::Sr25519 => 32,
+            Scheme::Ecdsa => 33,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Scheme::Ed25519 => "ed25519",
+            Scheme::Sr25519 => "sr25519",
+            Scheme::Ecdsa => "ecdsa",
+        }
+    }
+}
+
+/// Public key tagged with its scheme
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MultiPublic {
+    pub scheme: Scheme,
+    pub key: Vec<u8>,
+}
+
+/// Key pair of any supported scheme
+pub enum MultiPair {
+    Ed25519(ed25519::Pair),
+    Sr25519(sr25519::Pair),
+    Ecdsa(ecdsa::Pair),
+}
+
+impl MultiPair {
+    pub fn from_entropy_and_pwd(scheme: Scheme, entropy: &[u8], pwd: &str) -> Option<Self> {
+        match scheme {
+            Scheme::Ed25519 => ed25519::Pair::from_entropy_and_pwd(entropy, pwd).ok().map(MultiPair::Ed25519),
+            Scheme::Sr25519 => sr25519::Pair::from_entropy_and_pwd(entropy, pwd).ok().map(MultiPair::Sr25519),
+            Scheme::Ecdsa => ecdsa::Pair::from_entropy_and_pwd(entropy, pwd).ok().map(MultiPair::Ecdsa),
+        }
+    }
+
+    pub fn from_entropy_and_full_derivation(scheme: Scheme, entropy: &[u8], derivation: FullDerivation) -> Option<Self> {
+        match scheme {
+            Scheme::Ed25519 => ed25519::Pair::from_entropy_and_full_derivation(entropy, derivation).ok().map(MultiPair::Ed25519),
+            Scheme::Sr25519 => sr25519::Pair::from_entropy_and_full_derivation(entropy, derivation).ok().map(MultiPair::Sr25519),
+            Scheme::Ecdsa => ecdsa::Pair::from_entropy_and_full_derivation(entropy, derivation).ok().map(MultiPair::Ecdsa),
+        }
+    }
+
+    pub fn scheme(&self) -> Scheme {
+        match self {
+            MultiPair::Ed25519(_) => Scheme::Ed25519,
+            MultiPair::Sr25519(_) => Scheme::Sr25519,
+            MultiPair::Ecdsa(_) => Scheme::Ecdsa,
+        }
+    }
+
+    pub fn public(&self) -> MultiPublic {
+        let key = match self {
+            MultiPair::Ed25519(pair) => pair.public().0.to_vec(),
+            MultiPair::Sr25519(pair) => pair.public().0.to_vec(),
+            MultiPair::Ecdsa(pair) => pair.public().0.to_vec(),
+        };
+        MultiPublic { scheme: self.scheme(), key }
+    }
+
+    /// SCALE-encoded MultiSignature: variant id followed by signature.
+    ///
+    /// Only sr25519 signing is randomized, other schemes are deterministic.
+    pub fn sign<R: Rng + CryptoRng>(&self, msg: &[u8], rng: &mut R) -> Vec<u8> {
+        let mut signature_with_id = vec![self.scheme().id()];
+        match self {
+            MultiPair::Ed25519(pair) => signature_with_id.extend_from_slice(&pair.sign(msg).0),
+            MultiPair::Sr25519(pair) => signature_with_id.extend_from_slice(&pair.sign_external_rng(msg, rng).0),
+            MultiPair::Ecdsa(pair) => signature_with_id.extend_from_slice(&pair.sign(msg).0),
+        }
+        signature_with_id
+    }
 }
 
 pub struct NfcTransaction {
@@ -89,3 +282,110 @@ pub struct NfcTransaction {
     pub specs: ShortSpecs,
     pub spec_name: String,
 }
+
+/// Data that is actually signed for transaction: call followed by signed
+/// extensions data, both parts that go into extrinsic and the additional
+/// signed ones.
+///
+/// Payload longer than 256 bytes is replaced with its blake2b-256 hash, as
+/// Substrate chains expect.
+pub fn signing_payload(call: &[u8], extensions: &[u8]) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(call.len() + extensions.len());
+    payload.extend_from_slice(call);
+    payload.extend_from_slice(extensions);
+    if payload.len() > MAX_UNHASHED_PAYLOAD_LEN {
+        Blake2b::<U32>::digest(&payload).to_vec()
+    } else {
+        payload
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Entropy `000102..0f`, no password; seed is the first half of
+    /// `pbkdf2_hmac_sha512(entropy, "mnemonic", 2048)`
+    const ENTROPY: [u8; 16] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15];
+
+    const ED25519_PUBLIC: &str = "754d6911fbd9fe13a26888918589caa5a54187a8896af6530c2c746ad94b5a07";
+    const ECDSA_PUBLIC: &str = "02d5af924b64f71b200bc6e3345251999d76c8f6df9f7ecfea07502fa93b2762bf";
+
+    /// blake2b-256 of 257 bytes `0x05`
+    const LONG_PAYLOAD_HASH: &str = "f5e88000392407fc087a272b4340739891fdb52c40ce67b7379b601a3d82ff1d";
+
+    /// Call and extensions, `len` bytes `0x05` in total
+    fn parts(len: usize) -> (Vec<u8>, Vec<u8>) {
+        (vec![5; 100], vec![5; len - 100])
+    }
+
+    fn pair(scheme: Scheme) -> MultiPair {
+        MultiPair::from_entropy_and_pwd(scheme, &ENTROPY, "").unwrap()
+    }
+
+    #[test]
+    fn payload_of_256_bytes_signed_as_is() {
+        let (call, extensions) = parts(MAX_UNHASHED_PAYLOAD_LEN);
+        assert_eq!(signing_payload(&call, &extensions), vec![5; MAX_UNHASHED_PAYLOAD_LEN]);
+    }
+
+    #[test]
+    fn payload_of_257_bytes_hashed() {
+        let (call, extensions) = parts(MAX_UNHASHED_PAYLOAD_LEN + 1);
+        assert_eq!(hex::encode(signing_payload(&call, &extensions)), LONG_PAYLOAD_HASH);
+    }
+
+    #[test]
+    fn ed25519_signatures() {
+        let pair = pair(Scheme::Ed25519);
+        assert_eq!(pair.public(), MultiPublic { scheme: Scheme::Ed25519, key: hex::decode(ED25519_PUBLIC).unwrap() });
+
+        let expected = [
+            (MAX_UNHASHED_PAYLOAD_LEN, "f5545e2f173f3d4699bd076a7424bcc3865bf78c013e2b6349fb7ece6c2ea62ad1e0c34b857f6ed1c466e1975b161123b520d33926b14ee9cac9fd5dd88c6a0a"),
+            (MAX_UNHASHED_PAYLOAD_LEN + 1, "86b7d3f31e67aaf12bad38c268e4f9f390db8cca53aeab9ffd9cf8528165551af777f1d64fed230e30d7f391ab271ca83d79ee720f79684e3525b4117472230b"),
+        ];
+        for (len, signature) in expected {
+            let (call, extensions) = parts(len);
+            let signed = pair.sign(&signing_payload(&call, &extensions), &mut rand::thread_rng());
+            assert_eq!(signed[0], Scheme::Ed25519.id());
+            assert_eq!(hex::encode(&signed[1..]), signature);
+        }
+    }
+
+    /// Signed is blake2b-256 of payload; signature is recoverable and low-s
+    #[test]
+    fn ecdsa_signatures() {
+        let pair = pair(Scheme::Ecdsa);
+        assert_eq!(pair.public(), MultiPublic { scheme: Scheme::Ecdsa, key: hex::decode(ECDSA_PUBLIC).unwrap() });
+
+        let expected = [
+            (MAX_UNHASHED_PAYLOAD_LEN, "8027553323b8e8c612ac27caa1d21ce8cfc6d87721350fbbad1f7c4c9f7fcab94129a5cd9533f81c0aa1dcad4f5bd123bcf7e91b4b4481710cc2571e45337b0900"),
+            (MAX_UNHASHED_PAYLOAD_LEN + 1, "d5722ea090e254d5e98a93c899a6b60f51ced7ed2c5e6350cc62d5940cd05d854d25297c27f317808246494012e0d039bf3bc6ed504117192f7b94939912d3b901"),
+        ];
+        for (len, signature) in expected {
+            let (call, extensions) = parts(len);
+            let signed = pair.sign(&signing_payload(&call, &extensions), &mut rand::thread_rng());
+            assert_eq!(signed[0], Scheme::Ecdsa.id());
+            assert_eq!(hex::encode(&signed[1..]), signature);
+        }
+    }
+
+    /// Signing is randomized, so signatures are verified instead
+    #[test]
+    fn sr25519_signatures() {
+        let pair = pair(Scheme::Sr25519);
+        let public = schnorrkel::PublicKey::from_bytes(&pair.public().key).unwrap();
+        for len in [MAX_UNHASHED_PAYLOAD_LEN, MAX_UNHASHED_PAYLOAD_LEN + 1] {
+            let (call, extensions) = parts(len);
+            let payload = signing_payload(&call, &extensions);
+            let signed = pair.sign(&payload, &mut rand::thread_rng());
+            assert_eq!(signed[0], Scheme::Sr25519.id());
+            let signature = schnorrkel::Signature::from_bytes(&signed[1..]).unwrap();
+            assert!(public.verify_simple(b"substrate", &payload, &signature).is_ok());
+
+            let mut other_payload = payload.to_vec();
+            other_payload[0] ^= 1;
+            assert!(public.verify_simple(b"substrate", &other_payload, &signature).is_err());
+        }
+    }
+}
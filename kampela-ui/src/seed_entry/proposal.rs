@@ -22,7 +22,7 @@ use embedded_text::{
 use mnemonic_external::{AsWordList, WordListElement};
 const MAX_PROPOSAL: usize = 3;
 
-use crate::{platform::Platform, display_def::*, widget::view::{DrawView, View, Widget}};
+use crate::{display_def::*, widget::view::{DrawView, View, Widget}};
 
 use crate::seed_entry::phrase::PHRASE_AREA;
 
@@ -71,19 +71,19 @@ const PROPOSAL_SECTIONS: [Rectangle; 3] = [
     },
 ];
 
-pub struct Proposal<P> where
-    P: Platform + ?Sized
+pub struct Proposal<L> where
+    L: AsWordList
 {
     pub entered: Vec<Vec<char>>,
     entered_count: usize,
     variants: Vec<String>,
-    guess: Vec<WordListElement<P::AsWordList>>,
+    guess: Vec<WordListElement<L>>,
     guess_depth: usize,
-    wordlist: P::AsWordList,
+    wordlist: L,
 }
 
-impl<P: Platform> Proposal<P> {
-    pub fn new(wordlist: P::AsWordList) -> Self {
+impl<L: AsWordList> Proposal<L> {
+    pub fn new(wordlist: L) -> Self {
         Proposal {
             entered: Vec::new(),
             entered_count: 0,
@@ -124,7 +124,7 @@ impl<P: Platform> Proposal<P> {
     }
 
     fn make_guess(&mut self) {
-        let mut guess = Vec::<WordListElement<P::AsWordList>>::new();
+        let mut guess = Vec::<WordListElement<L>>::new();
         if self.variants.len() < 4 {
             self.variants = Vec::new();
         }
@@ -156,11 +156,11 @@ impl<P: Platform> Proposal<P> {
     }
 }
 
-impl<P: Platform> View for Proposal<P> {
-    type DrawInput<'a> = (bool, bool) where P: 'a;
+impl<L: AsWordList> View for Proposal<L> {
+    type DrawInput<'a> = (bool, bool) where L: 'a;
     type DrawOutput = ();
-    type TapInput<'a> = () where P: 'a;
-    type TapOutput = Option<WordListElement<P::AsWordList>>;
+    type TapInput<'a> = () where L: 'a;
+    type TapOutput = Option<WordListElement<L>>;
 
     fn bounding_box(&self) -> Rectangle {
         PROPOSAL_WIDGET.bounding_box()
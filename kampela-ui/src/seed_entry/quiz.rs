@@ -0,0 +1,258 @@
+//! Check that seed phrase was written down, asking for some of its words
+
+#[cfg(not(feature="std"))]
+use alloc::{format, string::String, vec::Vec};
+#[cfg(feature="std")]
+use std::{format, string::String, vec::Vec};
+
+use core::marker::PhantomData;
+
+use embedded_graphics::{
+    geometry::Point,
+    mono_font::{ascii::FONT_10X20, MonoTextStyle},
+    pixelcolor::BinaryColor,
+    prelude::{DrawTarget, Drawable},
+    primitives::{Primitive, PrimitiveStyle}
+};
+use embedded_text::{
+    alignment::{HorizontalAlignment, VerticalAlignment},
+    style::TextBoxStyleBuilder,
+    TextBox,
+};
+
+use mnemonic_external::AsWordList;
+use rand::seq::SliceRandom;
+
+use crate::{
+    platform::Platform,
+    widget::{
+        view::{View, ViewScreen},
+        nav_bar::nav_bar::{NavBar, NavCommand}
+    },
+    uistate::{EventResult, UpdateRequest},
+};
+
+use crate::seed_entry::{
+    keyboard::{Keyboard, REMOVE_KEY_WIDGET},
+    key::Key,
+    entry::Entry,
+    proposal::Proposal,
+    phrase::PHRASE_AREA,
+};
+
+/// Number of words asked
+pub const QUIZ_WORDS: usize = 3;
+
+enum KeyboardState {
+    Initial,
+    Tapped,
+    DrawTapped,
+    InitialInverse,
+}
+
+/// Result of quiz, once known
+pub enum QuizOutcome {
+    /// All words are right
+    Passed,
+    /// Some word is wrong
+    Failed,
+    /// User went back to seed phrase
+    Cancelled,
+}
+
+/// Quiz over words of wordlist `L`, seed phrase words by default
+pub struct Quiz<P, L = <P as Platform>::AsWordList> where
+    P: Platform,
+    L: AsWordList,
+{
+    /// What is checked, e.g. "backup" or "share 2"
+    name: String,
+    /// Asked words with their positions in phrase, in phrase order
+    questions: Vec<(usize, String)>,
+    answered: usize,
+    phrase_len: usize,
+    entry: Entry,
+    keyboard: Keyboard,
+    remove: Key,
+    proposal: Proposal<L>,
+    platform_type: PhantomData<P>,
+    navbar_entry: NavBar,
+    navbar_question: NavBar,
+    tapped: KeyboardState,
+    negative: bool,
+}
+
+impl<P: Platform, L: AsWordList> Quiz<P, L> {
+    pub fn new(name: &str, phrase: &[&str], wordlist: L, h: &mut <P as Platform>::HAL) -> Self {
+        let mut positions: Vec<usize> = (0..phrase.len()).collect();
+        positions.shuffle(&mut P::rng(h));
+        positions.truncate(QUIZ_WORDS);
+        positions.sort();
+        Quiz {
+            name: String::from(name),
+            questions: positions
+                .into_iter()
+                .map(|i| (i, String::from(phrase[i])))
+                .collect(),
+            answered: 0,
+            phrase_len: phrase.len(),
+            entry: Entry::new(),
+            keyboard: Keyboard::new(),
+            remove: Key::new("DEL", &REMOVE_KEY_WIDGET),
+            proposal: Proposal::new(wordlist),
+            navbar_entry: NavBar::new(("clear", "")),
+            navbar_question: NavBar::new(("back", "")),
+            tapped: KeyboardState::Initial,
+            negative: false,
+            platform_type: PhantomData::<P>::default(),
+        }
+    }
+    fn switch_tapped(&mut self) -> bool {
+        match self.tapped {
+            KeyboardState::Initial => false,
+            KeyboardState::Tapped => {
+                if self.negative {
+                    self.tapped = KeyboardState::InitialInverse;
+                } else {
+                    self.tapped = KeyboardState::DrawTapped;
+                }
+                self.negative = !self.negative;
+                true
+            },
+            KeyboardState::DrawTapped => {
+                self.tapped = KeyboardState::Initial;
+                self.negative = false;
+                false
+            },
+            KeyboardState::InitialInverse => {
+                self.tapped = KeyboardState::DrawTapped;
+                self.negative = true;
+                false
+            }
+        }
+    }
+    fn draw_question<D: DrawTarget<Color = BinaryColor>>(&self, target: &mut D) -> Result<(), D::Error> {
+        let on = if self.negative { BinaryColor::Off } else { BinaryColor::On };
+        let textbox_style = TextBoxStyleBuilder::new()
+            .alignment(HorizontalAlignment::Center)
+            .vertical_alignment(VerticalAlignment::Middle)
+            .build();
+        let (position, _) = self.questions[self.answered];
+        TextBox::with_textbox_style(
+            &format!(
+                "Check {} {}/{}\nword #{} of {}?",
+                self.name,
+                self.answered + 1,
+                self.questions.len(),
+                position + 1,
+                self.phrase_len,
+            ),
+            PHRASE_AREA,
+            MonoTextStyle::new(&FONT_10X20, on),
+            textbox_style,
+        ).draw(target)?;
+        Ok(())
+    }
+}
+
+impl<P: Platform, L: AsWordList> ViewScreen for Quiz<P, L> {
+    type DrawInput<'a> = () where P: 'a, L: 'a;
+    type DrawOutput = ();
+    type TapInput<'a> = () where P: 'a, L: 'a;
+    type TapOutput = Option<QuizOutcome>;
+
+    fn draw_screen<'a, D>(&mut self, target: &mut D, _: ()) -> Result<(EventResult, ()), D::Error>
+    where
+        D: DrawTarget<Color = BinaryColor>,
+        Self: 'a,
+    {
+        let state = None;
+        let mut request = None;
+
+        let t = self.switch_tapped();
+
+        let filled = if self.negative {
+            PrimitiveStyle::with_fill(BinaryColor::On)
+        } else {
+            PrimitiveStyle::with_fill(BinaryColor::Off)
+        };
+        target.bounding_box().into_styled(filled).draw(target)?;
+
+        self.remove.draw(target, self.negative)?;
+        self.keyboard.draw(target, self.negative)?;
+
+        if self.entry.is_empty() {
+            self.draw_question(target)?;
+            self.navbar_question.draw(target, self.negative)?;
+        } else {
+            self.entry.draw(target, self.negative)?;
+            self.proposal.draw(target, (t, self.negative))?;
+            self.navbar_entry.draw(target, self.negative)?;
+        }
+
+        match self.tapped {
+            KeyboardState::DrawTapped |
+            KeyboardState::InitialInverse => {
+                request = Some(UpdateRequest::UltraFast);
+            },
+            _ => {},
+        }
+        Ok((EventResult { request, state }, ()))
+    }
+
+    fn handle_tap_screen<'a>(&mut self, point: Point, _: ()) -> (EventResult, Self::TapOutput)
+    where
+        Self: 'a
+    {
+        let state = None;
+        let mut request = None;
+        let mut outcome = None;
+
+        if let Some(Some(c)) = self.keyboard.handle_tap(point, ()) {
+            if !self.entry.is_maxed() {
+                self.entry.add_letter(c[0]);
+                self.proposal.add_letters(c);
+            } else {
+                self.entry.set_invalid();
+            }
+            self.tapped = KeyboardState::Tapped;
+            request = Some(UpdateRequest::UltraFast);
+        };
+
+        if !self.entry.is_empty() {
+            if self.remove.handle_tap(point, ()).is_some() {
+                self.proposal.remove_letter();
+                self.entry.remove_letter();
+                self.tapped = KeyboardState::Tapped;
+                request = Some(UpdateRequest::UltraFast);
+            }
+        }
+        if let Some(Some(guess)) = self.proposal.handle_tap(point, ()) {
+            self.entry.clear();
+            let (_, ref word) = self.questions[self.answered];
+            if guess.word.as_ref() != word.as_str() {
+                outcome = Some(QuizOutcome::Failed);
+            } else {
+                self.answered += 1;
+                if self.answered == self.questions.len() {
+                    outcome = Some(QuizOutcome::Passed);
+                }
+            }
+            request = Some(UpdateRequest::Fast);
+        }
+        if self.entry.is_empty() {
+            if matches!(self.navbar_question.handle_tap(point, ()), Some(Some(NavCommand::Left))) {
+                outcome = Some(QuizOutcome::Cancelled);
+                request = Some(UpdateRequest::Fast);
+            }
+        } else {
+            if matches!(self.navbar_entry.handle_tap(point, ()), Some(Some(NavCommand::Left))) {
+                self.entry.clear();
+                self.proposal.clear();
+                request = Some(UpdateRequest::Fast);
+            }
+        }
+
+        (EventResult{ request, state }, outcome)
+    }
+}
@@ -11,6 +11,7 @@ use embedded_graphics::{
 };
 
 use mnemonic_external::{AsWordList, Bits11, WordListElement, WordSet};
+use zeroize::Zeroizing;
 
 use crate::{
     platform::Platform,
@@ -42,7 +43,7 @@ pub struct SeedEntry<P> where
     entry: Entry,
     keyboard: Keyboard,
     remove: Key,
-    proposal: Proposal<P>,
+    proposal: Proposal<P::AsWordList>,
     phrase: Phrase<P>,
     navbar_entry: NavBar,
     navbar_phrase: NavBar,
@@ -75,8 +76,8 @@ impl<P: Platform> SeedEntry<P> {
         Self::update_navbar_phrase(&mut state);
         state
     }
-    pub fn get_entropy(&self) -> Option<Vec<u8>> {
-        self.phrase.validate()
+    pub fn get_entropy(&self) -> Option<Zeroizing<Vec<u8>>> {
+        self.phrase.validate().map(Zeroizing::new)
     }
     pub fn get_buffer(&self) -> WordSet {
         WordSet {
@@ -113,6 +114,8 @@ impl<P: Platform> SeedEntry<P> {
     fn update_navbar_phrase(&mut self) {
         if self.phrase.validate().is_some() {
             self.navbar_phrase = NavBar::new(("back", "next"))
+        } else if self.phrase.is_empty() {
+            self.navbar_phrase = NavBar::new(("back", "shares"))
         } else {
             self.navbar_phrase = NavBar::new(("back", ""))
         }
@@ -235,9 +238,13 @@ impl<P: Platform> ViewScreen for SeedEntry<P> {
                             request = Some(UpdateRequest::UltraFast);
                         }
                     },
+                    NavCommand::Right if self.phrase.is_empty() => {
+                        state = Some(UnitScreen::OnboardingRestoreShares);
+                        request = Some(UpdateRequest::Fast);
+                    },
                     NavCommand::Right => {
                         if let Some(e) = self.get_entropy() {
-                            state = Some(UnitScreen::OnboardingBackup(Some(e)));
+                            state = Some(UnitScreen::OnboardingBackup(e));
                             request = Some(UpdateRequest::Fast);
                         } else {
                             self.phrase.set_invalid();
@@ -0,0 +1,291 @@
+//! Restore seed from SLIP-39 shares
+
+#[cfg(not(feature="std"))]
+use alloc::{format, string::String, vec::Vec};
+#[cfg(feature="std")]
+use std::{format, string::String, vec::Vec};
+
+use embedded_graphics::{
+    geometry::Point,
+    mono_font::{ascii::FONT_6X12, MonoTextStyle},
+    pixelcolor::BinaryColor,
+    prelude::{DrawTarget, Drawable},
+    primitives::{Primitive, PrimitiveStyle},
+};
+use embedded_text::{
+    alignment::{HorizontalAlignment, VerticalAlignment},
+    style::TextBoxStyleBuilder,
+    TextBox,
+};
+
+use zeroize::Zeroizing;
+
+use crate::{
+    slip39::{
+        share::{combine_shares, Share, Slip39Error, MAX_MNEMONIC_WORDS, MIN_MNEMONIC_WORDS},
+        wordlist::Slip39WordList,
+    },
+    widget::{
+        view::{View, ViewScreen},
+        nav_bar::nav_bar::{NavBar, NavCommand}
+    },
+    uistate::{EventResult, UpdateRequest, UnitScreen},
+};
+
+use crate::seed_entry::{
+    keyboard::{Keyboard, REMOVE_KEY_WIDGET},
+    key::Key,
+    entry::Entry,
+    proposal::Proposal,
+    phrase::PHRASE_AREA,
+};
+
+/// Characters of share words shown above keyboard
+const SHOWN_WORDS_LEN: usize = 150;
+
+enum KeyboardState {
+    Initial,
+    Tapped,
+    DrawTapped,
+    InitialInverse,
+}
+
+pub struct ShareEntry {
+    /// Words of share being entered
+    words: Vec<&'static str>,
+    /// Shares entered so far
+    shares: Vec<Share>,
+    /// Result of last complete share
+    status: Option<String>,
+    entry: Entry,
+    keyboard: Keyboard,
+    remove: Key,
+    proposal: Proposal<Slip39WordList>,
+    navbar_entry: NavBar,
+    navbar_phrase: NavBar,
+    tapped: KeyboardState,
+    negative: bool,
+    invalid: bool,
+}
+
+impl ShareEntry {
+    pub fn new() -> Self {
+        ShareEntry {
+            words: Vec::new(),
+            shares: Vec::new(),
+            status: None,
+            entry: Entry::new(),
+            keyboard: Keyboard::new(),
+            remove: Key::new("DEL", &REMOVE_KEY_WIDGET),
+            proposal: Proposal::new(Slip39WordList),
+            navbar_entry: NavBar::new(("clear", "")),
+            navbar_phrase: NavBar::new(("back", "")),
+            tapped: KeyboardState::Initial,
+            negative: false,
+            invalid: false,
+        }
+    }
+    fn switch_tapped(&mut self) -> bool {
+        match self.tapped {
+            KeyboardState::Initial => false,
+            KeyboardState::Tapped => {
+                if self.negative {
+                    self.tapped = KeyboardState::InitialInverse;
+                } else {
+                    self.tapped = KeyboardState::DrawTapped;
+                }
+                self.negative = !self.negative;
+                true
+            },
+            KeyboardState::DrawTapped => {
+                self.tapped = KeyboardState::Initial;
+                self.negative = false;
+                false
+            },
+            KeyboardState::InitialInverse => {
+                self.tapped = KeyboardState::DrawTapped;
+                self.negative = true;
+                false
+            }
+        }
+    }
+    /// Accept share once its words make valid share; returns entropy when
+    /// enough shares are entered
+    fn try_share(&mut self) -> Option<Zeroizing<Vec<u8>>> {
+        if self.words.len() < MIN_MNEMONIC_WORDS {
+            return None
+        }
+        let share = match Share::from_words(&self.words) {
+            Ok(share) => share,
+            Err(_) => {
+                // share could be longer, wait for more words
+                if self.words.len() >= MAX_MNEMONIC_WORDS {
+                    self.invalid = true;
+                }
+                return None
+            },
+        };
+        self.words = Vec::new();
+        if self.shares.contains(&share) {
+            self.status = Some(String::from("Share already entered"));
+            return None
+        }
+        self.shares.push(share);
+        match combine_shares(&self.shares) {
+            Ok(entropy) => Some(entropy),
+            Err(Slip39Error::NotEnoughShares) => {
+                self.status = Some(format!("{} share(s) accepted", self.shares.len()));
+                None
+            },
+            Err(e @ Slip39Error::UnsupportedSecretLength) => {
+                // the whole set could not restore a seed
+                self.shares = Vec::new();
+                self.status = Some(e.error_text());
+                None
+            },
+            Err(e) => {
+                self.shares.pop();
+                self.status = Some(e.error_text());
+                None
+            },
+        }
+    }
+    fn draw_words<D: DrawTarget<Color = BinaryColor>>(&mut self, target: &mut D) -> Result<(), D::Error> {
+        let on = if self.negative { BinaryColor::Off } else { BinaryColor::On };
+        let mut text = match self.status {
+            Some(ref status) => format!("{status}\n"),
+            None => String::from("Enter SLIP-39 share\n"),
+        };
+        text.push_str(&format!("share {}, word {}:", self.shares.len() + 1, self.words.len() + 1));
+        // last words that fit
+        let mut shown = 0;
+        let mut len = 0;
+        for word in self.words.iter().rev() {
+            if len + word.len() + 1 > SHOWN_WORDS_LEN {
+                break
+            }
+            len += word.len() + 1;
+            shown += 1;
+        }
+        if shown < self.words.len() {
+            text.push_str(" ...");
+        }
+        for word in self.words[self.words.len() - shown..].iter() {
+            text.push(' ');
+            text.push_str(word);
+        }
+        if self.invalid {
+            text = String::from("Invalid share, check words");
+            self.invalid = false;
+        }
+
+        let textbox_style = TextBoxStyleBuilder::new()
+            .alignment(HorizontalAlignment::Left)
+            .vertical_alignment(VerticalAlignment::Top)
+            .build();
+        TextBox::with_textbox_style(
+            &text,
+            PHRASE_AREA,
+            MonoTextStyle::new(&FONT_6X12, on),
+            textbox_style,
+        ).draw(target)?;
+        Ok(())
+    }
+}
+
+impl ViewScreen for ShareEntry {
+    type DrawInput<'a> = ();
+    type DrawOutput = ();
+    type TapInput<'a> = ();
+    type TapOutput = ();
+
+    fn draw_screen<'a, D>(&mut self, target: &mut D, _: ()) -> Result<(EventResult, ()), D::Error>
+    where
+        D: DrawTarget<Color = BinaryColor>,
+        Self: 'a,
+    {
+        let state = None;
+        let mut request = None;
+
+        let t = self.switch_tapped();
+
+        let filled = if self.negative {
+            PrimitiveStyle::with_fill(BinaryColor::On)
+        } else {
+            PrimitiveStyle::with_fill(BinaryColor::Off)
+        };
+        target.bounding_box().into_styled(filled).draw(target)?;
+
+        self.remove.draw(target, self.negative)?;
+        self.keyboard.draw(target, self.negative)?;
+
+        if self.entry.is_empty() {
+            self.draw_words(target)?;
+            self.navbar_phrase.draw(target, self.negative)?;
+        } else {
+            self.entry.draw(target, self.negative)?;
+            self.proposal.draw(target, (t, self.negative))?;
+            self.navbar_entry.draw(target, self.negative)?;
+        }
+
+        match self.tapped {
+            KeyboardState::DrawTapped |
+            KeyboardState::InitialInverse => {
+                request = Some(UpdateRequest::UltraFast);
+            },
+            _ => {},
+        }
+        Ok((EventResult { request, state }, ()))
+    }
+
+    fn handle_tap_screen<'a>(&mut self, point: Point, _: ()) -> (EventResult, ())
+    where
+        Self: 'a
+    {
+        let mut state = None;
+        let mut request = None;
+
+        if let Some(Some(c)) = self.keyboard.handle_tap(point, ()) {
+            if !self.entry.is_maxed() {
+                self.entry.add_letter(c[0]);
+                self.proposal.add_letters(c);
+            } else {
+                self.entry.set_invalid();
+            }
+            self.tapped = KeyboardState::Tapped;
+            request = Some(UpdateRequest::UltraFast);
+        };
+
+        if self.remove.handle_tap(point, ()).is_some() {
+            if self.entry.is_empty() {
+                self.words.pop();
+            } else {
+                self.proposal.remove_letter();
+                self.entry.remove_letter();
+            }
+            self.tapped = KeyboardState::Tapped;
+            request = Some(UpdateRequest::UltraFast);
+        }
+
+        if let Some(Some(guess)) = self.proposal.handle_tap(point, ()) {
+            self.words.push(guess.word);
+            self.entry.clear();
+            if let Some(entropy) = self.try_share() {
+                state = Some(UnitScreen::OnboardingBackup(entropy));
+            }
+            request = Some(UpdateRequest::Fast);
+        }
+        if !self.entry.is_empty() {
+            if matches!(self.navbar_entry.handle_tap(point, ()), Some(Some(NavCommand::Left))) {
+                self.entry.clear();
+                self.proposal.clear();
+                request = Some(UpdateRequest::Fast);
+            }
+        } else if matches!(self.navbar_phrase.handle_tap(point, ()), Some(Some(NavCommand::Left))) {
+            state = Some(UnitScreen::OnboardingRestore(None));
+            request = Some(UpdateRequest::Fast);
+        }
+
+        (EventResult{ request, state }, ())
+    }
+}
@@ -0,0 +1,148 @@
+//! Settings menu reached from address screen
+
+use embedded_graphics::{
+    draw_target::DrawTarget,
+    geometry::{Point, Size},
+    pixelcolor::BinaryColor,
+    mono_font::{
+        ascii::FONT_10X20,
+        MonoTextStyle,
+    },
+    primitives::{Line, Primitive, PrimitiveStyle, Rectangle},
+    transform::Transform,
+    Drawable,
+};
+use embedded_text::{
+    alignment::{HorizontalAlignment, VerticalAlignment},
+    style::TextBoxStyleBuilder,
+    TextBox,
+};
+
+use crate::display_def::*;
+
+use crate::widget::{view::{ViewScreen, Widget}, nav_bar::nav_bar::{NavBar, NavCommand, NAV_BAR_WIDGET}};
+
+use crate::uistate::{EventResult, UpdateRequest, UnitScreen};
+
+#[derive(Clone, Copy)]
+enum Item {
+    Accounts,
+    ChangePin,
+    FactoryReset,
+}
+
+impl Item {
+    const ALL: [Item; 3] = [Item::Accounts, Item::ChangePin, Item::FactoryReset];
+
+    fn name(&self) -> &'static str {
+        match self {
+            Item::Accounts => "Accounts",
+            Item::ChangePin => "Change PIN",
+            Item::FactoryReset => "Factory reset",
+        }
+    }
+
+    fn screen(&self, account_page: usize) -> UnitScreen {
+        match self {
+            Item::Accounts => UnitScreen::AccountList(account_page),
+            Item::ChangePin => UnitScreen::ChangePin,
+            Item::FactoryReset => UnitScreen::FactoryReset,
+        }
+    }
+}
+
+const ITEMS_PER_SCREEN: u32 = 3;
+
+const ROW_SIZE: Size = Size{
+    width: SCREEN_SIZE_X,
+    height: (SCREEN_SIZE_Y - NAV_BAR_WIDGET.bounds.size.height) / ITEMS_PER_SCREEN,
+};
+
+const ROW_WIDGET: Widget = Widget::new(
+    Rectangle{
+        top_left: Point{
+            x: GAP as i32,
+            y: 0,
+        },
+        size: Size{
+            width: ROW_SIZE.width - 2 * GAP,
+            height: ROW_SIZE.height,
+        }
+    },
+    SCREEN_ZERO
+);
+
+pub struct Settings {
+    /// Page of account list with currently shown account
+    account_page: usize,
+    navbar: NavBar,
+}
+
+impl Settings {
+    pub fn new(account_page: usize) -> Self {
+        Settings {
+            account_page,
+            navbar: NavBar::new(("back", "")),
+        }
+    }
+}
+
+impl ViewScreen for Settings {
+    type DrawInput<'a> = ();
+    type DrawOutput = ();
+    type TapInput<'a> = ();
+    type TapOutput = ();
+
+    fn draw_screen<'a, D>(&mut self, target: &mut D, _: ()) -> Result<(EventResult, ()), D::Error>
+    where
+        D: DrawTarget<Color = BinaryColor>,
+        Self: 'a,
+    {
+        let character_style = MonoTextStyle::new(&FONT_10X20, BinaryColor::On);
+        let textbox_style = TextBoxStyleBuilder::new()
+            .alignment(HorizontalAlignment::Left)
+            .vertical_alignment(VerticalAlignment::Middle)
+            .build();
+        let linestyle = PrimitiveStyle::with_stroke(BinaryColor::On, 1);
+
+        for (i, item) in Item::ALL.iter().enumerate() {
+            let offset = Point::new(0, (i as u32 * ROW_SIZE.height) as i32);
+            TextBox::with_textbox_style(
+                item.name(),
+                ROW_WIDGET.bounds.translate(offset),
+                character_style,
+                textbox_style,
+            ).draw(target)?;
+            if i != 0 {
+                Line::new(offset, offset + Point::new(SCREEN_SIZE_X as i32, 0))
+                    .into_styled(linestyle)
+                    .draw(target)?;
+            }
+        }
+        self.navbar.draw(target, false)?;
+
+        Ok((EventResult{request: None, state: None}, ()))
+    }
+
+    fn handle_tap_screen<'a>(&mut self, point: Point, _: ()) -> (EventResult, ())
+    where
+        Self: 'a
+    {
+        let mut state = None;
+        let mut request = None;
+
+        if let Some(c) = self.navbar.handle_tap(point, ()) {
+            if let Some(NavCommand::Left) = c {
+                state = Some(UnitScreen::QRAddress);
+                request = Some(UpdateRequest::Slow);
+            }
+        } else if point.y >= 0 {
+            if let Some(item) = Item::ALL.get(point.y as usize / ROW_SIZE.height as usize) {
+                state = Some(item.screen(self.account_page));
+                request = Some(UpdateRequest::Fast);
+            }
+        }
+
+        (EventResult{state, request}, ())
+    }
+}
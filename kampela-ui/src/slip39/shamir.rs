@@ -0,0 +1,203 @@
+//! Shamir secret sharing over GF(256) as specified in SLIP-39
+
+#[cfg(not(feature="std"))]
+use alloc::{vec, vec::Vec};
+#[cfg(feature="std")]
+use std::{vec, vec::Vec};
+
+use hmac::{Hmac, Mac};
+use rand::{CryptoRng, Rng};
+use sha2::Sha256;
+use zeroize::Zeroizing;
+
+use crate::slip39::share::Slip39Error;
+
+/// Most shares in group, and most groups
+pub const MAX_SHARE_COUNT: u8 = 16;
+
+/// Share index at which shared secret is found
+const SECRET_INDEX: u8 = 255;
+/// Share index at which secret digest is found
+const DIGEST_INDEX: u8 = 254;
+const DIGEST_LEN: usize = 4;
+
+/// Exponent and logarithm tables of GF(256) with Rijndael polynomial, generator 3
+const fn gf_tables() -> ([u8; 255], [u8; 256]) {
+    let mut exp = [0u8; 255];
+    let mut log = [0u8; 256];
+    let mut poly: u16 = 1;
+    let mut i = 0;
+    while i < 255 {
+        exp[i] = poly as u8;
+        log[poly as usize] = i as u8;
+        // multiply by 3, reducing by x^8 + x^4 + x^3 + x + 1
+        poly = (poly << 1) ^ poly;
+        if poly & 0x100 != 0 {
+            poly ^= 0x11B;
+        }
+        i += 1;
+    }
+    (exp, log)
+}
+
+const GF_TABLES: ([u8; 255], [u8; 256]) = gf_tables();
+const EXP: [u8; 255] = GF_TABLES.0;
+const LOG: [u8; 256] = GF_TABLES.1;
+
+/// Value at `x` of polynomial passing through shares `(index, value)`;
+/// share indices must be distinct
+fn interpolate(shares: &[(u8, &[u8])], x: u8) -> Zeroizing<Vec<u8>> {
+    if let Some((_, value)) = shares.iter().find(|(index, _)| *index == x) {
+        return Zeroizing::new(value.to_vec())
+    }
+    let len = shares[0].1.len();
+    // logarithm of product of (x - x_j) over all shares
+    let log_product = shares
+        .iter()
+        .map(|(index, _)| LOG[(index ^ x) as usize] as u32)
+        .sum::<u32>();
+    let mut result = Zeroizing::new(vec![0u8; len]);
+    for (i, (index_i, value)) in shares.iter().enumerate() {
+        let log_denominator = shares
+            .iter()
+            .enumerate()
+            .filter(|(j, _)| *j != i)
+            .map(|(_, (index_j, _))| LOG[(index_i ^ index_j) as usize] as u32)
+            .sum::<u32>();
+        // logarithm of Lagrange basis polynomial at x, kept positive
+        let log_basis = (log_product + 255 * 255 - LOG[(index_i ^ x) as usize] as u32 - log_denominator) % 255;
+        for (r, v) in result.iter_mut().zip(value.iter()) {
+            if *v != 0 {
+                *r ^= EXP[((LOG[*v as usize] as u32 + log_basis) % 255) as usize];
+            }
+        }
+    }
+    result
+}
+
+fn digest(random: &[u8], secret: &[u8]) -> [u8; DIGEST_LEN] {
+    let mut mac = Hmac::<Sha256>::new_from_slice(random).expect("hmac takes key of any length");
+    mac.update(secret);
+    let mut out = [0u8; DIGEST_LEN];
+    out.copy_from_slice(&mac.finalize().into_bytes()[..DIGEST_LEN]);
+    out
+}
+
+/// Split secret into `count` shares, any `threshold` of which recover it;
+/// share values go in order of their indices
+pub fn split_secret<R: Rng + CryptoRng>(threshold: u8, count: u8, secret: &[u8], rng: &mut R) -> Result<Vec<Zeroizing<Vec<u8>>>, Slip39Error> {
+    if threshold == 0 || threshold > count || count > MAX_SHARE_COUNT {
+        return Err(Slip39Error::InvalidThreshold)
+    }
+    if threshold == 1 {
+        return Ok(vec![Zeroizing::new(secret.to_vec()); count as usize])
+    }
+    if secret.len() < DIGEST_LEN {
+        return Err(Slip39Error::InvalidLength)
+    }
+
+    let random_shares = threshold as usize - 2;
+    let mut shares: Vec<Zeroizing<Vec<u8>>> = Vec::with_capacity(count as usize);
+    for _ in 0..random_shares {
+        let mut share = Zeroizing::new(vec![0u8; secret.len()]);
+        rng.fill(share.as_mut_slice());
+        shares.push(share);
+    }
+
+    let mut random_part = Zeroizing::new(vec![0u8; secret.len() - DIGEST_LEN]);
+    rng.fill(random_part.as_mut_slice());
+    // reserved in full, so that extending does not leave a copy behind
+    let mut digest_share = Zeroizing::new(Vec::with_capacity(secret.len()));
+    digest_share.extend_from_slice(&digest(&random_part, secret));
+    digest_share.extend_from_slice(&random_part);
+
+    let mut base: Vec<(u8, &[u8])> = shares
+        .iter()
+        .enumerate()
+        .map(|(i, share)| (i as u8, share.as_slice()))
+        .collect();
+    base.push((DIGEST_INDEX, &digest_share));
+    base.push((SECRET_INDEX, secret));
+
+    let interpolated: Vec<Zeroizing<Vec<u8>>> = (random_shares as u8..count)
+        .map(|i| interpolate(&base, i))
+        .collect();
+    shares.extend(interpolated);
+    Ok(shares)
+}
+
+/// Recover secret from at least `threshold` shares `(index, value)`
+pub fn recover_secret(threshold: u8, shares: &[(u8, &[u8])]) -> Result<Zeroizing<Vec<u8>>, Slip39Error> {
+    let Some((_, first)) = shares.first() else {
+        return Err(Slip39Error::NotEnoughShares)
+    };
+    if threshold == 1 {
+        return Ok(Zeroizing::new(first.to_vec()))
+    }
+    if shares.len() < threshold as usize {
+        return Err(Slip39Error::NotEnoughShares)
+    }
+    let shares = &shares[..threshold as usize];
+    let secret = interpolate(shares, SECRET_INDEX);
+    let digest_share = interpolate(shares, DIGEST_INDEX);
+    if digest_share[..DIGEST_LEN] != digest(&digest_share[DIGEST_LEN..], &secret) {
+        return Err(Slip39Error::DigestMismatch)
+    }
+    Ok(secret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recover_known_shares() {
+        // member shares 3 and 1 of 2-of-3 reference vector, recovering
+        // encrypted master secret
+        let third = hex::decode("08fb14b66e692e25dfe2edf53289ed62").unwrap();
+        let first = hex::decode("06ab48fef4bedc8ce58baeef0a73f76e").unwrap();
+        let encrypted = hex::decode("cdc017fddc829e791b1371780be0605a").unwrap();
+        assert_eq!(*recover_secret(2, &[(2, third.as_slice()), (0, first.as_slice())]).unwrap(), encrypted);
+        assert_eq!(*recover_secret(2, &[(0, first.as_slice()), (2, third.as_slice())]).unwrap(), encrypted);
+    }
+
+    #[test]
+    fn split_recover_round_trip() {
+        let mut rng = rand::thread_rng();
+        let mut secret = [0u8; 32];
+        rng.fill(&mut secret[..]);
+        for (threshold, count) in [(1, 1), (1, 4), (2, 2), (3, 5), (MAX_SHARE_COUNT, MAX_SHARE_COUNT)] {
+            let shares = split_secret(threshold, count, &secret, &mut rng).unwrap();
+            assert_eq!(shares.len(), count as usize);
+            let indexed: Vec<(u8, &[u8])> = shares
+                .iter()
+                .enumerate()
+                .map(|(i, share)| (i as u8, share.as_slice()))
+                .collect();
+            for start in 0..=(count - threshold) as usize {
+                let subset = &indexed[start..start + threshold as usize];
+                assert_eq!(*recover_secret(threshold, subset).unwrap(), secret);
+            }
+            if threshold > 1 {
+                assert_eq!(recover_secret(threshold, &indexed[1..threshold as usize]), Err(Slip39Error::NotEnoughShares));
+            }
+        }
+    }
+
+    #[test]
+    fn damaged_share_rejected() {
+        let mut rng = rand::thread_rng();
+        let shares = split_secret(2, 3, &[7u8; 16], &mut rng).unwrap();
+        let mut damaged = shares[1].clone();
+        damaged[0] ^= 1;
+        assert_eq!(recover_secret(2, &[(0, shares[0].as_slice()), (1, damaged.as_slice())]), Err(Slip39Error::DigestMismatch));
+    }
+
+    #[test]
+    fn invalid_threshold_rejected() {
+        let mut rng = rand::thread_rng();
+        assert_eq!(split_secret(0, 3, &[0; 16], &mut rng), Err(Slip39Error::InvalidThreshold));
+        assert_eq!(split_secret(4, 3, &[0; 16], &mut rng), Err(Slip39Error::InvalidThreshold));
+        assert_eq!(split_secret(2, MAX_SHARE_COUNT + 1, &[0; 16], &mut rng), Err(Slip39Error::InvalidThreshold));
+    }
+}
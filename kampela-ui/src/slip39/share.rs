@@ -0,0 +1,506 @@
+//! SLIP-39 shares: mnemonic encoding, master secret encryption and
+//! two-level sharing
+
+#[cfg(not(feature="std"))]
+use alloc::{string::String, vec, vec::Vec};
+#[cfg(feature="std")]
+use std::{string::String, vec, vec::Vec};
+
+use core::fmt::{Display, Formatter, Result as FmtResult};
+
+use pbkdf2::pbkdf2_hmac;
+use rand::{CryptoRng, Rng};
+use sha2::Sha256;
+use zeroize::Zeroizing;
+
+use crate::platform::MNEMONIC_LENGTHS;
+use crate::slip39::{
+    shamir::{recover_secret, split_secret},
+    wordlist::{word_index, RADIX_BITS, WORDLIST},
+};
+
+/// Words of identifier and sharing parameters
+const HEADER_WORDS: usize = 4;
+const CHECKSUM_WORDS: usize = 3;
+/// Shortest share, for 128-bit secret
+pub const MIN_MNEMONIC_WORDS: usize = HEADER_WORDS + 13 + CHECKSUM_WORDS;
+/// Longest share, for 256-bit secret
+pub const MAX_MNEMONIC_WORDS: usize = HEADER_WORDS + 26 + CHECKSUM_WORDS;
+
+const MIN_SECRET_LEN: usize = 16;
+const MAX_SECRET_LEN: usize = 32;
+
+const ID_BITS: u32 = 15;
+const ROUND_COUNT: u8 = 4;
+/// PBKDF2 iterations of Feistel round for exponent 0
+const BASE_ROUND_ITERATIONS: u32 = 2500;
+/// Iteration exponent of generated shares; shares are decrypted on device,
+/// so cheapest one is used
+const ITERATION_EXPONENT: u8 = 0;
+
+const CUSTOMIZATION: &[u8] = b"shamir";
+const CUSTOMIZATION_EXTENDABLE: &[u8] = b"shamir_extendable";
+
+const RS1024_GEN: [u32; 10] = [
+    0xE0E040, 0x1C1C080, 0x3838100, 0x7070200, 0xE0E0009,
+    0x1C0C2412, 0x38086C24, 0x3090FC48, 0x21B1F890, 0x3F3F120,
+];
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum Slip39Error {
+    /// Shares disagree on identifier or sharing parameters
+    InconsistentShares,
+    /// Recovered secret does not match its digest
+    DigestMismatch,
+    /// Checksum of share mnemonic is wrong
+    InvalidChecksum,
+    /// Share mnemonic or secret has unsupported length
+    InvalidLength,
+    /// Padding bits of share value are not zero
+    InvalidPadding,
+    /// Threshold is zero or above share count
+    InvalidThreshold,
+    /// Fewer shares than threshold
+    NotEnoughShares,
+    /// Word is not in SLIP-39 wordlist
+    UnknownWord,
+    /// Recovered secret is valid, but its length is not of BIP39 entropy
+    UnsupportedSecretLength,
+}
+
+impl Slip39Error {
+    pub fn error_text(&self) -> String {
+        match &self {
+            Slip39Error::InconsistentShares => String::from("Shares are not from the same set."),
+            Slip39Error::DigestMismatch => String::from("Shares do not recover valid secret."),
+            Slip39Error::InvalidChecksum => String::from("Share checksum is wrong."),
+            Slip39Error::InvalidLength => String::from("Share has invalid length."),
+            Slip39Error::InvalidPadding => String::from("Share has invalid padding."),
+            Slip39Error::InvalidThreshold => String::from("Invalid share threshold."),
+            Slip39Error::NotEnoughShares => String::from("Not enough shares."),
+            Slip39Error::UnknownWord => String::from("Unknown word in share."),
+            Slip39Error::UnsupportedSecretLength => String::from("Shares hold secret that is not a BIP39 seed."),
+        }
+    }
+}
+
+impl Display for Slip39Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "{}", self.error_text())
+    }
+}
+
+fn customization(extendable: bool) -> &'static [u8] {
+    if extendable { CUSTOMIZATION_EXTENDABLE } else { CUSTOMIZATION }
+}
+
+fn rs1024_polymod(customization: &[u8], values: &[u16]) -> u32 {
+    let mut chk: u32 = 1;
+    for v in customization.iter().map(|c| *c as u32).chain(values.iter().map(|v| *v as u32)) {
+        let b = chk >> 20;
+        chk = ((chk & 0xFFFFF) << 10) ^ v;
+        for (i, generator) in RS1024_GEN.iter().enumerate() {
+            if (b >> i) & 1 != 0 {
+                chk ^= generator;
+            }
+        }
+    }
+    chk
+}
+
+/// Words of share mnemonic, built from bit fields
+struct WordWriter {
+    words: Vec<u16>,
+    acc: u32,
+    acc_bits: usize,
+}
+
+impl WordWriter {
+    fn new() -> Self {
+        WordWriter { words: Vec::new(), acc: 0, acc_bits: 0 }
+    }
+    fn push(&mut self, value: u32, bits: usize) {
+        for i in (0..bits).rev() {
+            self.acc = (self.acc << 1) | ((value >> i) & 1);
+            self.acc_bits += 1;
+            if self.acc_bits == RADIX_BITS {
+                self.words.push(self.acc as u16);
+                self.acc = 0;
+                self.acc_bits = 0;
+            }
+        }
+    }
+}
+
+/// Bit fields read from share mnemonic words
+struct WordReader<'a> {
+    words: &'a [u16],
+    position: usize,
+}
+
+impl<'a> WordReader<'a> {
+    fn new(words: &'a [u16]) -> Self {
+        WordReader { words, position: 0 }
+    }
+    fn read(&mut self, bits: usize) -> u32 {
+        let mut value = 0;
+        for _ in 0..bits {
+            let word = self.words[self.position / RADIX_BITS];
+            let bit = (word >> (RADIX_BITS - 1 - self.position % RADIX_BITS)) & 1;
+            value = (value << 1) | bit as u32;
+            self.position += 1;
+        }
+        value
+    }
+}
+
+/// One SLIP-39 share
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Share {
+    pub identifier: u16,
+    pub extendable: bool,
+    pub iteration_exponent: u8,
+    pub group_index: u8,
+    pub group_threshold: u8,
+    pub group_count: u8,
+    pub member_index: u8,
+    pub member_threshold: u8,
+    pub value: Zeroizing<Vec<u8>>,
+}
+
+impl Share {
+    /// Share as mnemonic words
+    pub fn to_words(&self) -> Vec<&'static str> {
+        let value_words = (self.value.len() * 8).div_ceil(RADIX_BITS);
+        let mut writer = WordWriter::new();
+        writer.push(self.identifier as u32, ID_BITS as usize);
+        writer.push(self.extendable as u32, 1);
+        writer.push(self.iteration_exponent as u32, 4);
+        writer.push(self.group_index as u32, 4);
+        writer.push(self.group_threshold as u32 - 1, 4);
+        writer.push(self.group_count as u32 - 1, 4);
+        writer.push(self.member_index as u32, 4);
+        writer.push(self.member_threshold as u32 - 1, 4);
+        writer.push(0, value_words * RADIX_BITS - self.value.len() * 8);
+        for byte in self.value.iter() {
+            writer.push(*byte as u32, 8);
+        }
+        let mut indices = writer.words;
+        let checksum_input: Vec<u16> = indices.iter().copied().chain([0; CHECKSUM_WORDS]).collect();
+        let checksum = rs1024_polymod(customization(self.extendable), &checksum_input) ^ 1;
+        for i in (0..CHECKSUM_WORDS).rev() {
+            indices.push(((checksum >> (RADIX_BITS * i)) & 0x3FF) as u16);
+        }
+        indices.iter().map(|i| WORDLIST[*i as usize]).collect()
+    }
+
+    /// Parse share from mnemonic words, checking its checksum
+    pub fn from_words(words: &[&str]) -> Result<Self, Slip39Error> {
+        if words.len() < MIN_MNEMONIC_WORDS {
+            return Err(Slip39Error::InvalidLength)
+        }
+        let indices = words
+            .iter()
+            .map(|word| word_index(word).ok_or(Slip39Error::UnknownWord))
+            .collect::<Result<Vec<u16>, Slip39Error>>()?;
+        let extendable = (indices[1] >> 4) & 1 == 1;
+        if rs1024_polymod(customization(extendable), &indices) != 1 {
+            return Err(Slip39Error::InvalidChecksum)
+        }
+
+        let value_words = indices.len() - HEADER_WORDS - CHECKSUM_WORDS;
+        let padding = value_words * RADIX_BITS % 16;
+        if padding > 8 {
+            return Err(Slip39Error::InvalidPadding)
+        }
+        let mut reader = WordReader::new(&indices);
+        let identifier = reader.read(ID_BITS as usize) as u16;
+        reader.read(1);
+        let iteration_exponent = reader.read(4) as u8;
+        let group_index = reader.read(4) as u8;
+        let group_threshold = reader.read(4) as u8 + 1;
+        let group_count = reader.read(4) as u8 + 1;
+        let member_index = reader.read(4) as u8;
+        let member_threshold = reader.read(4) as u8 + 1;
+        if reader.read(padding) != 0 {
+            return Err(Slip39Error::InvalidPadding)
+        }
+        let value_len = (value_words * RADIX_BITS - padding) / 8;
+        let mut value = Zeroizing::new(Vec::with_capacity(value_len));
+        for _ in 0..value_len {
+            value.push(reader.read(8) as u8);
+        }
+        if value.len() < MIN_SECRET_LEN {
+            return Err(Slip39Error::InvalidLength)
+        }
+        if group_threshold > group_count {
+            return Err(Slip39Error::InvalidThreshold)
+        }
+        Ok(Share {
+            identifier,
+            extendable,
+            iteration_exponent,
+            group_index,
+            group_threshold,
+            group_count,
+            member_index,
+            member_threshold,
+            value,
+        })
+    }
+
+    /// Shares agree on identifier and parameters of the whole set
+    fn same_set(&self, other: &Share) -> bool {
+        self.identifier == other.identifier
+            && self.extendable == other.extendable
+            && self.iteration_exponent == other.iteration_exponent
+            && self.group_threshold == other.group_threshold
+            && self.group_count == other.group_count
+            && self.value.len() == other.value.len()
+    }
+}
+
+/// Feistel network of SLIP-39 master secret encryption; `rounds` go
+/// forward for encryption and backward for decryption
+fn feistel<I: Iterator<Item = u8>>(value: &[u8], passphrase: &[u8], share: &Share, rounds: I) -> Zeroizing<Vec<u8>> {
+    let half = value.len() / 2;
+    let mut left = Zeroizing::new(value[..half].to_vec());
+    let mut right = Zeroizing::new(value[half..].to_vec());
+    // salt takes right half in every round, so it is reserved in full
+    let mut salt = Zeroizing::new(Vec::with_capacity(CUSTOMIZATION.len() + 2 + value.len()));
+    if !share.extendable {
+        salt.extend_from_slice(CUSTOMIZATION);
+        salt.extend_from_slice(&share.identifier.to_be_bytes());
+    }
+    let salt_len = salt.len();
+    let iterations = BASE_ROUND_ITERATIONS << share.iteration_exponent;
+    for round in rounds {
+        let mut password = Zeroizing::new(Vec::with_capacity(1 + passphrase.len()));
+        password.push(round);
+        password.extend_from_slice(passphrase);
+        salt.truncate(salt_len);
+        salt.extend_from_slice(&right);
+        let mut f = Zeroizing::new(vec![0u8; right.len()]);
+        pbkdf2_hmac::<Sha256>(&password, &salt, iterations, &mut f);
+        for (l, f) in left.iter_mut().zip(f.iter()) {
+            *l ^= f;
+        }
+        core::mem::swap(&mut left, &mut right);
+    }
+    let mut result = Zeroizing::new(Vec::with_capacity(value.len()));
+    result.extend_from_slice(&right);
+    result.extend_from_slice(&left);
+    result
+}
+
+/// Split master secret into `count` single-group shares, any `threshold` of
+/// which recover it. SLIP-39 passphrase is not used.
+pub fn generate_shares<R: Rng + CryptoRng>(master_secret: &[u8], threshold: u8, count: u8, rng: &mut R) -> Result<Vec<Share>, Slip39Error> {
+    if master_secret.len() < MIN_SECRET_LEN || master_secret.len() > MAX_SECRET_LEN || master_secret.len() % 2 != 0 {
+        return Err(Slip39Error::InvalidLength)
+    }
+    if threshold == 1 && count > 1 {
+        return Err(Slip39Error::InvalidThreshold)
+    }
+    let template = Share {
+        identifier: rng.gen::<u16>() & ((1 << ID_BITS) - 1),
+        extendable: true,
+        iteration_exponent: ITERATION_EXPONENT,
+        group_index: 0,
+        group_threshold: 1,
+        group_count: 1,
+        member_index: 0,
+        member_threshold: threshold,
+        value: Zeroizing::new(Vec::new()),
+    };
+    let encrypted = feistel(master_secret, b"", &template, 0..ROUND_COUNT);
+    // one group of one: group share is the encrypted secret itself
+    let member_values = split_secret(threshold, count, &encrypted, rng)?;
+    Ok(member_values
+        .into_iter()
+        .enumerate()
+        .map(|(i, value)| Share {
+            member_index: i as u8,
+            value,
+            ..template.clone()
+        })
+        .collect())
+}
+
+/// Recover master secret from shares. SLIP-39 passphrase is not used.
+///
+/// Secret is stored as BIP39 entropy, so secrets of other lengths, valid in
+/// SLIP-39, are rejected.
+pub fn combine_shares(shares: &[Share]) -> Result<Zeroizing<Vec<u8>>, Slip39Error> {
+    let secret = combine_shares_with_passphrase(shares, b"")?;
+    if !MNEMONIC_LENGTHS.iter().any(|words| words / 3 * 4 == secret.len()) {
+        return Err(Slip39Error::UnsupportedSecretLength)
+    }
+    Ok(secret)
+}
+
+fn combine_shares_with_passphrase(shares: &[Share], passphrase: &[u8]) -> Result<Zeroizing<Vec<u8>>, Slip39Error> {
+    let Some(first) = shares.first() else {
+        return Err(Slip39Error::NotEnoughShares)
+    };
+    if shares.iter().any(|share| !first.same_set(share)) {
+        return Err(Slip39Error::InconsistentShares)
+    }
+
+    let mut group_shares: Vec<(u8, Zeroizing<Vec<u8>>)> = Vec::new();
+    for group_index in 0..first.group_count {
+        let members: Vec<&Share> = shares.iter().filter(|share| share.group_index == group_index).collect();
+        let Some(member) = members.first() else { continue };
+        if members.iter().any(|m| m.member_threshold != member.member_threshold) {
+            return Err(Slip39Error::InconsistentShares)
+        }
+        let mut member_values: Vec<(u8, &[u8])> = Vec::with_capacity(members.len());
+        for m in members.iter() {
+            if !member_values.iter().any(|(index, _)| *index == m.member_index) {
+                member_values.push((m.member_index, m.value.as_slice()));
+            }
+        }
+        if member_values.len() >= member.member_threshold as usize {
+            group_shares.push((group_index, recover_secret(member.member_threshold, &member_values)?));
+        }
+    }
+    let group_values: Vec<(u8, &[u8])> = group_shares
+        .iter()
+        .map(|(index, value)| (*index, value.as_slice()))
+        .collect();
+    if group_values.len() < first.group_threshold as usize {
+        return Err(Slip39Error::NotEnoughShares)
+    }
+    let encrypted = recover_secret(first.group_threshold, &group_values)?;
+    Ok(feistel(&encrypted, passphrase, first, (0..ROUND_COUNT).rev()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Valid vectors of reference implementation, passphrase "TREZOR"
+    const VECTORS: &[(&[&str], &str)] = &[
+        (
+            &["duckling enlarge academic academic agency result length solution fridge kidney coal piece deal husband erode duke ajar critical decision keyboard"],
+            "bb54aac4b89dc868ba37d9cc21b2cece",
+        ),
+        (
+            &[
+                "shadow pistol academic always adequate wildlife fancy gross oasis cylinder mustang wrist rescue view short owner flip making coding armed",
+                "shadow pistol academic acid actress prayer class unknown daughter sweater depict flip twice unkind craft early superior advocate guest smoking",
+            ],
+            "b43ceb7e57a0ea8766221624d01b0864",
+        ),
+        (
+            &["theory painting academic academic armed sweater year military elder discuss acne wildlife boring employer fused large satoshi bundle carbon diagnose anatomy hamster leaves tracks paces beyond phantom capital marvel lips brave detect luck"],
+            "989baf9dcaad5b10ca33dfd8cc75e42477025dce88ae83e75a230086a0e00e92",
+        ),
+        (
+            &["testify swimming academic academic column loyalty smear include exotic bedroom exotic wrist lobe cover grief golden smart junior estimate learn"],
+            "1679b4516e0ee5954351d288a838f45e",
+        ),
+    ];
+    const PASSPHRASE: &[u8] = b"TREZOR";
+
+    fn share(mnemonic: &str) -> Result<Share, Slip39Error> {
+        Share::from_words(&mnemonic.split(' ').collect::<Vec<&str>>())
+    }
+
+    #[test]
+    fn known_answers() {
+        for (mnemonics, secret) in VECTORS {
+            let shares: Vec<Share> = mnemonics.iter().map(|m| share(m).unwrap()).collect();
+            assert_eq!(*combine_shares_with_passphrase(&shares, PASSPHRASE).unwrap(), hex::decode(secret).unwrap());
+            // share order does not matter
+            let reversed: Vec<Share> = shares.iter().rev().cloned().collect();
+            assert_eq!(*combine_shares_with_passphrase(&reversed, PASSPHRASE).unwrap(), hex::decode(secret).unwrap());
+        }
+    }
+
+    #[test]
+    fn words_round_trip() {
+        for (mnemonics, _) in VECTORS {
+            for mnemonic in mnemonics.iter() {
+                assert_eq!(share(mnemonic).unwrap().to_words().join(" "), *mnemonic);
+            }
+        }
+    }
+
+    #[test]
+    fn encryption_matches_vectors() {
+        // single share of one group of one is encrypted master secret itself
+        for (mnemonics, secret) in VECTORS.iter().filter(|(mnemonics, _)| mnemonics.len() == 1) {
+            let share = share(mnemonics[0]).unwrap();
+            let encrypted = feistel(&hex::decode(secret).unwrap(), PASSPHRASE, &share, 0..ROUND_COUNT);
+            assert_eq!(encrypted, share.value);
+        }
+    }
+
+    #[test]
+    fn invalid_vectors() {
+        assert_eq!(
+            share("duckling enlarge academic academic agency result length solution fridge kidney coal piece deal husband erode duke ajar critical decision kidney"),
+            Err(Slip39Error::InvalidChecksum),
+        );
+        assert_eq!(
+            share("duckling enlarge academic academic email result length solution fridge kidney coal piece deal husband erode duke ajar music cargo fitness"),
+            Err(Slip39Error::InvalidPadding),
+        );
+        let one_of_two = share(VECTORS[1].0[0]).unwrap();
+        assert_eq!(combine_shares_with_passphrase(&[one_of_two], PASSPHRASE), Err(Slip39Error::NotEnoughShares));
+        let different_sets = [
+            share("adequate smoking academic acid debut wine petition glen cluster slow rhyme slow simple epidemic rumor junk tracks treat olympic tolerate").unwrap(),
+            share("adequate stay academic agency agency formal party ting frequent learn upstairs remember smear leaf damage anatomy ladle market hush corner").unwrap(),
+        ];
+        assert_eq!(combine_shares_with_passphrase(&different_sets, PASSPHRASE), Err(Slip39Error::InconsistentShares));
+    }
+
+    #[test]
+    fn generated_shares_round_trip() {
+        let mut rng = rand::thread_rng();
+        for secret_len in [MIN_SECRET_LEN, MAX_SECRET_LEN] {
+            let mut secret = vec![0u8; secret_len];
+            rng.fill(secret.as_mut_slice());
+            for (threshold, count) in [(1, 1), (2, 3), (3, 5)] {
+                let shares = generate_shares(&secret, threshold, count, &mut rng).unwrap();
+                assert_eq!(shares.len(), count as usize);
+                let parsed: Vec<Share> = shares
+                    .iter()
+                    .map(|s| Share::from_words(&s.to_words()).unwrap())
+                    .collect();
+                assert_eq!(parsed, shares);
+                // last shares, to not depend on shares next to secret
+                let used = &parsed[(count - threshold) as usize..];
+                assert_eq!(*combine_shares(used).unwrap(), secret);
+                if threshold > 1 {
+                    assert_eq!(combine_shares(&used[1..]), Err(Slip39Error::NotEnoughShares));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn only_bip39_secrets_recovered() {
+        let mut rng = rand::thread_rng();
+        for secret_len in (MIN_SECRET_LEN..=MAX_SECRET_LEN).step_by(2) {
+            let secret = vec![0x42u8; secret_len];
+            let shares = generate_shares(&secret, 2, 3, &mut rng).unwrap();
+            if MNEMONIC_LENGTHS.iter().any(|words| words / 3 * 4 == secret_len) {
+                assert_eq!(*combine_shares(&shares[..2]).unwrap(), secret);
+            } else {
+                assert_eq!(combine_shares(&shares[..2]), Err(Slip39Error::UnsupportedSecretLength));
+            }
+        }
+        // 18, 22, 26 and 30 bytes are rejected
+        assert_eq!((MIN_SECRET_LEN..=MAX_SECRET_LEN).step_by(2).count() - MNEMONIC_LENGTHS.len(), 4);
+    }
+
+    #[test]
+    fn generation_rejects_bad_parameters() {
+        let mut rng = rand::thread_rng();
+        assert_eq!(generate_shares(&[0; 15], 2, 3, &mut rng), Err(Slip39Error::InvalidLength));
+        assert_eq!(generate_shares(&[0; 16], 1, 3, &mut rng), Err(Slip39Error::InvalidThreshold));
+        assert_eq!(generate_shares(&[0; 16], 4, 3, &mut rng), Err(Slip39Error::InvalidThreshold));
+    }
+}
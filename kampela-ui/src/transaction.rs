@@ -30,6 +30,26 @@ pub enum TransactionPage {
     Extension,
 }
 
+/// Warning shown before transaction received without metadata proof, decoded
+/// with metadata that chain has not committed to
+pub fn unproven_metadata(next: UnitScreen) -> UnitScreen {
+    UnitScreen::ShowDialog(
+        "No metadata proof! Transaction content shown could differ from what is signed.",
+        ("reject", "continue"),
+        (
+            Box::new(|| EventResult {
+                request: Some(UpdateRequest::Slow),
+                state: Some(UnitScreen::QRAddress),
+            }),
+            Box::new(move || EventResult {
+                request: Some(UpdateRequest::Fast),
+                state: Some(next),
+            }),
+        ),
+        true,
+    )
+}
+
 pub struct Transaction {
     page: TransactionPage,
     navbar: NavBar,
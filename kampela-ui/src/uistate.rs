@@ -18,11 +18,13 @@ mod stdwrap {
 }
 
 use mnemonic_external::WordSet;
+use zeroize::Zeroizing;
 use stdwrap::*;
 
 use embedded_graphics::{
     draw_target::DrawTarget,
-    geometry::Point,
+    geometry::{Point, Size},
+    mono_font::{ascii::FONT_6X10, MonoTextStyle},
     pixelcolor::BinaryColor,
     prelude::Primitive,
     primitives::{
@@ -32,17 +34,25 @@ use embedded_graphics::{
     },
     Drawable,
 };
+use embedded_text::{style::TextBoxStyleBuilder, TextBox};
 
-use crate::{dialog::Dialog, display_def::*, pin::pin::Pincode, qr, transaction::{Transaction, TransactionPage}, widget::view::ViewScreen};
+use crate::{account::Account, account_add::AccountAdd, account_list::{AccountList, ACCOUNTS_PER_PAGE}, dialog::Dialog, dice::Dice, display_def::*, mnemonic_length::MnemonicLength, pairing::{self, Pairing}, passphrase::{PassphraseEntry, WalletChoice}, pin::{pin::Pincode, setup::PinSetup}, qr, settings::Settings, transaction::{self, Transaction, TransactionPage}, widget::view::ViewScreen};
 
 use crate::backup::Backup;
 
-use crate::platform::Platform;
+use crate::platform::{CompanionCheck, CompanionKey, Platform, Scheme};
 
 use crate::seed_entry::seed_entry::SeedEntry;
+use crate::seed_entry::share_entry::ShareEntry;
 
 use crate::message;
 
+/// Corner of address screen beside QR code
+const WALLET_LABEL_AREA: Rectangle = Rectangle{
+    top_left: Point{x: GAP as i32, y: GAP as i32},
+    size: Size{width: 48, height: 24},
+};
+
 pub struct EventResult{
     pub request: Option<UpdateRequest>,
     pub state: Option<UnitScreen>,
@@ -77,12 +87,24 @@ pub struct UIState<P, D> where
     pub platform: P,
     pub display: D,
     unlocked: bool,
+    /// Index of account shown in address QR
+    account: usize,
+    /// Wallet was chosen after unlock, passphrase is not asked again
+    wallet_chosen: bool,
 }
 
 pub enum UnitScreen {
     OnboardingRestoreOrGenerate,
     OnboardingRestore(Option<WordSet>),
-    OnboardingBackup(Option<Vec<u8>>),
+    /// Restore seed from SLIP-39 shares
+    OnboardingRestoreShares,
+    /// Choose number of words for new seed
+    OnboardingLength,
+    /// Optionally enter dice rolls for new seed with given number of words
+    OnboardingDice(usize),
+    /// Generate new seed with given number of words, mixing in dice rolls
+    OnboardingGenerate(usize, Zeroizing<Vec<u8>>),
+    OnboardingBackup(Zeroizing<Vec<u8>>),
     ShowMessage(String),
     ShowDialog(
         &'static str,
@@ -91,6 +113,20 @@ pub enum UnitScreen {
         bool
     ),
     ShowTransaction(TransactionPage),
+    PairCompanion(CompanionKey),
+    /// Confirm account sent by companion app, once unlocked
+    AddAccount(Account),
+    AccountList(usize),
+    Settings,
+    /// Check old PIN before setting new one
+    ChangePin,
+    PinSetup,
+    /// Ask to confirm erasing all data
+    FactoryReset,
+    /// Check PIN before erasing all data
+    FactoryResetPin,
+    /// Erase all data and return to onboarding
+    FactoryResetWipe,
     QRSignature,
     QRAddress,
     Locked,
@@ -103,12 +139,21 @@ impl Default for UnitScreen {
 /// keeps states of screens, initialization can take a lot of memory
 pub enum Screen<P: Platform> {
     PinEntry(Pincode<P>, UnitScreen),
+    PinSetup(PinSetup<P>),
+    PassphraseEntry(PassphraseEntry, UnitScreen),
     OnboardingRestoreOrGenerate(Dialog),
     OnboardingRestore(SeedEntry<P>),
+    OnboardingRestoreShares(ShareEntry),
+    OnboardingLength(MnemonicLength),
+    OnboardingDice(Dice),
     OnboardingBackup(Backup<P>),
     ShowMessage(String, Option<UnitScreen>),
     ShowDialog(Dialog),
     ShowTransaction(Transaction),
+    PairCompanion(Pairing),
+    AddAccount(AccountAdd),
+    AccountList(AccountList),
+    Settings(Settings),
     QRSignature,
     QRAddress,
     Locked,
@@ -119,9 +164,14 @@ impl<P: Platform> Screen<P> {
         match self {
             Screen::OnboardingRestoreOrGenerate(_) => Some(UnitScreen::OnboardingRestoreOrGenerate),
             Screen::OnboardingRestore(s) => Some(UnitScreen::OnboardingRestore(Some(s.get_buffer()))),
-            Screen::OnboardingBackup(b) => Some(UnitScreen::OnboardingBackup(Some(b.get_entropy().unwrap()))),
+            Screen::OnboardingRestoreShares(_) => Some(UnitScreen::OnboardingRestoreShares),
+            Screen::OnboardingLength(_) => Some(UnitScreen::OnboardingLength),
+            Screen::OnboardingDice(d) => Some(UnitScreen::OnboardingDice(d.words())),
+            Screen::OnboardingBackup(b) => Some(UnitScreen::OnboardingBackup(b.get_entropy().unwrap())),
             Screen::ShowMessage(s, _) => Some(UnitScreen::ShowMessage(s.to_owned())),
             Screen::ShowTransaction(t) => Some(UnitScreen::ShowTransaction(t.get_page())),
+            Screen::AccountList(a) => Some(UnitScreen::AccountList(a.get_page())),
+            Screen::Settings(_) => Some(UnitScreen::Settings),
             Screen::QRSignature => Some(UnitScreen::QRSignature),
             Screen::QRAddress => Some(UnitScreen::QRAddress),
             Screen::Locked => Some(UnitScreen::Locked),
@@ -136,10 +186,21 @@ impl<P: Platform> Default for Screen<P> {
 impl <P: Platform, D: DrawTarget<Color = BinaryColor>> UIState<P, D> {
     pub fn new(mut platform: P, display: D, h: &mut <P as Platform>::HAL) -> Self
         where <P as Platform>::AsWordList: Sized {
-        platform.read_entropy();
+        let seed_readable = platform.read_entropy();
         let initial_screen: Option<UnitScreen>;
         let unlocked: bool;
-        if platform.public().is_none() {
+        if !seed_readable {
+            initial_screen = Some(UnitScreen::ShowDialog(
+                "Stored seed is damaged and could not be read. Please restore it from backup",
+                ("", "ok"),
+                (
+                    Box::new(|| EventResult{request: Some(UpdateRequest::Fast), state: Some(UnitScreen::OnboardingRestoreOrGenerate)}),
+                    Box::new(|| EventResult{request: Some(UpdateRequest::Fast), state: Some(UnitScreen::OnboardingRestoreOrGenerate)}),
+                ),
+                true,
+            ));
+            unlocked = true;
+        } else if !platform.has_entropy() {
             initial_screen = Some(UnitScreen::OnboardingRestoreOrGenerate);
             unlocked = true;
         } else {
@@ -151,6 +212,8 @@ impl <P: Platform, D: DrawTarget<Color = BinaryColor>> UIState<P, D> {
             platform,
             display,
             unlocked,
+            account: 0,
+            wallet_chosen: false,
         };
         state.switch_screen(initial_screen, h);
         state
@@ -161,16 +224,27 @@ impl <P: Platform, D: DrawTarget<Color = BinaryColor>> UIState<P, D> {
         if let Some(s) = s {
             match s {
                 UnitScreen::QRAddress => {
-                    self.screen = Screen::QRAddress;
+                    // seed is bound to PIN, address could not be derived before unlock
+                    if self.unlocked {
+                        self.screen = Screen::QRAddress;
+                    } else {
+                        self.screen = Screen::PinEntry(Pincode::new(h, self.platform.pin_attempts_left()), UnitScreen::QRAddress);
+                    }
                 },
                 UnitScreen::Locked => {
                     self.screen = Screen::Locked;
                 },
-                UnitScreen::OnboardingBackup(e) => {
-                    let entropy = match e {
-                        Some(e) => e,
-                        None => P::generate_seed_entropy(h).to_vec(),
-                    };
+                UnitScreen::OnboardingLength => {
+                    self.screen = Screen::OnboardingLength(MnemonicLength::new());
+                },
+                UnitScreen::OnboardingDice(words) => {
+                    self.screen = Screen::OnboardingDice(Dice::new(words));
+                },
+                UnitScreen::OnboardingGenerate(words, dice) => {
+                    let entropy = P::generate_seed_entropy(h, words, &dice);
+                    self.screen = Screen::OnboardingBackup(Backup::new(entropy, self.screen.get_unit().expect("Backup returns only to unit screens")));
+                },
+                UnitScreen::OnboardingBackup(entropy) => {
                     self.screen = Screen::OnboardingBackup(Backup::new(entropy, self.screen.get_unit().expect("Backup returns only to unit screens")));
                 },
                 UnitScreen::ShowMessage(m) => {
@@ -185,7 +259,7 @@ impl <P: Platform, D: DrawTarget<Color = BinaryColor>> UIState<P, D> {
                         ("restore", "generate"),
                         (
                             Box::new(|| EventResult{request: Some(UpdateRequest::Fast), state: Some(UnitScreen::OnboardingRestore(None))}),
-                            Box::new(|| EventResult{request: Some(UpdateRequest::Fast), state: Some(UnitScreen::OnboardingBackup(None))}),
+                            Box::new(|| EventResult{request: Some(UpdateRequest::Fast), state: Some(UnitScreen::OnboardingLength)}),
                         ),
                         false,
                     ))
@@ -193,6 +267,9 @@ impl <P: Platform, D: DrawTarget<Color = BinaryColor>> UIState<P, D> {
                 UnitScreen::OnboardingRestore(p) => {
                     self.screen = Screen::OnboardingRestore(SeedEntry::new(p));
                 },
+                UnitScreen::OnboardingRestoreShares => {
+                    self.screen = Screen::OnboardingRestoreShares(ShareEntry::new());
+                },
                 UnitScreen::QRSignature => {
                     if self.unlocked {
                         if matches!(self.screen, Screen::ShowMessage(_, _)) {
@@ -201,12 +278,75 @@ impl <P: Platform, D: DrawTarget<Color = BinaryColor>> UIState<P, D> {
                             self.screen = Screen::ShowMessage("Signing...".to_owned(), Some(UnitScreen::QRSignature));
                         }
                     } else {
-                        self.screen = Screen::PinEntry(Pincode::new(h), UnitScreen::QRSignature);
+                        self.screen = Screen::PinEntry(Pincode::new(h, self.platform.pin_attempts_left()), UnitScreen::QRSignature);
                     }
                 },
                 UnitScreen::ShowTransaction(p) => {
                     self.screen = Screen::ShowTransaction(Transaction::new(p));
                 },
+                UnitScreen::PairCompanion(k) => {
+                    self.screen = Screen::PairCompanion(Pairing::new(k));
+                },
+                UnitScreen::AddAccount(a) => {
+                    if self.unlocked {
+                        self.screen = Screen::AddAccount(AccountAdd::new(a));
+                    } else {
+                        self.screen = Screen::PinEntry(Pincode::new(h, self.platform.pin_attempts_left()), UnitScreen::AddAccount(a));
+                    }
+                },
+                UnitScreen::AccountList(p) => {
+                    if self.unlocked {
+                        self.screen = Screen::AccountList(AccountList::new(p, self.platform.accounts().len()));
+                    } else {
+                        self.screen = Screen::PinEntry(Pincode::new(h, self.platform.pin_attempts_left()), UnitScreen::AccountList(p));
+                    }
+                },
+                UnitScreen::Settings => {
+                    if self.unlocked {
+                        self.screen = Screen::Settings(Settings::new(self.account / ACCOUNTS_PER_PAGE));
+                    } else {
+                        self.screen = Screen::PinEntry(Pincode::new(h, self.platform.pin_attempts_left()), UnitScreen::Settings);
+                    }
+                },
+                UnitScreen::ChangePin => {
+                    self.screen = Screen::PinEntry(Pincode::new(h, self.platform.pin_attempts_left()), UnitScreen::PinSetup);
+                },
+                UnitScreen::PinSetup => {
+                    self.screen = Screen::PinSetup(PinSetup::new(h));
+                },
+                UnitScreen::FactoryReset => {
+                    self.screen = Screen::ShowDialog(Dialog::new(
+                        "Erase seed, PIN and all data?\nThis can not be undone",
+                        ("no", "yes"),
+                        (
+                            Box::new(|| EventResult{request: Some(UpdateRequest::Fast), state: Some(UnitScreen::Settings)}),
+                            Box::new(|| EventResult{request: Some(UpdateRequest::Fast), state: Some(UnitScreen::FactoryResetPin)}),
+                        ),
+                        true,
+                    ));
+                },
+                UnitScreen::FactoryResetPin => {
+                    self.screen = Screen::PinEntry(Pincode::new(h, self.platform.pin_attempts_left()), UnitScreen::FactoryResetWipe);
+                },
+                UnitScreen::FactoryResetWipe => {
+                    let message = if self.platform.factory_reset() {
+                        "All data is erased."
+                    } else {
+                        "System error! Storage is not erased; if this persists, please destroy the device"
+                    };
+                    self.unlocked = true;
+                    self.account = 0;
+                    self.wallet_chosen = false;
+                    self.screen = Screen::ShowDialog(Dialog::new(
+                        message,
+                        ("", "ok"),
+                        (
+                            Box::new(|| EventResult{request: Some(UpdateRequest::Fast), state: Some(UnitScreen::OnboardingRestoreOrGenerate)}),
+                            Box::new(|| EventResult{request: Some(UpdateRequest::Fast), state: Some(UnitScreen::OnboardingRestoreOrGenerate)}),
+                        ),
+                        true,
+                    ));
+                },
             }
         }
     }
@@ -223,20 +363,60 @@ impl <P: Platform, D: DrawTarget<Color = BinaryColor>> UIState<P, D> {
         let mut new_screen = None;
         match self.screen {
             Screen::PinEntry(ref mut a, _) => {
-                let (res, _) = a.handle_tap_screen(point, self.platform.pin());
+                let (res, _) = a.handle_tap_screen(point, &mut self.platform);
+                out = res.request;
+                new_screen = res.state;
+            },
+            Screen::PinSetup(ref mut a) => {
+                let (res, pin) = a.handle_tap_screen(point, ());
                 out = res.request;
                 new_screen = res.state;
+                if let Some(p) = pin {
+                    self.platform.set_pin(&p);
+                    out = Some(UpdateRequest::UltraFast);
+                    self.screen = Screen::ShowMessage("PIN is set".to_owned(), Some(UnitScreen::QRAddress));
+                }
+            },
+            Screen::PassphraseEntry(ref mut a, _) => {
+                let (res, choice) = a.handle_tap_screen(point, ());
+                out = res.request;
+                if let Some(c) = choice {
+                    if let WalletChoice::Open(passphrase, store) = c {
+                        self.platform.set_passphrase(passphrase, store);
+                    }
+                    self.wallet_chosen = true;
+                    out = Some(UpdateRequest::Fast);
+                    new_screen = match core::mem::take(&mut self.screen) {
+                        Screen::PassphraseEntry(_, u) => Some(u),
+                        _ => None
+                    };
+                }
             },
             Screen::OnboardingRestore(ref mut a) => {
                 let (res, _) = a.handle_tap_screen(point, ());
                 out = res.request;
                 new_screen = res.state;
             },
-            Screen::OnboardingBackup(ref mut a) => {
+            Screen::OnboardingRestoreShares(ref mut a) => {
+                let (res, _) = a.handle_tap_screen(point, ());
+                out = res.request;
+                new_screen = res.state;
+            },
+            Screen::OnboardingDice(ref mut a) => {
                 let (res, _) = a.handle_tap_screen(point, ());
                 out = res.request;
                 new_screen = res.state;
             },
+            Screen::OnboardingLength(ref mut a) => {
+                let (res, _) = a.handle_tap_screen(point, ());
+                out = res.request;
+                new_screen = res.state;
+            },
+            Screen::OnboardingBackup(ref mut a) => {
+                let (res, _) = a.handle_tap_screen(point, h);
+                out = res.request;
+                new_screen = res.state;
+            },
             Screen::OnboardingRestoreOrGenerate(ref mut a) |
             Screen::ShowDialog(ref mut a) => {
                 let (res, _) = a.handle_tap_screen(point, ());
@@ -248,6 +428,44 @@ impl <P: Platform, D: DrawTarget<Color = BinaryColor>> UIState<P, D> {
                 out = res.request;
                 new_screen = res.state;
             },
+            Screen::PairCompanion(ref mut a) => {
+                let (res, key) = a.handle_tap_screen(point, ());
+                if let Some(k) = key {
+                    self.platform.trust_companion(k);
+                }
+                out = res.request;
+                new_screen = res.state;
+            },
+            Screen::AddAccount(ref mut a) => {
+                let (res, added) = a.handle_tap_screen(point, ());
+                out = res.request;
+                new_screen = res.state;
+                if let Some(account) = added {
+                    let message = if self.platform.add_account(account) {
+                        "Account added."
+                    } else {
+                        "Account is known already or account storage is full."
+                    };
+                    new_screen = Some(UnitScreen::ShowMessage(message.to_owned()));
+                }
+            },
+            Screen::AccountList(ref mut a) => {
+                let (res, picked) = a.handle_tap_screen(point, ());
+                if let Some(i) = picked {
+                    self.account = i;
+                }
+                out = res.request;
+                new_screen = res.state;
+            },
+            Screen::Settings(ref mut a) => {
+                let (res, _) = a.handle_tap_screen(point, ());
+                out = res.request;
+                new_screen = res.state;
+            },
+            Screen::QRAddress => {
+                out = Some(UpdateRequest::Fast);
+                new_screen = Some(UnitScreen::Settings);
+            },
             _ => (),
         }
         self.switch_screen(new_screen, h);
@@ -260,14 +478,21 @@ impl <P: Platform, D: DrawTarget<Color = BinaryColor>> UIState<P, D> {
         Some(UpdateRequest::UltraFast)
     }
     /// Handle NFC message reception.
-    /// TODO this correctly
-    /// currently it is a quick demo for expo
-    pub fn handle_transaction(&mut self, h: &mut <P as Platform>::HAL) -> Option<UpdateRequest>
+    ///
+    /// Transaction from paired companion app is shown right away; unknown
+    /// companion app is offered for pairing, unsigned transaction is shown
+    /// only after warning.
+    pub fn handle_transaction(&mut self, companion: CompanionCheck, metadata_proven: bool, h: &mut <P as Platform>::HAL) -> Option<UpdateRequest>
         where <P as Platform>::AsWordList: Sized {
         // match self.screen {
             // Screen::OnboardingRestoreOrGenerate => {
-        let screen = Some(UnitScreen::ShowTransaction(TransactionPage::Call));
-        self.switch_screen(screen, h);
+        let screen = match companion {
+            CompanionCheck::Trusted => UnitScreen::ShowTransaction(TransactionPage::Call),
+            CompanionCheck::Untrusted(k) => UnitScreen::PairCompanion(k),
+            CompanionCheck::Unsigned => pairing::unverified_source(),
+        };
+        let screen = if metadata_proven { screen } else { transaction::unproven_metadata(screen) };
+        self.switch_screen(Some(screen), h);
         Some(UpdateRequest::UltraFast)
             // },
             // _ => {},
@@ -275,6 +500,13 @@ impl <P: Platform, D: DrawTarget<Color = BinaryColor>> UIState<P, D> {
         // out
     }
 
+    /// Account sent by companion app is stored only after user confirms it
+    pub fn handle_account(&mut self, account: Account, h: &mut <P as Platform>::HAL) -> Option<UpdateRequest>
+        where <P as Platform>::AsWordList: Sized {
+        self.switch_screen(Some(UnitScreen::AddAccount(account)), h);
+        Some(UpdateRequest::UltraFast)
+    }
+
     pub fn handle_address(&mut self, addr: [u8; 76]) -> Option<UpdateRequest> {
         self.platform.set_address(addr);
         self.screen = Screen::QRAddress;
@@ -306,11 +538,25 @@ impl <P: Platform, D: DrawTarget<Color = BinaryColor>> UIState<P, D> {
                     self.unlocked = true;
                     out = Some(UpdateRequest::UltraFast);
                     new_screen = match core::mem::take(&mut self.screen) {
-                        Screen::PinEntry(_, u) => Some(u),
+                        Screen::PinEntry(_, u) => {
+                            if self.wallet_chosen {
+                                Some(u)
+                            } else {
+                                // hidden wallet could be opened right after unlock
+                                self.screen = Screen::PassphraseEntry(PassphraseEntry::new(), u);
+                                out = Some(UpdateRequest::Fast);
+                                None
+                            }
+                        },
                         _ => None
                     };
                 }
             },
+            Screen::PinSetup(ref mut a) => {
+                let (res, _) = a.draw_screen(display, h)?;
+                out = res.request;
+                new_screen = res.state;
+            },
             Screen::OnboardingRestore(ref mut entry) => {
                 let (res, _) = entry.draw_screen(display, ())?;
                 out = res.request;
@@ -331,10 +577,27 @@ impl <P: Platform, D: DrawTarget<Color = BinaryColor>> UIState<P, D> {
                 .into_styled(linestyle)
                 .draw(display)?;
             },
+            Screen::OnboardingRestoreShares(ref mut a) => {
+                let (res, _) = a.draw_screen(display, ())?;
+                out = res.request;
+                new_screen = res.state;
+            },
+            Screen::OnboardingDice(ref mut a) => {
+                let (res, _) = a.draw_screen(display, ())?;
+                out = res.request;
+                new_screen = res.state;
+            },
+            Screen::OnboardingLength(ref mut a) => {
+                let (res, _) = a.draw_screen(display, ())?;
+                out = res.request;
+                new_screen = res.state;
+            },
             Screen::OnboardingBackup(ref mut a) => {
                 let (res, entropy) = a.draw_screen(display, ())?;
                 if let Some(e) = entropy {
                     self.platform.store_entropy(&e);
+                    // new seed opens main wallet, there is no passphrase to ask for
+                    self.wallet_chosen = true;
                 }
                 out = res.request;
                 new_screen = res.state;
@@ -372,13 +635,58 @@ impl <P: Platform, D: DrawTarget<Color = BinaryColor>> UIState<P, D> {
                 out = res.request;
                 new_screen = res.state;
             },
+            Screen::PairCompanion(ref mut a) => {
+                let (res, _) = a.draw_screen(display, ())?;
+                out = res.request;
+                new_screen = res.state;
+            },
+            Screen::AddAccount(ref mut a) => {
+                let (res, _) = a.draw_screen(display, ())?;
+                out = res.request;
+                new_screen = res.state;
+            },
             Screen::QRSignature => {
-                qr::draw(&self.platform.signature(), display)?
+                match self.platform.signature() {
+                    Some(signature) => qr::draw(&signature, display)?,
+                    None => message::draw(display, "Signer is not an account of this wallet", true)?,
+                }
+            },
+            Screen::AccountList(ref mut a) => {
+                let platform = &self.platform;
+                let (res, _) = a.draw_screen(
+                    display,
+                    (platform.accounts(), Box::new(|account: &Account| platform.account_public(account))),
+                )?;
+                out = res.request;
+                new_screen = res.state;
+            },
+            Screen::Settings(ref mut a) => {
+                let (res, _) = a.draw_screen(display, ())?;
+                out = res.request;
+                new_screen = res.state;
             },
             Screen::QRAddress => {
-                let line1 = format!("substrate:0x{}", hex::encode(self.platform.public().expect("no entropy stored, no address could be shown").0));
+                let public = match self.platform.accounts().get(self.account) {
+                    Some(account) => self.platform.account_public(account),
+                    None => self.platform.public(Scheme::Sr25519),
+                };
+                match public {
+                    Some(public) => {
+                        let line1 = format!("substrate:0x{}", hex::encode(public.key));
+
+                        qr::draw(&line1.as_bytes(), display)?;
 
-                qr::draw(&line1.as_bytes(), display)?
+                        let wallet = if self.platform.passphrase().is_empty() { "main\nwallet" } else { "hidden\nwallet" };
+                        TextBox::with_textbox_style(
+                            wallet,
+                            WALLET_LABEL_AREA,
+                            MonoTextStyle::new(&FONT_6X10, BinaryColor::On),
+                            TextBoxStyleBuilder::new().build(),
+                        ).draw(display)?;
+                    },
+                    // seed that does not open, e.g. bound to another PIN, has no address
+                    None => message::draw(display, "Seed could not be opened, no address could be shown", true)?,
+                }
             },
         }
         self.switch_screen(new_screen, h);
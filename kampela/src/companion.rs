@@ -0,0 +1,70 @@
+//! Companion app signature over received payload
+//!
+//! Companion app signs SHA-256 digest of encoded payload with its P-256 key.
+//! Signature and public key are DER-encoded and follow the payload in transfer.
+
+use alloc::vec::Vec;
+use core::cmp;
+
+use external_memory_tools::AddressableBuffer;
+use kampela_system::{devices::psram::PsramAccess, nfc::{CompanionSignature, NfcError, NfcMemory, NfcPayloadError}};
+use kampela_ui::platform::{CompanionCheck, CompanionKey};
+use p256::{
+    ecdsa::{signature::hazmat::PrehashVerifier, Signature, VerifyingKey},
+    pkcs8::DecodePublicKey,
+};
+use sha2::{Digest, Sha256};
+
+/// Part of payload read from PSRAM at once while hashing
+const HASH_CHUNK_LEN: usize = 1024;
+
+pub fn payload_hash<M>(encoded_data: &PsramAccess, memory: &mut M) -> Result<[u8; 32], NfcPayloadError>
+where
+    M: NfcMemory,
+    PsramAccess: AddressableBuffer<M, ReadBuffer = Vec<u8>>,
+{
+    let mut hasher = Sha256::new();
+    let mut position = 0;
+    while position < encoded_data.total_len {
+        let len = cmp::min(HASH_CHUNK_LEN, encoded_data.total_len - position);
+        let chunk = encoded_data
+            .read_slice(memory, position, len)
+            .map_err(|_| NfcPayloadError::AccessOnPayload)?;
+        hasher.update(&chunk);
+        position += len;
+    }
+    Ok(hasher.finalize().into())
+}
+
+/// Check companion signature over payload hash and whether the companion app is paired.
+///
+/// Signature that is present, but does not verify, is an error: such payload
+/// is refused altogether.
+pub fn check_companion(
+    payload_hash: &[u8; 32],
+    companion_signature: &Option<CompanionSignature>,
+    trusted_companions: &[CompanionKey],
+) -> Result<CompanionCheck, NfcError> {
+    let companion_signature = match companion_signature {
+        Some(a) => a,
+        None => return Ok(CompanionCheck::Unsigned),
+    };
+    let signature = Signature::from_der(&companion_signature.signature)
+        .map_err(|_| NfcError::CompanionSignatureInvalid)?;
+    let verifying_key = VerifyingKey::from_public_key_der(&companion_signature.public_key)
+        .map_err(|_| NfcError::CompanionKeyInvalid)?;
+    verifying_key
+        .verify_prehash(payload_hash, &signature)
+        .map_err(|_| NfcError::CompanionSignatureInvalid)?;
+
+    let key: CompanionKey = verifying_key
+        .to_encoded_point(true)
+        .as_bytes()
+        .try_into()
+        .expect("compressed point has static length");
+    if trusted_companions.contains(&key) {
+        Ok(CompanionCheck::Trusted)
+    } else {
+        Ok(CompanionCheck::Untrusted(key))
+    }
+}
@@ -19,8 +19,10 @@ use kampela_ui::platform::Platform;
 
 mod ui;
 use ui::UI;
+mod companion;
 mod nfc;
-use nfc::{BufferStatus, NfcReceiver, NfcStateOutput, NfcResult, NfcError};
+mod pin;
+use nfc::{BufferStatus, NfcReceiver, NfcStateOutput, NfcResult, region_slice};
 
 #[global_allocator]
 static HEAP: Heap = Heap::empty();
@@ -31,6 +33,8 @@ use kampela_system::{
     debug_display::burning_tank,
     init::init_peripherals,
     parallel::Operation,
+    devices::psram::PsramInFree,
+    nfc::CaptureSource,
     BUF_THIRD, CH_TIM0, LINK_1, LINK_2, LINK_DESCRIPTORS, TIMER0_CC0_ICF, NfcXfer, NfcXferBlock,
 };
 
@@ -88,6 +92,38 @@ fn LDMA() {
     });
 }
 
+/// Capture written by LDMA from TIMER0, regions are tracked in `BUFFER_STATUS`.
+struct LdmaCapture<'a> {
+    nfc_buffer: &'a [u16; 3*BUF_THIRD],
+}
+
+impl <'a> CaptureSource for LdmaCapture<'a> {
+    fn read_region(&self) -> Option<&[u16]> {
+        let mut read_from = None;
+        free(|cs| {
+            let buffer_status = BUFFER_STATUS.borrow(cs).borrow();
+            read_from = buffer_status.read_from();
+        });
+        read_from.map(|region| region_slice(self.nfc_buffer, region))
+    }
+    fn read_done(&mut self) {
+        free(|cs| {
+            let mut buffer_status = BUFFER_STATUS.borrow(cs).borrow_mut();
+            let was_write_halted = buffer_status.is_write_halted();
+            buffer_status.pass_read_done().expect("to do");
+            if was_write_halted & ! buffer_status.is_write_halted() {
+                if let Some(ref mut peripherals) = PERIPHERALS.borrow(cs).borrow_mut().deref_mut() {
+                    peripherals.LDMA_S.linkload.write(|w_reg| w_reg.linkload().variant(1 << CH_TIM0));
+                }
+                else {panic!("can not borrow peripherals, buffer_status: {:?}, got some new frames", buffer_status)}
+            }
+        });
+    }
+    fn halt(&mut self) {
+        NVIC::mask(Interrupt::LDMA);
+    }
+}
+
 #[entry]
 fn main() -> ! {
     {
@@ -161,26 +197,19 @@ fn main() -> ! {
     let mut ui = UI::init();
     let mut adc = ADC::new(());
 
-    // hard derivation
-    //let junction = DeriveJunction::hard("kampela");
-    // let pair_derived = pair
-    //         //.hard_derive_mini_secret_key(Some(ChainCode(*junction.inner())), b"")
-    //         .0
-    //         .expand_to_keypair(ExpansionMode::Ed25519);
-
-
-    let mut nfc = NfcReceiver::new(&nfc_buffer, ui.state.platform.public().map(|a| a.0));
+    let mut nfc = NfcReceiver::new(
+        LdmaCapture{nfc_buffer: &nfc_buffer},
+        PsramInFree,
+        ui.state.platform.has_entropy(),
+        ui.state.platform.trusted_companions(),
+    );
     loop {
         adc.advance(());
         let nfc_state = nfc.advance(adc.read());
         if let Some(s) = nfc_state {
             match s {
                 Err(e) => {
-                    match e {
-                        NfcError::InvalidAddress => {
-                            ui.handle_message("Invalid sender address".to_owned())
-                        }
-                    }
+                    ui.handle_message(e.error_text());
                     while !ui.advance(adc.read()).is_some_and(|c| c == true) {
                         adc.advance(());
                     }
@@ -203,36 +232,13 @@ fn main() -> ! {
                                     ui.handle_address([0;76]);
                                     break
                                 },
-                                NfcResult::Transaction(transaction) => {
-                                    ui.handle_transaction(transaction);
+                                NfcResult::Transaction(transaction, companion) => {
+                                    ui.handle_transaction(transaction, companion);
+                                    break
+                                },
+                                NfcResult::AddAccount(account) => {
+                                    ui.handle_account(account);
                                     break
-        
-        
-                /* // calculate correct hash of the payload
-                {
-                            let mut hasher = sha2::Sha256::new();
-                            in_free(|peripherals| {
-                                for shift in 0..nfc_payload.encoded_data.total_len {
-                                    let address = nfc_payload.encoded_data.start_address.try_shift(shift).unwrap();
-                                    let single_element_vec = psram_read_at_address(peripherals, address, 1usize).unwrap();
-                                    if shift == 0 {first_byte = Some(single_element_vec[0])}
-                                    hasher.update(&single_element_vec);
-                                }
-                            });
-                            let hash = hasher.finalize();
-        
-                            // transform signature and verifying key from der-encoding into usable form
-                            let signature = Signature::from_der(&nfc_payload.companion_signature).unwrap();
-                            let verifying_key = VerifyingKey::from_public_key_der(&nfc_payload.companion_public_key).unwrap();
-        
-                            // and check
-                            assert!(verifying_key
-                                .verify_prehash(&hash, &signature)
-                                .is_ok());
-        
-                }
-                */
-        
                                 },
                             }
                         }
@@ -1,22 +1,20 @@
-//! NFC packet collector and decoder
+//! NFC reception on the device and received payload interpretation
 
-use nfca_parser::frame::Frame;
-use alloc::vec::Vec;
+use alloc::{string::String, vec::Vec};
 
-use kampela_system::{
-    PERIPHERALS, in_free, BUF_THIRD, CH_TIM0,
+use kampela_system::BUF_THIRD;
+
+use external_memory_tools::AddressableBuffer;
+use kampela_system::devices::psram::PsramAccess;
+use kampela_system::nfc::{
+    compact_at, process_nfc_payload, read_section, section_at, turn_nfc_collector_correctly,
+    CaptureSource, NfcCollector, NfcError, NfcMemory, NfcPayloadError,
 };
-use cortex_m::interrupt::free;
-use crate::BUFFER_STATUS;
-use efm32pg23_fix::{NVIC,Interrupt};
 
-use kampela_system::devices::psram::{AddressPsram, ExternalPsram, PsramAccess, psram_read_at_address};
-use lt_codes::{decoder_metal::ExternalData, mock_worst_case::DecoderMetal, packet::{Packet, PACKET_SIZE}};
-use substrate_parser::compacts::find_compact;
+use kampela_ui::{account::{parse_path, Account}, platform::{CompanionCheck, CompanionKey, MultiPublic, Scheme}};
 
-use core::ops::DerefMut;
+use crate::companion::{check_companion, payload_hash};
 
-pub const FREQ: u16 = 22;
 const NFC_MIN_VOLTAGE: i32 = 6000; //Affects initiation time, but lower values result in unreliable nfc reception
 
 #[derive(Clone, Debug)]
@@ -108,182 +106,29 @@ impl BufferStatus {
     }
 }
 
-pub fn turn_nfc_collector_correctly(collector: &mut NfcCollector, nfc_buffer: &[u16; 3*BUF_THIRD]) {
-    let mut read_from = None;
-    free(|cs| {
-        let buffer_status = BUFFER_STATUS.borrow(cs).borrow();
-        read_from = buffer_status.read_from();
-    });
-    let decoder_input = match read_from {
-        Some(BufRegion::Reg0) => &nfc_buffer[..BUF_THIRD],
-        Some(BufRegion::Reg1) => &nfc_buffer[BUF_THIRD..2*BUF_THIRD],
-        Some(BufRegion::Reg2) => &nfc_buffer[2*BUF_THIRD..],
-        None => return,
-    };
-    let frames = Frame::process_buffer_miller_skip_tails::<_, FREQ>(decoder_input, |frame| frame_selected(&frame));
-
-    for frame in frames.into_iter() {
-        if let Frame::Standard(standard_frame) = frame {
-            let serialized_packet = standard_frame[standard_frame.len() - PACKET_SIZE..].try_into().expect("static length, always fits");
-            in_free(|peripherals| {
-                let mut external_psram = ExternalPsram{peripherals};
-                let packet = Packet::deserialize(serialized_packet);
-                collector.add_packet(&mut external_psram, packet);
-            });
-        }
-        else {unreachable!()}
-    }
-
-    free(|cs| {
-        let mut buffer_status = BUFFER_STATUS.borrow(cs).borrow_mut();
-        let was_write_halted = buffer_status.is_write_halted();
-        buffer_status.pass_read_done().expect("to do");
-        if was_write_halted & ! buffer_status.is_write_halted() {
-            if let Some(ref mut peripherals) = PERIPHERALS.borrow(cs).borrow_mut().deref_mut() {
-                peripherals.LDMA_S.linkload.write(|w_reg| w_reg.linkload().variant(1 << CH_TIM0));
-            }
-            else {panic!("can not borrow peripherals, buffer_status: {:?}, got some new frames", buffer_status)}
-        }
-    });
-}
-
-fn frame_selected(frame: &Frame) -> bool {
-    if let Frame::Standard(standard_frame) = frame {
-        if standard_frame.len() >= PACKET_SIZE {true}
-        else {false}
-    }
-    else {false}
-}
-
-pub enum NfcCollector {
-    Empty,
-    InProgress(DecoderMetal<AddressPsram>),
-    Done(ExternalData<AddressPsram>)
-}
-
-impl NfcCollector {
-    pub fn new() -> Self {
-        Self::Empty
-    }
-    pub fn add_packet(&mut self, external_psram: &mut ExternalPsram, nfc_packet: Packet) {
-        match self {
-            NfcCollector::Empty => {
-                let decoder_metal = DecoderMetal::init(external_psram, nfc_packet).unwrap();
-                match decoder_metal.try_read(external_psram) {
-                    None => *self = NfcCollector::InProgress(decoder_metal),
-                    Some(a) => *self = NfcCollector::Done(a),
-                }
-            },
-            NfcCollector::InProgress(decoder_metal) => {
-                decoder_metal.add_packet(external_psram, nfc_packet).unwrap();
-                if let Some(a) = decoder_metal.try_read(external_psram) {
-                    *self = NfcCollector::Done(a);
-                }
-            },
-            NfcCollector::Done(_) => {},
-        }
+pub fn region_slice(nfc_buffer: &[u16; 3*BUF_THIRD], region: BufRegion) -> &[u16] {
+    match region {
+        BufRegion::Reg0 => &nfc_buffer[..BUF_THIRD],
+        BufRegion::Reg1 => &nfc_buffer[BUF_THIRD..2*BUF_THIRD],
+        BufRegion::Reg2 => &nfc_buffer[2*BUF_THIRD..],
     }
 }
 
-#[derive(Debug)]
-pub enum NfcPayloadError {
-    AccessOnPayload,
-//    AccessOnPublicKey,
-//    AccessOnSignature,
-//    ExcessData,
-//    NoCompactPayload,
-//    NoCompactPublicKey,
-//    NoCompactSignature,
-}
-
-#[derive(Debug)]
-pub struct TransferDataReceived {
-    pub encoded_data: PsramAccess,
-//    pub companion_signature: Vec<u8>,
-//    pub companion_public_key: Vec<u8>,
-}
-
-pub fn process_nfc_payload(completed_collector: &ExternalData<AddressPsram>) -> Result<TransferDataReceived, NfcPayloadError> {
-    let psram_data = PsramAccess {
-        start_address: completed_collector.start_address.clone(),
-        total_len: completed_collector.len,
-    };
-
-    let mut position = 0usize; // *relative* position in PsramAccess!
-
-    let mut try_encoded_data = None;
-    in_free(|peripherals| {
-        let mut external_psram = ExternalPsram{peripherals};
-        let found_compact = find_compact::<u32, PsramAccess, ExternalPsram>(&psram_data, &mut external_psram, position).unwrap(); //.map_err(|_| NfcPayloadError::NoCompactPayload)?;
-        let start_address = completed_collector.start_address.try_shift(found_compact.start_next_unit).unwrap();
-        try_encoded_data = Some(PsramAccess {
-            start_address,
-            total_len: found_compact.compact as usize,
-        });
-        position = found_compact.start_next_unit + found_compact.compact as usize;
-    });
-    let encoded_data = match try_encoded_data {
-        Some(a) => a,
-        None => return Err(NfcPayloadError::AccessOnPayload),
-    };
-    Ok(TransferDataReceived{
-        encoded_data,
-    })
-/*
-    let mut try_companion_signature = None;
-    in_free(|peripherals| {
-        let mut external_psram = ExternalPsram{peripherals};
-        let found_compact = find_compact::<u32, PsramAccess, ExternalPsram>(&psram_data, &mut external_psram, position).unwrap(); //.map_err(|_| NfcPayloadError::NoCompactSignature)?;
-        let start_address = completed_collector.start_address.try_shift(found_compact.start_next_unit).unwrap();
-        let signature_data = psram_read_at_address(external_psram.peripherals, start_address, found_compact.compact as usize).unwrap(); //.map_err(|_| NfcPayloadError::AccessOnSignature)?;
-        try_companion_signature = Some(signature_data);
-        position = found_compact.start_next_unit + found_compact.compact as usize;
-    });
-    let companion_signature = match try_companion_signature {
-        Some(a) => a,
-        None => return Err(NfcPayloadError::AccessOnSignature),
-    };
-
-    let mut try_companion_public_key = None;
-    in_free(|peripherals| {
-        let mut external_psram = ExternalPsram{peripherals};
-        let found_compact = find_compact::<u32, PsramAccess, ExternalPsram>(&psram_data, &mut external_psram, position).unwrap(); //.map_err(|_| NfcPayloadError::NoCompactSignature)?;
-        let start_address = completed_collector.start_address.try_shift(found_compact.start_next_unit).unwrap();
-        let public_key_data = psram_read_at_address(external_psram.peripherals, start_address, found_compact.compact as usize).unwrap(); //.map_err(|_| NfcPayloadError::AccessOnSignature)?;
-        try_companion_public_key = Some(public_key_data);
-        position = found_compact.start_next_unit + found_compact.compact as usize;
-    });
-    let companion_public_key = match try_companion_public_key {
-        Some(a) => a,
-        None => return Err(NfcPayloadError::AccessOnPublicKey),
-    };
-
-    if position != psram_data.total_len {
-        panic!("after decoding position not matching total length, position: {position}, total_len: {}", psram_data.total_len);
-        //Err(NfcPayloadError::ExcessData)
-    }
-    else {Ok(TransferDataReceived{
-        encoded_data,
-        companion_signature,
-        companion_public_key
-    })}
-*/
-}
-
 pub struct NfcTransactionPsramAccess {
     pub call_psram_access: PsramAccess,
     pub extension_psram_access: PsramAccess,
     pub metadata_psram_access: PsramAccess,
+    pub metadata_proof_psram_access: Option<PsramAccess>,
     pub genesis_hash_bytes_psram_access: PsramAccess,
-}
-
-pub enum NfcError {
-    InvalidAddress,
+    /// Public key named in payload; matching account is looked up in active
+    /// wallet at signing, as it could be a hidden one
+    pub signer: MultiPublic,
 }
 
 pub enum NfcResult {
-    Transaction(NfcTransactionPsramAccess),
+    Transaction(NfcTransactionPsramAccess, CompanionCheck),
     DisplayAddress,
+    AddAccount(Account),
     Empty,
 }
 
@@ -297,134 +142,152 @@ pub enum NfcStateOutput {
 }
 
 
-pub struct NfcReceiver <'a> {
-    buffer: &'a [u16; 3*BUF_THIRD],
+pub struct NfcReceiver<C, M> {
+    capture: C,
+    memory: M,
     collector: NfcCollector,
     state: NfcState,
-    public_memory: [u8; 32],
+    trusted_companions: Vec<CompanionKey>,
 }
 
-impl <'a> NfcReceiver<'a> {
-    pub fn new(nfc_buffer: &'a [u16; 3*BUF_THIRD], public_memory: Option<[u8; 32]>) -> Self {
-        match public_memory {
-            Some(a) => Self {
-                buffer: nfc_buffer,
-                collector: NfcCollector::new(),
-                state: NfcState::Operational(0),
-                public_memory: a,
-            },
-            None => 
-                Self {
-                    buffer: nfc_buffer,
-                    collector: NfcCollector::new(),
-                    state: NfcState::Done,
-                    public_memory: [0u8; 32],
-            },
+impl <C, M> NfcReceiver<C, M>
+where
+    C: CaptureSource,
+    M: NfcMemory,
+    PsramAccess: AddressableBuffer<M, ReadBuffer = Vec<u8>>,
+{
+    /// Receiver is not started if there is no seed to sign with.
+    pub fn new(capture: C, memory: M, seed_stored: bool, trusted_companions: Vec<CompanionKey>) -> Self {
+        let state = if !seed_stored {
+            NfcState::Done
+        } else {
+            NfcState::Operational(0)
+        };
+        Self {
+            capture,
+            memory,
+            collector: NfcCollector::new(),
+            state,
+            trusted_companions,
         }
     }
 
     fn process(&mut self) -> Option<Result<NfcResult, NfcError>> {
-        turn_nfc_collector_correctly(&mut self.collector, self.buffer);
+        if let Err(e) = turn_nfc_collector_correctly(&mut self.collector, &mut self.capture, &mut self.memory) {
+            self.capture.halt();
+            return Some(Err(e))
+        }
 
+        if let Some(received) = self.collector.received() {
+            self.capture.halt();
+            return Some(Self::parse_payload(&received, &mut self.memory, &self.trusted_companions))
+        }
         match self.collector {
-            NfcCollector::Done(ref a) => {
-                NVIC::mask(Interrupt::LDMA);
-                let payload = process_nfc_payload(a).unwrap();
-
-                let mut first_byte: Option<u8> = None;
-                in_free(|peripherals| {
-                    first_byte = Some(psram_read_at_address(peripherals, payload.encoded_data.start_address, 1usize).unwrap()[0]);
-                });
-
-                match first_byte {
-                    Some(2) => return Some(Ok(NfcResult::DisplayAddress)),
-                    Some(3) => {
-                        let address = payload.encoded_data.start_address.try_shift(1usize).unwrap();
-                        let genesis_hash_bytes_psram_access = PsramAccess{start_address: address, total_len: 32usize};
-
-                        let mut metadata_psram_access_option = None;
-                        let mut position = 1usize + 32usize;
-                        in_free(|peripherals| {
-                            let mut external_psram = ExternalPsram{peripherals};
-                            let compact_meta = find_compact::<u32, PsramAccess, ExternalPsram>(&payload.encoded_data, &mut external_psram, position).unwrap();
-                            let start_address = payload.encoded_data.start_address.try_shift(compact_meta.start_next_unit).unwrap();
-                            metadata_psram_access_option = Some(PsramAccess{start_address, total_len: compact_meta.compact as usize});
-                            position = compact_meta.start_next_unit + compact_meta.compact as usize;
-                        });
-                        let metadata_psram_access = metadata_psram_access_option.unwrap();
-
-                        let mut data_to_sign_psram_access = None;
-                        in_free(|peripherals| {
-                            let mut external_psram = ExternalPsram{peripherals};
-                            let compact_transaction_1 = find_compact::<u32, PsramAccess, ExternalPsram>(
-                                &payload.encoded_data,
-                                &mut external_psram,
-                                position
-                            ).unwrap(); // fix this madness maybe later
-                            position = compact_transaction_1.start_next_unit;
-                            
-                            let compact_transaction_2 = find_compact::<u32, PsramAccess, ExternalPsram>(
-                                &payload.encoded_data,
-                                &mut external_psram,
-                                position
-                            ).unwrap();
-                            position = compact_transaction_2.start_next_unit;
-
-                            let compact_call = find_compact::<u32, PsramAccess, ExternalPsram>(&
-                                payload.encoded_data,
-                                &mut external_psram,
-                                position
-                            ).unwrap();
-
-                            let call_address_to_sign = payload.encoded_data.start_address
-                                .try_shift(compact_call.start_next_unit)
-                                .unwrap();
-
-                            let extension_address_to_sign = payload.encoded_data.start_address
-                                .try_shift(compact_call.start_next_unit + compact_call.compact as usize)
-                                .unwrap();
-                            let extension_len_to_sign = compact_transaction_2.compact as usize - compact_call.start_next_unit - compact_call.compact as usize + position;
-
-                            let call_to_sign_psram_access = PsramAccess{start_address: call_address_to_sign, total_len: compact_call.compact as usize};
-                            let extension_to_sign_psram_access = PsramAccess{start_address: extension_address_to_sign, total_len: extension_len_to_sign};
-                            data_to_sign_psram_access = Some((call_to_sign_psram_access, extension_to_sign_psram_access));
-
-                            position = compact_transaction_2.start_next_unit + compact_transaction_2.compact as usize;
-                        });
-                        let (call_to_sign_psram_access, extension_to_sign_psram_access) = data_to_sign_psram_access.unwrap();
-
-                        let mut public_key: Option<Vec<u8>> = None;
-                        in_free(|peripherals| {
-                            let start_address = payload.encoded_data.start_address.try_shift(position).unwrap();
-                            let k = psram_read_at_address(peripherals, start_address, 32usize).unwrap();
-                            public_key = Some(k);
-                        });
-                        // TODO: check address differently
-                        match public_key {
-                            None => {
-                                return Some(Err(NfcError::InvalidAddress))
-                            },
-                            Some(k) => {
-                                if k != self.public_memory {
-                                    return Some(Err(NfcError::InvalidAddress))
-                                }
-                            }
-                        }
+            NfcCollector::Empty => Some(Ok(NfcResult::Empty)),
+            _ => None,
+        }
+    }
 
-                        return Some(Ok(NfcResult::Transaction(NfcTransactionPsramAccess{
-                            call_psram_access: call_to_sign_psram_access,
-                            extension_psram_access: extension_to_sign_psram_access,
-                            metadata_psram_access,
-                            genesis_hash_bytes_psram_access,
-                        })));
-                    },
-                    _ => {
-                        return Some(Ok(NfcResult::Empty))
-                    }
+    fn parse_payload(
+        received: &PsramAccess,
+        memory: &mut M,
+        trusted_companions: &[CompanionKey],
+    ) -> Result<NfcResult, NfcError> {
+        let payload = process_nfc_payload(received, memory)?;
+
+        let first_byte = section_at(&payload.encoded_data, 0, 1)?;
+        let first_byte = read_section(&first_byte, memory)?[0];
+
+        match first_byte {
+            2 => Ok(NfcResult::DisplayAddress),
+            3 | 4 => {
+                let payload_hash = payload_hash(&payload.encoded_data, memory)?;
+                let companion = check_companion(&payload_hash, &payload.companion_signature, trusted_companions)?;
+
+                let genesis_hash_bytes_psram_access = section_at(&payload.encoded_data, 1, 32)?;
+                let mut position = 1usize + 32usize;
+
+                let (metadata_len, metadata_start) = compact_at(&payload.encoded_data, memory, position)?;
+                let metadata_psram_access = section_at(&payload.encoded_data, metadata_start, metadata_len)?;
+                position = metadata_start + metadata_len;
+
+                // RFC-0078 metadata proof follows metadata in payloads of type 4;
+                // transaction without one is shown only after a warning
+                let metadata_proof_psram_access = if first_byte == 4 {
+                    let (metadata_proof_len, metadata_proof_start) = compact_at(&payload.encoded_data, memory, position)?;
+                    let metadata_proof_psram_access = section_at(&payload.encoded_data, metadata_proof_start, metadata_proof_len)?;
+                    position = metadata_proof_start + metadata_proof_len;
+                    Some(metadata_proof_psram_access)
+                } else {
+                    None
+                };
+
+                let (_, transaction_1_start) = compact_at(&payload.encoded_data, memory, position)?; // fix this madness maybe later
+                position = transaction_1_start;
+
+                let (transaction_len, transaction_start) = compact_at(&payload.encoded_data, memory, position)?;
+                position = transaction_start;
+                let transaction_end = transaction_start
+                    .checked_add(transaction_len)
+                    .ok_or(NfcPayloadError::Truncated{position: transaction_start, minimal_length: transaction_len})?;
+
+                let (call_len, call_start) = compact_at(&payload.encoded_data, memory, position)?;
+                let call_to_sign_psram_access = section_at(&payload.encoded_data, call_start, call_len)?;
+
+                let extension_start = call_start + call_len;
+                let extension_len = transaction_end
+                    .checked_sub(extension_start)
+                    .ok_or_else(|| NfcPayloadError::Truncated{position: transaction_end, minimal_length: extension_start.saturating_sub(transaction_end)})?;
+                let extension_to_sign_psram_access = section_at(&payload.encoded_data, extension_start, extension_len)?;
+
+                position = transaction_end;
+
+                // Signer follows transaction: scheme byte and public key of
+                // that scheme; bare 32-byte key is sr25519 one
+                let scheme = if payload.encoded_data.total_len.checked_sub(position) == Some(32) {
+                    Scheme::Sr25519
+                } else {
+                    let scheme_id = read_section(&section_at(&payload.encoded_data, position, 1)?, memory)?[0];
+                    position += 1;
+                    Scheme::from_id(scheme_id).ok_or(NfcPayloadError::UnknownScheme(scheme_id))?
+                };
+                let public_key = section_at(&payload.encoded_data, position, scheme.public_len())?;
+                if position + scheme.public_len() != payload.encoded_data.total_len {
+                    return Err(NfcPayloadError::ExcessSignerData.into())
                 }
+                let signer = MultiPublic {
+                    scheme,
+                    key: read_section(&public_key, memory)?,
+                };
+
+                Ok(NfcResult::Transaction(NfcTransactionPsramAccess{
+                    call_psram_access: call_to_sign_psram_access,
+                    extension_psram_access: extension_to_sign_psram_access,
+                    metadata_psram_access,
+                    metadata_proof_psram_access,
+                    genesis_hash_bytes_psram_access,
+                    signer,
+                }, companion))
             },
-            NfcCollector::Empty => Some(Ok(NfcResult::Empty)),
-            NfcCollector::InProgress(_) => None,
+            // New account: scheme byte, network prefix and derivation path;
+            // accepted only from paired companion app
+            5 => {
+                let payload_hash = payload_hash(&payload.encoded_data, memory)?;
+                if !matches!(check_companion(&payload_hash, &payload.companion_signature, trusted_companions)?, CompanionCheck::Trusted) {
+                    return Err(NfcError::CompanionNotTrusted)
+                }
+                let header = read_section(&section_at(&payload.encoded_data, 1, 3)?, memory)?;
+                let scheme = Scheme::from_id(header[0]).ok_or(NfcPayloadError::UnknownScheme(header[0]))?;
+                let base58prefix = u16::from_le_bytes([header[1], header[2]]);
+                let (path_len, path_start) = compact_at(&payload.encoded_data, memory, 4)?;
+                let path = read_section(&section_at(&payload.encoded_data, path_start, path_len)?, memory)?;
+                let path = String::from_utf8(path).map_err(|_| NfcPayloadError::InvalidPath)?;
+                if parse_path(&path).is_none() {
+                    return Err(NfcPayloadError::InvalidPath.into())
+                }
+                Ok(NfcResult::AddAccount(Account{scheme, base58prefix, path}))
+            },
+            a => Err(NfcPayloadError::UnknownPayloadType(a).into()),
         }
     }
 
@@ -0,0 +1,130 @@
+//! PIN kept as salted SHA-256 hash wrapped by security element, with failed
+//! attempts counter surviving power loss
+
+use cortex_m::asm::delay;
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+
+use kampela_system::devices::{
+    flash::{erase_pin_record, read_pin_failures, read_pin_record, store_pin_failures, store_pin_record, SpiNorFlash, PIN_SALT_LEN},
+    record_store::RecordStore,
+    se_aes_gcm::{decode_entropy, encode_entropy, Protected, BINDING_LEN, RECORD_PIN_BOUND},
+    se_rng::random_with_length,
+};
+use kampela_ui::platform::{PinCheck, MAX_PIN_ATTEMPTS};
+
+/// PIN accepted before user sets one
+pub const DEFAULT_PIN: &[u8] = &[0; 4];
+
+/// Seed binding is hashed apart from PIN record hash
+const SEED_BINDING_DOMAIN: &[u8] = b"kampela seed binding";
+
+/// Delay before checking PIN after first failure, about a second on default clock
+const PIN_DELAY_CYCLES: u32 = 19_000_000;
+
+/// Delay doubles with each failure up to this power of two
+const PIN_DELAY_MAX_SHIFT: u8 = 5;
+
+pub struct PinStore {
+    record: Option<([u8; PIN_SALT_LEN], Protected)>,
+    failures: u8,
+}
+
+impl PinStore {
+    pub fn read(store: &mut RecordStore<SpiNorFlash>) -> Self {
+        PinStore {
+            record: read_pin_record(store),
+            failures: read_pin_failures(store),
+        }
+    }
+
+    pub fn attempts_left(&self) -> u8 {
+        MAX_PIN_ATTEMPTS.saturating_sub(self.failures)
+    }
+
+    /// Check PIN. Attempt is counted as failed in flash before the check and
+    /// cleared only on success, so cutting power does not give free attempts.
+    ///
+    /// After `MAX_PIN_ATTEMPTS` failures `wipe` is called to erase the seed.
+    ///
+    /// Missing PIN record means default PIN only if the stored `seed` is not
+    /// bound to PIN. Otherwise the device stays locked until the seed opens
+    /// with entered PIN, and the record is restored then.
+    pub fn check<F: FnOnce(&mut RecordStore<SpiNorFlash>)>(&mut self, store: &mut RecordStore<SpiNorFlash>, pin: &[u8], seed: Option<&Protected>, wipe: F) -> PinCheck {
+        let failures = self.failures;
+        self.set_failures(store, failures.saturating_add(1));
+        if failures != 0 {
+            delay(PIN_DELAY_CYCLES << core::cmp::min(failures - 1, PIN_DELAY_MAX_SHIFT));
+        }
+
+        let correct = match self.record {
+            Some((ref salt, ref protected)) => decode_entropy(protected, None)
+                .is_some_and(|hash| bool::from(hash[..].ct_eq(&pin_hash(salt, pin)))),
+            None => match seed.filter(|p| p.version() == RECORD_PIN_BOUND) {
+                Some(seed) => {
+                    let opened = decode_entropy(seed, Some(&seed_binding(pin))).is_some();
+                    if opened {
+                        self.set(store, pin);
+                    }
+                    opened
+                },
+                None => bool::from(pin.ct_eq(DEFAULT_PIN)),
+            },
+        };
+        if correct {
+            self.set_failures(store, 0);
+            PinCheck::Ok
+        } else if self.attempts_left() == 0 {
+            wipe(store);
+            self.erase(store);
+            self.set_failures(store, 0);
+            PinCheck::Wiped
+        } else {
+            PinCheck::Wrong{attempts_left: self.attempts_left()}
+        }
+    }
+
+    /// Store new PIN with fresh salt
+    pub fn set(&mut self, store: &mut RecordStore<SpiNorFlash>, pin: &[u8]) {
+        let salt: [u8; PIN_SALT_LEN] = random_with_length(PIN_SALT_LEN)
+            .expect("security element rng failed")
+            .try_into()
+            .expect("static length");
+        let protected = encode_entropy(&pin_hash(&salt, pin), None);
+        if let Err(_) = store_pin_record(store, &salt, &protected) {
+            panic!("Failed to save PIN");
+        }
+        self.record = Some((salt, protected));
+        self.set_failures(store, 0);
+    }
+
+    /// Forget PIN along with the seed it protected
+    pub fn erase(&mut self, store: &mut RecordStore<SpiNorFlash>) {
+        erase_pin_record(store);
+        self.record = None;
+    }
+
+    fn set_failures(&mut self, store: &mut RecordStore<SpiNorFlash>, failures: u8) {
+        if let Err(_) = store_pin_failures(store, failures) {
+            panic!("Failed to save PIN attempts");
+        }
+        self.failures = failures;
+    }
+}
+
+/// Value authenticated together with seed record, so that the seed decrypts
+/// only with the right PIN. It does not depend on PIN record, as seed is
+/// rewrapped before new PIN is stored.
+pub fn seed_binding(pin: &[u8]) -> [u8; BINDING_LEN] {
+    let mut hasher = Sha256::new();
+    hasher.update(SEED_BINDING_DOMAIN);
+    hasher.update(pin);
+    hasher.finalize().into()
+}
+
+fn pin_hash(salt: &[u8; PIN_SALT_LEN], pin: &[u8]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(salt);
+    hasher.update(pin);
+    hasher.finalize().into()
+}
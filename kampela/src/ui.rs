@@ -2,8 +2,9 @@
 
 use nalgebra::{Affine2, OMatrix, Point2, RowVector3};
 use alloc::{collections::VecDeque, string::String, vec::Vec};
+use core::cell::RefCell;
 use lazy_static::lazy_static;
-use substrate_crypto_light::sr25519::{Pair, Public};
+use zeroize::Zeroizing;
 use embedded_graphics::{
     prelude::Point,
     geometry::Dimensions,
@@ -11,17 +12,21 @@ use embedded_graphics::{
 
 use kampela_system::{
     devices::{
-        psram::{psram_decode_call, psram_decode_extension, read_from_psram, PsramAccess},
-        se_aes_gcm::{decode_entropy, encode_entropy, Protected},
+        psram::{psram_check_metadata_proof, psram_decode_call, psram_decode_extension, read_from_psram},
+        se_aes_gcm::{decode_entropy, encode_entropy, Protected, BINDING_LEN, RECORD_PIN_BOUND},
         se_rng,
+        record_store::RecordStore,
+        storage_schema::migrate_storage,
         touch::{touch_detected, Read, FT6X36_REG_NUM_TOUCHES, LEN_NUM_TOUCHES}
     }, draw::FrameBuffer, flash_mnemonic::FlashWordList, parallel::Operation
 };
 use kampela_system::devices::flash::*;
 use crate::nfc::NfcTransactionPsramAccess;
+use crate::pin::{seed_binding, PinStore, DEFAULT_PIN};
 use kampela_ui::{
+    account::{decode_accounts, default_accounts, encode_accounts, Account},
     display_def::*,
-    platform::{PinCode, Platform},
+    platform::{signing_payload, CompanionCheck, CompanionKey, MultiPublic, PinCheck, Platform, Scheme},
     uistate::{UIState, UpdateRequest, UpdateRequestMutate}
 };
 
@@ -130,9 +135,24 @@ impl UI {
         self.update_request.propagate(self.state.handle_message(message, &mut ()));
     }
 
-    pub fn handle_transaction(&mut self, transaction: NfcTransactionPsramAccess) {
+    pub fn handle_transaction(&mut self, transaction: NfcTransactionPsramAccess, companion: CompanionCheck) {
+        if let Some(ref metadata_proof_psram_access) = transaction.metadata_proof_psram_access {
+            if let Err(e) = psram_check_metadata_proof(
+                metadata_proof_psram_access,
+                &transaction.metadata_psram_access,
+                &transaction.extension_psram_access,
+            ) {
+                self.handle_message(e.error_text());
+                return
+            }
+        }
+        let metadata_proven = transaction.metadata_proof_psram_access.is_some();
         self.state.platform.set_transaction(transaction);
-        self.update_request.propagate(self.state.handle_transaction(&mut ()));
+        self.update_request.propagate(self.state.handle_transaction(companion, metadata_proven, &mut ()));
+    }
+
+    pub fn handle_account(&mut self, account: Account) {
+        self.update_request.propagate(self.state.handle_account(account, &mut ()));
     }
 
     pub fn handle_address(&mut self, addr: [u8; 76]) {
@@ -160,24 +180,71 @@ enum UIStatus {
     TouchOperation(Read<LEN_NUM_TOUCHES, FT6X36_REG_NUM_TOUCHES>, UIStatusDisplay),
 }
 pub struct Hardware {
-    pin: PinCode,
+    /// Device records other than encoded entropy
+    store: RecordStore<SpiNorFlash>,
+    pin: PinStore,
     protected: Option<Protected>,
+    /// Seed binding of PIN entered last, known once device is unlocked
+    binding: Option<Zeroizing<[u8; BINDING_LEN]>>,
     address: Option<[u8; 76]>,
     transaction_psram_access: Option<NfcTransactionPsramAccess>,
+    companions: Vec<CompanionKey>,
+    accounts: Vec<Account>,
+    passphrase: Zeroizing<String>,
+    /// Public keys derived since seed or wallet changed, so that seed is not
+    /// decrypted on every address redraw
+    publics: RefCell<Vec<(Account, MultiPublic)>>,
 }
 
 impl Hardware {
     pub fn new() -> Self {
+        // layout of older firmware is upgraded before anything is read
+        if let Err(e) = migrate_storage(&mut SpiNorFlash) {
+            panic!("Storage upgrade failed: {:?}", e);
+        }
+        let mut store = match RecordStore::mount(SpiNorFlash) {
+            Ok(store) => store,
+            Err(e) => panic!("Record store mount failed: {:?}", e),
+        };
         let protected = None;
-        let pin_set = false; // TODO query storage
-        let pin = [0; 4];
         Self {
-            pin,
+            pin: PinStore::read(&mut store),
             protected,
+            binding: None,
             address: None,
             transaction_psram_access: None,
+            companions: read_companion_keys(&mut store),
+            accounts: decode_accounts(&read_accounts(&mut store)).unwrap_or_else(default_accounts),
+            passphrase: read_encoded_passphrase(&mut store)
+                .and_then(|p| decode_entropy(&p, None))
+                .and_then(|p| String::from_utf8(p.to_vec()).ok())
+                .map(Zeroizing::new)
+                .unwrap_or_default(),
+            publics: RefCell::new(Vec::new()),
+            store,
         }
     }
+
+    pub fn trusted_companions(&self) -> Vec<CompanionKey> {
+        self.companions.clone()
+    }
+
+    /// Public key of account, derived once
+    fn cached_public<F: FnOnce() -> Option<MultiPublic>>(&self, account: &Account, derive: F) -> Option<MultiPublic> {
+        if let Some((_, public)) = self.publics.borrow().iter().find(|(a, _)| a == account) {
+            return Some(public.clone())
+        }
+        let public = derive()?;
+        self.publics.borrow_mut().push((account.clone(), public.clone()));
+        Some(public)
+    }
+
+    /// Wrap seed bound to PIN and store it
+    fn store_bound_entropy(&mut self, e: &[u8], binding: &[u8; BINDING_LEN]) {
+        let protected = encode_entropy(e, Some(binding));
+        store_encoded_entopy(&mut SpiNorFlash, &protected);
+        self.protected = Some(protected);
+    }
 }
 
 impl Platform for Hardware {
@@ -194,44 +261,140 @@ impl Platform for Hardware {
         se_rng::SeRng{}
     }
 
-    fn pin(&self) -> &PinCode {
-        &self.pin
+    fn check_pin(&mut self, pin: &[u8]) -> PinCheck {
+        let check = self.pin.check(&mut self.store, pin, self.protected.as_ref(), |store| {
+            erase_encoded_entropy(&mut SpiNorFlash);
+            erase_passphrase(store);
+        });
+        match check {
+            PinCheck::Ok => {
+                let binding = Zeroizing::new(seed_binding(pin));
+                // PIN change cut by power leaves seed copies bound to old
+                // and new PIN; the one opening with PIN that passed is kept
+                match settle_entropy_copies(&mut SpiNorFlash, |p| decode_entropy(p, Some(&binding)).is_some()) {
+                    Ok(Some(protected)) => {
+                        self.protected = Some(protected);
+                        self.publics.get_mut().clear();
+                    },
+                    Ok(None) => {},
+                    Err(_) => panic!("Failed to save seedphrase"),
+                }
+                // seed stored by older firmware is bound to PIN on first unlock
+                let unbound = self.protected
+                    .as_ref()
+                    .filter(|p| p.version() != RECORD_PIN_BOUND)
+                    .and_then(|p| decode_entropy(p, None));
+                if let Some(e) = unbound {
+                    self.store_bound_entropy(&e, &binding);
+                }
+                self.binding = Some(binding);
+            },
+            PinCheck::Wiped => {
+                self.protected = None;
+                self.binding = None;
+                self.passphrase = Zeroizing::new(String::new());
+                self.publics.get_mut().clear();
+            },
+            PinCheck::Wrong{..} => {},
+        }
+        check
     }
 
-    fn pin_mut(&mut self) -> &mut PinCode {
-        &mut self.pin
+    fn pin_attempts_left(&self) -> u8 {
+        self.pin.attempts_left()
     }
 
-    fn store_entropy(&mut self, e: &[u8]) {
-        self.protected = if e.len() != 0 {
-            let protected = encode_entropy(e);
-            store_encoded_entopy(&protected);
-            Some(protected)
-        } else {
-            None
+    fn set_pin(&mut self, pin: &[u8]) {
+        // seed bound to new PIN goes to first copy only, second one keeps
+        // seed bound to old PIN until new PIN record is stored; if power is
+        // cut in between, copies are settled on next unlock
+        let binding = Zeroizing::new(seed_binding(pin));
+        let rewrapped = match self.entropy() {
+            Some(e) => {
+                let protected = encode_entropy(&e, Some(&binding));
+                store_first_entropy_copy(&mut SpiNorFlash, &protected);
+                self.protected = Some(protected);
+                true
+            },
+            None => false,
+        };
+        self.binding = Some(binding);
+        self.pin.set(&mut self.store, pin);
+        if rewrapped {
+            if let Err(_) = settle_entropy_copies(&mut SpiNorFlash, |_| true) {
+                panic!("Failed to save seedphrase");
+            }
         }
     }
 
-    fn read_entropy(&mut self) {
-        self.protected = read_encoded_entropy();
+    fn factory_reset(&mut self) -> bool {
+        let erased = erase_storage(&mut self.store).is_ok();
+        self.protected = None;
+        self.binding = None;
+        self.companions = Vec::new();
+        self.accounts = default_accounts();
+        self.passphrase = Zeroizing::new(String::new());
+        self.publics.get_mut().clear();
+        self.pin = PinStore::read(&mut self.store);
+        erased
     }
 
-    fn public(&self) -> Option<Public> {
-        if let Some(e) = self.entropy() {
-            Some(Pair::from_entropy_and_pwd(&e, "").unwrap().public())
+    fn store_entropy(&mut self, e: &[u8]) {
+        self.publics.get_mut().clear();
+        if e.len() != 0 {
+            let binding = match self.binding.take() {
+                Some(binding) => binding,
+                None => {
+                    // PIN was not entered since seed could not be read; new
+                    // seed starts with default PIN, to be set right after
+                    self.pin.erase(&mut self.store);
+                    Zeroizing::new(seed_binding(DEFAULT_PIN))
+                },
+            };
+            self.store_bound_entropy(e, &binding);
+            self.binding = Some(binding);
+            // accounts and hidden wallet of previous seed, if any, are not kept
+            self.accounts = default_accounts();
+            store_accounts(&mut self.store, &encode_accounts(&self.accounts)).expect("default accounts fit the store");
+            self.passphrase = Zeroizing::new(String::new());
+            erase_passphrase(&mut self.store);
         } else {
-            None
+            self.protected = None;
         }
     }
 
-    fn entropy(&self) -> Option<Vec<u8>> {
-        if let Some(p) = &self.protected {
-            Some(decode_entropy(&p))
-        } else {
-            None
+    fn read_entropy(&mut self) -> bool {
+        self.publics.get_mut().clear();
+        match read_encoded_entropy(&mut SpiNorFlash) {
+            Ok(protected) => {
+                self.protected = protected;
+                true
+            },
+            Err(_) => {
+                self.protected = None;
+                false
+            },
         }
     }
 
+    fn public(&self, scheme: Scheme) -> Option<MultiPublic> {
+        self.cached_public(&Account::root(scheme), || self.pair(scheme).map(|pair| pair.public()))
+    }
+
+    fn account_public(&self, account: &Account) -> Option<MultiPublic> {
+        self.cached_public(account, || self.account_pair(account).map(|pair| pair.public()))
+    }
+
+    fn has_entropy(&self) -> bool {
+        self.protected.is_some()
+    }
+
+    fn entropy(&self) -> Option<Zeroizing<Vec<u8>>> {
+        self.protected
+            .as_ref()
+            .and_then(|p| decode_entropy(p, self.binding.as_deref()))
+    }
+
     fn set_address(&mut self, addr: [u8; 76]) {
         self.address = Some(addr);
     }
@@ -250,6 +413,7 @@ impl Platform for Hardware {
         let (decoded_call, specs, spec_name) = psram_decode_call(
             &transaction_psram_access.call_psram_access,
             &transaction_psram_access.metadata_psram_access,
+            transaction_psram_access.metadata_proof_psram_access.as_ref(),
         );
 
         let carded = decoded_call.card(0, &specs, &spec_name);
@@ -271,6 +435,7 @@ impl Platform for Hardware {
         let (decoded_extension, specs, spec_name) = psram_decode_extension(
             &transaction_psram_access.extension_psram_access,
             &transaction_psram_access.metadata_psram_access,
+            transaction_psram_access.metadata_proof_psram_access.as_ref(),
             &transaction_psram_access.genesis_hash_bytes_psram_access
         );
 
@@ -290,32 +455,28 @@ impl Platform for Hardware {
         Some(extensions)
     }
 
-    fn signature(&mut self) -> [u8; 130] {
+    fn signature(&mut self) -> Option<Vec<u8>> {
         let transaction_psram_access = match self.transaction_psram_access {
             Some(ref a) => a,
             None => panic!("qr generation failed")
         };
-        
-        let data_to_sign_psram_access = PsramAccess {
-            start_address: transaction_psram_access.call_psram_access.start_address,
-            total_len:
-                transaction_psram_access.call_psram_access.total_len
-                + &transaction_psram_access.extension_psram_access.total_len
-        };
-        let data_to_sign = read_from_psram(&data_to_sign_psram_access);
 
-        let signature = self.pair()
-            .expect("entropy should be stored at this point")
-            .sign_external_rng(&data_to_sign, &mut Self::rng(&mut ()));
+        let signer = &transaction_psram_access.signer;
+        // signer is found among cached public keys, only its pair is derived
+        let account = self.accounts
+            .iter()
+            .filter(|account| account.scheme == signer.scheme)
+            .find(|account| self.account_public(account).as_ref() == Some(signer))?;
+        let pair = self.account_pair(account)?;
+
+        let data_to_sign = signing_payload(
+            &read_from_psram(&transaction_psram_access.call_psram_access),
+            &read_from_psram(&transaction_psram_access.extension_psram_access),
+        );
 
-        let mut signature_with_id: [u8; 65] = [1; 65];
-        signature_with_id[1..].copy_from_slice(&signature.0);
-        let signature_with_id_bytes = hex::encode(signature_with_id)
-            .into_bytes()
-            .try_into()
-            .expect("static length");
+        let signature_with_id = pair.sign(&data_to_sign, &mut Self::rng(&mut ()));
 
-        signature_with_id_bytes
+        Some(hex::encode(signature_with_id).into_bytes())
     }
 
     fn address(&mut self) -> &[u8; 76] {
@@ -326,6 +487,53 @@ impl Platform for Hardware {
         }
     }
 
+    fn trust_companion(&mut self, key: CompanionKey) {
+        if self.companions.contains(&key) { return }
+        if self.companions.len() == MAX_COMPANION_KEYS {
+            self.companions.remove(0);
+        }
+        self.companions.push(key);
+        if let Err(_) = store_companion_keys(&mut self.store, &self.companions) {
+            panic!("Failed to save companion keys");
+        }
+    }
+
+    fn accounts(&self) -> &[Account] {
+        &self.accounts
+    }
+
+    fn add_account(&mut self, account: Account) -> bool {
+        if self.accounts.contains(&account) {
+            return false
+        }
+        let mut accounts = self.accounts.clone();
+        accounts.push(account);
+        let encoded = encode_accounts(&accounts);
+        if encoded.len() > MAX_ACCOUNTS_LEN || accounts.len() > u8::MAX as usize {
+            return false
+        }
+        if store_accounts(&mut self.store, &encoded).is_err() {
+            return false
+        }
+        self.accounts = accounts;
+        true
+    }
+
+    fn passphrase(&self) -> &str {
+        &self.passphrase
+    }
+
+    fn set_passphrase(&mut self, passphrase: Zeroizing<String>, store: bool) {
+        if store && !passphrase.is_empty() {
+            if let Err(_) = store_encoded_passphrase(&mut self.store, &encode_entropy(passphrase.as_bytes(), None)) {
+                panic!("Failed to save passphrase");
+            }
+        } else {
+            erase_passphrase(&mut self.store);
+        }
+        self.passphrase = passphrase;
+        self.publics.get_mut().clear();
+    }
 }
 
 lazy_static! {